@@ -104,6 +104,20 @@ pub fn derive_input(_attr: TokenStream, input: TokenStream) -> TokenStream {
         };
     }
 
+    let mut clear_action = quote!();
+    for field in &old_fields {
+        let pressed = Ident::new(&format!("{field}_pressed"), Span::call_site());
+        let just_pressed = Ident::new(&format!("{field}_just_pressed"), Span::call_site());
+        let just_released = Ident::new(&format!("{field}_just_released"), Span::call_site());
+
+        clear_action = quote! {
+            #clear_action
+            self.#pressed = false;
+            self.#just_pressed = false;
+            self.#just_released = false;
+        };
+    }
+
     let mut new_fields = Vec::new();
     for field in &old_fields {
         let pressed = Ident::new(&format!("{field}_pressed"), Span::call_site());
@@ -135,6 +149,11 @@ pub fn derive_input(_attr: TokenStream, input: TokenStream) -> TokenStream {
                     #input_action
                 }
             }
+
+            /// Releases all keys, for when the keyboard is used for something else
+            pub fn clear(&mut self) {
+                #clear_action
+            }
         }
     };
 
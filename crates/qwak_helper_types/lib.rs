@@ -4,3 +4,13 @@ use serde::{Deserialize, Serialize};
 #[derive(Debug, Clone, FromBytes, ToBytes, Deserialize, Serialize)]
 #[encoding(Msgpack)]
 pub struct MapInteraction(pub String, pub u64);
+
+/// A chat command sent by a player: the command without the slash, its arguments, and the player id
+#[derive(Debug, Clone, FromBytes, ToBytes, Deserialize, Serialize)]
+#[encoding(Msgpack)]
+pub struct ChatCommand(pub String, pub String, pub u64);
+
+/// A player died: the victim, the attacker (None for suicides and accidents), the weapon id and the cause
+#[derive(Debug, Clone, FromBytes, ToBytes, Deserialize, Serialize)]
+#[encoding(Msgpack)]
+pub struct Frag(pub u64, pub Option<u64>, pub Option<String>, pub String);
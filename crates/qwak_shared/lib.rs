@@ -5,4 +5,8 @@ pub trait QwakPlugin {
     fn plugin_name() -> String;
     fn plugin_version() -> [i32; 3];
     fn map_interact(args: qwak_helper_types::MapInteraction) -> ();
+    /// Returns true if the plugin handled the command
+    fn chat_command(args: qwak_helper_types::ChatCommand) -> bool;
+    /// Called on the server whenever a player dies
+    fn player_fragged(args: qwak_helper_types::Frag) -> ();
 }
@@ -10,7 +10,31 @@ pub struct Projectile {
     pub texture_file: String,
     pub scale: f32,
     pub rotation: [f32; 3],
+    /// Units per second
     pub speed: f32,
+    /// Seconds before it's removed without exploding
+    #[serde(default = "default_projectile_lifetime")]
+    pub lifetime: f32,
+    /// Size of the sphere that's swept along its path to find what it flies into
+    #[serde(default = "default_projectile_radius")]
+    pub radius: f32,
+    /// Taken by whoever it flies into
+    #[serde(default)]
+    pub damage: f32,
+    /// Taken by everyone else around the explosion, less towards the edge of `splash_radius`
+    #[serde(default)]
+    pub splash_damage: f32,
+    #[serde(default)]
+    pub splash_radius: f32,
+    pub explosion_sound: Option<String>,
+}
+
+fn default_projectile_lifetime() -> f32 {
+    10.0
+}
+
+fn default_projectile_radius() -> f32 {
+    0.05
 }
 
 #[derive(Debug, Resource)]
@@ -23,9 +47,103 @@ impl Default for Projectiles {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Movement physics in the style of Quake's, in units and seconds.
+/// Players move on their own client, so the server sends its own to everyone joining.
+#[derive(Debug, Serialize, Deserialize, Clone, Resource)]
+pub struct Movement {
+    /// The speed players accelerate to on the ground
+    pub max_speed: f32,
+    pub accelerate: f32,
+    /// Acceleration in the air, only adding speed along the wish direction up to `air_speed_cap`.
+    /// Turning while strafing keeps finding new directions below the cap, so players gain speed.
+    pub air_accelerate: f32,
+    pub air_speed_cap: f32,
+    pub friction: f32,
+    /// Friction slows down players moving slower than this as if they were moving this fast,
+    /// so that they come to a stop instead of sliding
+    pub stop_speed: f32,
+    pub gravity: f32,
+    pub jump_speed: f32,
+    /// Seconds a jump pressed before landing is remembered for
+    pub jump_buffer: f32,
+    /// Keeps jumping on landing while jump is held
+    pub auto_hop: bool,
+    /// Takes the place of `max_speed` while crouching
+    pub crouch_speed: f32,
+    /// Takes the place of `friction` for players crouching on the ground faster than `crouch_speed`,
+    /// so that they slide
+    pub slide_friction: f32,
+    /// The speed players climb ladders with
+    pub climb_speed: f32,
+    /// The speed players jumping off a ladder are pushed away from it with
+    pub ladder_jump: f32,
+    /// Landing faster than this, in units per second, makes a sound and dips the camera
+    pub land_speed: f32,
+    /// Landing faster than this hurts, by `fall_damage` for every unit per second over it
+    pub fall_damage_speed: f32,
+    pub fall_damage: f32,
+}
+impl Default for Movement {
+    fn default() -> Self {
+        Self::from_file("assets/movement.json").unwrap()
+    }
+}
+impl Movement {
+    pub fn from_file(path: impl AsRef<std::path::Path>) -> Result<Self, String> {
+        let input = read_to_string(path).map_err(|e| e.to_string())?;
+        serde_json::from_str(&input).map_err(|e| e.to_string())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PickupType {
     Weapon,
+    Ammo,
+    Health,
+    Armour,
+    Powerup,
+}
+
+/// Timed effects given by `PickupType::Powerup` pickups, `gives` is the lowercase name
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Powerup {
+    /// Four times the damage
+    Quad,
+    /// Faster movement and firing
+    Haste,
+}
+impl Powerup {
+    pub fn damage_scale(&self) -> f32 {
+        match self {
+            Self::Quad => 4.0,
+            Self::Haste => 1.0,
+        }
+    }
+
+    pub fn speed_scale(&self) -> f32 {
+        match self {
+            Self::Quad => 1.0,
+            Self::Haste => 1.3,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Quad => "QUAD DAMAGE",
+            Self::Haste => "HASTE",
+        }
+    }
+}
+impl std::str::FromStr for Powerup {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "quad" => Ok(Self::Quad),
+            "haste" => Ok(Self::Haste),
+            _ => Err(format!("unknown powerup: {s}")),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -37,6 +155,48 @@ pub struct PickupData {
     pub pickup_material: FastStr,
     pub texture_file: FastStr,
     pub scale: f32,
+    /// How much of `gives` an ammo pickup gives, or how much health or armour
+    #[serde(default)]
+    pub amount: u32,
+    /// How high health pickups go, health above 100 slowly goes back down
+    #[serde(default = "default_pickup_limit")]
+    pub limit: u32,
+    /// The part of the damage armour takes instead of health
+    #[serde(default)]
+    pub absorb: f32,
+    /// Seconds a powerup lasts
+    #[serde(default)]
+    pub duration: f32,
+    /// Seconds until the pickup comes back after being taken, see [PickupData::respawn_time]
+    #[serde(default)]
+    pub respawn: Option<f32>,
+}
+impl PickupData {
+    /// `respawn`, or how long pickups of the type take by default
+    pub fn respawn_time(&self) -> f32 {
+        self.respawn.unwrap_or(match self.pickup_type {
+            PickupType::Weapon | PickupType::Ammo => 30.0,
+            PickupType::Health | PickupType::Armour => 20.0,
+            PickupType::Powerup => 60.0,
+        })
+    }
+}
+
+fn default_pickup_limit() -> u32 {
+    100
+}
+
+/// A kind of ammunition from `ammo.json`, which attacks use up
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AmmoData {
+    pub id: FastStr,
+    /// Shown under the amount on the HUD
+    pub fancy_name: FastStr,
+    /// The most a player can carry
+    pub max: u32,
+    /// How much players spawn with
+    #[serde(default)]
+    pub start: u32,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
@@ -59,6 +219,9 @@ pub struct WeaponData {
     pub texture_file: FastStr,
     #[serde(default)]
     pub model_file: FastStr,
+    /// Shown in the kill feed, the fancy name is shown instead if there's none
+    #[serde(default)]
+    pub icon: FastStr,
     pub scale: f32,
     #[serde(default)]
     pub animations: WeaponAnimations,
@@ -71,6 +234,17 @@ pub struct WeaponData {
     pub attack1: Attack,
     #[serde(default)]
     pub attack2: Attack,
+    /// Ammo for the first attack given along with the weapon when it's picked up
+    #[serde(default)]
+    pub pickup_ammo: u32,
+    /// Rounds of the attacks' ammo the weapon holds, both attacks fire from it and reloading
+    /// fills it up from the player's ammo. Without one they fire from the player's ammo.
+    /// Both attacks have to use the same ammo to share it, see [WeaponData::magazine_ammo].
+    #[serde(default)]
+    pub magazine: u32,
+    /// Reloads a round at a time instead of the whole magazine, shooting stops the reload
+    #[serde(default)]
+    pub reload_single: bool,
     #[serde(default = "default_pickupmessage1")]
     pub pickup_message1: FastStr,
     #[serde(default = "default_pickupmessage2")]
@@ -82,6 +256,22 @@ impl WeaponData {
     fn default_firetime() -> f32 {
         1.0
     }
+
+    /// The ammo the magazine is loaded with, from whichever attack uses any
+    pub fn magazine_ammo(&self) -> Option<&FastStr> {
+        self.attack1
+            .ammo()
+            .or(self.attack2.ammo())
+            .map(|(ammo, _)| ammo)
+    }
+
+    /// Whether both attacks can fire from one magazine, they can't with different ammo
+    pub fn magazine_fits(&self) -> bool {
+        match (self.attack1.ammo(), self.attack2.ammo()) {
+            (Some((ammo1, _)), Some((ammo2, _))) => self.magazine == 0 || ammo1 == ammo2,
+            _ => true,
+        }
+    }
 }
 
 fn default_fancyname() -> FastStr {
@@ -96,6 +286,10 @@ fn default_pickupmessage2() -> FastStr {
     FastStr::from("!")
 }
 
+fn default_ammo_cost() -> u32 {
+    1
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 #[serde(tag = "type")]
 pub enum Attack {
@@ -107,11 +301,37 @@ pub enum Attack {
         damage: f32,
         damage_mod: f32,
         range: f32,
+        /// The `AmmoData` used up by the attack, it's free if there is none
+        #[serde(default)]
+        ammo: Option<FastStr>,
+        #[serde(default = "default_ammo_cost")]
+        cost: u32,
+        /// How hard each hit pushes the victim away from the shooter, in units per second
+        #[serde(default)]
+        knockback: f32,
     },
     Projectile {
         projectile: String,
+        #[serde(default)]
+        ammo: Option<FastStr>,
+        #[serde(default = "default_ammo_cost")]
+        cost: u32,
+        /// How hard the explosion pushes players away, less towards the edge of its splash radius
+        #[serde(default)]
+        knockback: f32,
     },
 }
+impl Attack {
+    /// The ammo used up by this attack, and how much of it
+    pub fn ammo(&self) -> Option<(&FastStr, u32)> {
+        match self {
+            Self::RayCast { ammo, cost, .. } | Self::Projectile { ammo, cost, .. } => {
+                ammo.as_ref().map(|ammo| (ammo, *cost))
+            }
+            Self::None => None,
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct WeaponAnimations {
@@ -129,8 +349,10 @@ pub struct WeaponAnimations {
     #[serde(default = "WeaponData::default_firetime")]
     pub anim_time2: f32,
 
+    /// How long reloading takes before the rounds go in and the weapon can fire again
     #[serde(default = "WeaponData::default_firetime")]
     pub reload_time_skip: f32,
+    /// How long the reload animation plays
     #[serde(default = "WeaponData::default_firetime")]
     pub reload_time: f32,
 }
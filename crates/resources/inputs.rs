@@ -16,6 +16,8 @@ pub struct PlayerInput {
     walk_left: Key,
     walk_right: Key,
     jump: Key,
+    crouch: Key,
+    reload: Key,
     debug_fly_up: Key,
     debug_fly_down: Key,
     pause_game: Key,
@@ -31,6 +33,16 @@ pub struct PlayerInput {
     weapon_slot9: Key,
     weapon_slot10: Key,
     show_lobby: Key,
+    scoreboard_sort: Key,
+    chat: Key,
+    chat_team: Key,
+    spectate: Key,
+    demo_pause: Key,
+    demo_faster: Key,
+    demo_slower: Key,
+    demo_forward: Key,
+    demo_back: Key,
+    demo_stop: Key,
 }
 impl Default for PlayerInput {
     fn default() -> Self {
@@ -2,11 +2,11 @@ use bevy::{
     asset::{Handle, UntypedHandle},
     ecs::system::{Res, Resource},
     image::Image,
-    log::info,
+    log::{error, info},
     math::Vec3,
     prelude::States,
 };
-use data::{PickupData, WeaponData};
+use data::{AmmoData, PickupData, WeaponData};
 use faststr::FastStr;
 use macros::error_return;
 use std::{collections::HashMap, fs, path::PathBuf};
@@ -44,9 +44,30 @@ pub fn if_map_done_loading(val: Res<MapDoneLoading>) -> bool {
     val.0
 }
 
-/// Represents where a player will spawn in the current level
-#[derive(Resource)]
-pub struct PlayerSpawnpoint(pub Vec3);
+/// Which players may use a spawn point
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SpawnKind {
+    /// `info_player_start`, only used if a map has no other spawns
+    #[default]
+    Start,
+    /// `info_player_deathmatch`
+    Deathmatch,
+    /// `info_player_team1` and `info_player_team2`, used by the team in team based game modes
+    Team(u8),
+}
+
+/// A place where a player can spawn
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SpawnPoint {
+    pub position: Vec3,
+    /// Rotation around the y axis, in radians
+    pub yaw: f32,
+    pub kind: SpawnKind,
+}
+
+/// Represents where players can spawn in the current level
+#[derive(Debug, Resource, Default)]
+pub struct PlayerSpawnpoints(pub Vec<SpawnPoint>);
 
 /// A list of which textures are currently being loaded
 #[derive(Debug, Resource, Default)]
@@ -104,6 +125,10 @@ impl WeaponMap {
 
         let mut map = HashMap::new();
         for item in parsed {
+            if !item.magazine_fits() {
+                error!("weapon {} shares its magazine between different ammo", item.id);
+                continue;
+            }
             map.insert(item.id.clone(), item);
         }
 
@@ -111,3 +136,22 @@ impl WeaponMap {
         Self(map)
     }
 }
+
+/// A map with ammo data
+#[derive(Debug, Resource, Default)]
+pub struct AmmoMap(pub HashMap<FastStr, AmmoData>);
+impl AmmoMap {
+    pub fn new() -> Self {
+        info!("Loading ammo...");
+        let data = error_return!(fs::read_to_string("assets/ammo.json"));
+        let parsed = error_return!(serde_json::from_str::<Vec<AmmoData>>(&data));
+
+        let mut map = HashMap::new();
+        for item in parsed {
+            map.insert(item.id.clone(), item);
+        }
+
+        info!("Done loading ammo...");
+        Self(map)
+    }
+}
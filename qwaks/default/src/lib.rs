@@ -1,4 +1,4 @@
-use qwak_helper_types::MapInteraction;
+use qwak_helper_types::{ChatCommand, Frag, MapInteraction};
 use qwak_shared::QwakPlugin;
 qwak_shared::plugin_gen!(Plugin);
 
@@ -38,4 +38,19 @@ impl QwakPlugin for Plugin {
             }
         }
     }
+
+    fn chat_command(ChatCommand(command, _, id): ChatCommand) -> bool {
+        unsafe {
+            match &*command {
+                "quack" => {
+                    let name = get_player_name(id).unwrap();
+                    broadcast_message(format!("{name} quacks")).unwrap();
+                    true
+                }
+                _ => false,
+            }
+        }
+    }
+
+    fn player_fragged(_: Frag) {}
 }
@@ -0,0 +1,167 @@
+use super::{ChatLine, sanitize};
+use crate::{
+    kill_feed::Hurter,
+    net::{
+        ServerChannel, ServerMessage,
+        server::{expose_to_qwaks, transmit_message},
+    },
+    queries::NetWorld,
+};
+use bevy::log::info;
+use bevy_renet::renet::RenetServer;
+use faststr::FastStr;
+use macros::{error_return, option_return};
+use qwak_helper_types::ChatCommand;
+
+/// Longest name a player can pick with `/name`
+const MAX_NAME_LENGTH: usize = 24;
+
+/// Handles a chat message sent by a player, either running it as a command or passing it on.
+pub fn handle_chat(
+    server: &mut RenetServer,
+    client_id: u64,
+    text: &str,
+    team: bool,
+    nw: &mut NetWorld,
+) {
+    let allowed = option_return!(nw.lobby.get_mut(&client_id))
+        .chat_limiter
+        .allow();
+    if !allowed {
+        reply(server, nw, client_id, "you are sending messages too fast");
+        return;
+    }
+
+    let text = option_return!(sanitize(text));
+    if text.starts_with('/') {
+        run_command(server, client_id, &text[1..], nw);
+    } else {
+        send_chat(server, nw, client_id, text, team);
+    }
+}
+
+/// Sends a message from `from` to everyone, or only to their team
+pub fn send_chat(server: &mut RenetServer, nw: &mut NetWorld, from: u64, text: String, team: bool) {
+    let sender = option_return!(nw.lobby.get(&from));
+    let (name, sender_team) = (sender.name.clone(), sender.team);
+    let bytes = error_return!(
+        ServerMessage::Chat {
+            name: Some(name.clone()),
+            text: text.clone(),
+            team
+        }
+        .bytes()
+    );
+
+    // Without teams every player is on the same (lack of a) team
+    let recipients = nw
+        .lobby
+        .iter()
+        .filter(|(_, info)| !team || info.team == sender_team)
+        .map(|(id, _)| *id)
+        .collect::<Vec<_>>();
+    for id in recipients {
+        if id == nw.current_id.0 {
+            nw.chat
+                .push(ChatLine::new(Some(name.clone()), text.clone(), team));
+        } else {
+            server.send_message(id, ServerChannel::ServerMessages as u8, bytes.clone());
+        }
+    }
+}
+
+/// Sends a message from the server to a single player
+pub fn reply(server: &mut RenetServer, nw: &mut NetWorld, client_id: u64, text: impl Into<String>) {
+    let text = text.into();
+    if client_id == nw.current_id.0 {
+        nw.chat.push(ChatLine::new(None, text, false));
+        return;
+    }
+    server.send_message(
+        client_id,
+        ServerChannel::ServerMessages as u8,
+        error_return!(
+            ServerMessage::Chat {
+                name: None,
+                text,
+                team: false
+            }
+            .bytes()
+        ),
+    );
+}
+
+/// Runs a chat command, `line` is the message without the leading slash.
+/// Commands not known by the server are passed on to the Qwak plugins.
+fn run_command(server: &mut RenetServer, client_id: u64, line: &str, nw: &mut NetWorld) {
+    let (command, args) = line
+        .split_once(char::is_whitespace)
+        .map(|(c, a)| (c, a.trim()))
+        .unwrap_or((line, ""));
+    info!("client {client_id} ran command /{command} {args}");
+
+    match command {
+        "name" => rename(server, client_id, args, nw),
+        "kill" => {
+            let info = option_return!(nw.lobby.get(&client_id));
+            if info.spectating {
+                return;
+            }
+            let player = info.entity;
+            let (_, mut player, _) = error_return!(nw.players.get_mut(player));
+            player.last_hurter = Hurter::default();
+            player.health = 0.0;
+        }
+        _ => {
+            expose_to_qwaks(nw, server);
+            let handled = error_return!(nw.plugins.default.chat_command(ChatCommand(
+                command.to_string(),
+                args.to_string(),
+                client_id
+            )));
+            if !handled {
+                reply(
+                    server,
+                    nw,
+                    client_id,
+                    format!("unknown command: /{command}"),
+                );
+            }
+        }
+    }
+}
+
+fn rename(server: &mut RenetServer, client_id: u64, name: &str, nw: &mut NetWorld) {
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        reply(
+            server,
+            nw,
+            client_id,
+            format!("names must be between 1 and {MAX_NAME_LENGTH} characters"),
+        );
+        return;
+    }
+
+    let name = FastStr::from(name);
+    let info = option_return!(nw.lobby.get_mut(&client_id));
+    let old = std::mem::replace(&mut info.name, name.clone());
+    server.broadcast_message(
+        ServerChannel::ServerMessages as u8,
+        error_return!(
+            ServerMessage::PlayerRenamed {
+                id: client_id,
+                name: name.clone()
+            }
+            .bytes()
+        ),
+    );
+    transmit_message(
+        server,
+        nw,
+        format!(
+            "{} IS NOW KNOWN AS {}",
+            old.to_lowercase(),
+            name.to_lowercase()
+        ),
+    );
+}
@@ -0,0 +1,301 @@
+use crate::{
+    net::{ClientMessage, rcon::RconPassword},
+    player::{Player, PlayerController},
+};
+use bevy::{ecs::schedule::SystemConfigs, prelude::*};
+use bevy_simple_text_input::{
+    TextInputInactive, TextInputPlaceholder, TextInputSubmitEvent, TextInputValue,
+};
+use faststr::FastStr;
+use macros::{error_continue, error_return, option_continue, option_return};
+use resources::inputs::PlayerInput;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+pub mod commands;
+
+/// Longest chat message in characters, longer messages are cut off
+pub const MAX_CHAT_LENGTH: usize = 128;
+/// How many lines are kept in the chat history
+const CHAT_HISTORY_LENGTH: usize = 50;
+/// How many lines are shown on the HUD
+const CHAT_VISIBLE_LINES: usize = 8;
+/// How long a line stays on the HUD while the chat is closed
+const CHAT_FADE_TIME: Duration = Duration::from_secs(10);
+
+/// How many messages a player can send at once
+const CHAT_BURST: f32 = 5.0;
+/// How many messages a player can send per second after using up the burst
+const CHAT_RATE: f32 = 1.0;
+
+/// Trims a message, removes control characters and cuts it to `MAX_CHAT_LENGTH`.
+/// Returns None if nothing is left.
+pub fn sanitize(text: &str) -> Option<String> {
+    let text = text
+        .trim()
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_CHAT_LENGTH)
+        .collect::<String>();
+    (!text.is_empty()).then_some(text)
+}
+
+/// Keeps players from flooding the chat, kept per player on the server
+#[derive(Debug)]
+pub struct ChatLimiter {
+    tokens: f32,
+    last: Instant,
+}
+impl Default for ChatLimiter {
+    fn default() -> Self {
+        Self {
+            tokens: CHAT_BURST,
+            last: Instant::now(),
+        }
+    }
+}
+impl ChatLimiter {
+    /// Returns true if the player is allowed to send a message right now
+    pub fn allow(&mut self) -> bool {
+        self.allow_at(Instant::now())
+    }
+
+    fn allow_at(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f32();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * CHAT_RATE).min(CHAT_BURST);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ChatLine {
+    /// None for messages from the server itself
+    pub name: Option<FastStr>,
+    pub text: String,
+    pub team: bool,
+    received: Instant,
+}
+impl ChatLine {
+    pub fn new(name: Option<FastStr>, text: String, team: bool) -> Self {
+        Self {
+            name,
+            text,
+            team,
+            received: Instant::now(),
+        }
+    }
+}
+impl std::fmt::Display for ChatLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.team {
+            write!(f, "(team) ")?;
+        }
+        match &self.name {
+            Some(name) => write!(f, "{name}: {}", self.text),
+            None => write!(f, "* {}", self.text),
+        }
+    }
+}
+
+/// Chat messages received by this player
+#[derive(Debug, Resource, Default)]
+pub struct ChatHistory {
+    lines: VecDeque<ChatLine>,
+}
+impl ChatHistory {
+    pub fn push(&mut self, line: ChatLine) {
+        info!("chat: {line}");
+        if self.lines.len() >= CHAT_HISTORY_LENGTH {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+
+    pub fn lines(&self) -> impl DoubleEndedIterator<Item = &ChatLine> {
+        self.lines.iter()
+    }
+}
+
+/// Whether the own player is typing a message
+#[derive(Debug, Resource, Default)]
+pub struct ChatState {
+    pub open: bool,
+    pub team: bool,
+}
+
+pub fn chat_closed(chat: Res<ChatState>) -> bool {
+    !chat.open
+}
+
+pub fn systems() -> SystemConfigs {
+    (open_chat, submit_chat, cancel_chat, update_chat_hud).into_configs()
+}
+
+type ChatInputs<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static mut TextInputInactive,
+        &'static mut TextInputValue,
+        &'static mut TextInputPlaceholder,
+        &'static mut Visibility,
+    ),
+>;
+
+fn set_chat_input(player: &Player, chat: &ChatState, inputs: &mut ChatInputs) {
+    let input = option_return!(player.children.chat_input);
+    let (mut inactive, mut value, mut placeholder, mut vis) = error_return!(inputs.get_mut(input));
+    inactive.0 = !chat.open;
+    value.0.clear();
+    placeholder.value = if chat.team {
+        "say to team..."
+    } else {
+        "say..."
+    }
+    .to_string();
+    *vis = if chat.open {
+        Visibility::Inherited
+    } else {
+        Visibility::Hidden
+    };
+}
+
+pub fn open_chat(
+    q_players: Query<&Player, With<PlayerController>>,
+    mut inputs: ChatInputs,
+    mut input: ResMut<PlayerInput>,
+    mut chat: ResMut<ChatState>,
+) {
+    if chat.open {
+        return;
+    }
+    // Opened on release, otherwise the key itself would be typed into the chat
+    if !input.chat_just_released && !input.chat_team_just_released {
+        return;
+    }
+    chat.open = true;
+    chat.team = input.chat_team_just_released;
+    input.clear();
+
+    for player in &q_players {
+        set_chat_input(player, &chat, &mut inputs);
+    }
+}
+
+pub fn submit_chat(
+    q_players: Query<&Player, With<PlayerController>>,
+    mut inputs: ChatInputs,
+    mut submits: EventReader<TextInputSubmitEvent>,
+    mut chat: ResMut<ChatState>,
+    mut history: ResMut<ChatHistory>,
+    mut rcon_password: ResMut<RconPassword>,
+    mut client_events: EventWriter<ClientMessage>,
+) {
+    for player in &q_players {
+        let input = option_continue!(player.children.chat_input);
+        for submit in submits.read() {
+            if submit.entity != input || !chat.open {
+                continue;
+            }
+            // The rcon commands never show up in the chat
+            match sanitize(&submit.value) {
+                Some(text) if text.starts_with("/rcon_password") => {
+                    rcon_password.0 = text["/rcon_password".len()..].trim().to_string();
+                    history.push(ChatLine::new(None, "rcon password set".to_string(), false));
+                }
+                Some(text) if text.starts_with("/rcon ") => {
+                    client_events.send(ClientMessage::Rcon {
+                        password: rcon_password.0.clone(),
+                        command: text["/rcon ".len()..].trim().to_string(),
+                    });
+                }
+                Some(text) => {
+                    client_events.send(ClientMessage::Chat {
+                        text,
+                        team: chat.team,
+                    });
+                }
+                None => {}
+            }
+            chat.open = false;
+            set_chat_input(player, &chat, &mut inputs);
+        }
+    }
+}
+
+pub fn cancel_chat(
+    q_players: Query<&Player, With<PlayerController>>,
+    mut inputs: ChatInputs,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut chat: ResMut<ChatState>,
+) {
+    if !chat.open || !keys.just_pressed(KeyCode::Escape) {
+        return;
+    }
+    chat.open = false;
+    for player in &q_players {
+        set_chat_input(player, &chat, &mut inputs);
+    }
+}
+
+pub fn update_chat_hud(
+    q_players: Query<&Player, With<PlayerController>>,
+    mut text: Query<&mut Text>,
+    history: Res<ChatHistory>,
+    chat: Res<ChatState>,
+) {
+    for player in &q_players {
+        let chat_hud = option_continue!(player.children.chat_hud);
+        let mut chat_hud = error_continue!(text.get_mut(chat_hud));
+
+        let mut lines = history
+            .lines()
+            .rev()
+            .take(CHAT_VISIBLE_LINES)
+            .filter(|l| chat.open || l.received.elapsed() < CHAT_FADE_TIME)
+            .map(|l| l.to_string())
+            .collect::<Vec<_>>();
+        lines.reverse();
+        chat_hud.0 = lines.join("\n");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_trims_and_cuts() {
+        assert_eq!(
+            sanitize("  hello\u{7} there \n"),
+            Some("hello there".to_string())
+        );
+        assert_eq!(sanitize(" \t "), None);
+        let long = "a".repeat(MAX_CHAT_LENGTH * 2);
+        assert_eq!(sanitize(&long).unwrap().len(), MAX_CHAT_LENGTH);
+    }
+
+    #[test]
+    fn limiter_refills_over_time() {
+        let mut limiter = ChatLimiter::default();
+        let now = limiter.last;
+        for _ in 0..CHAT_BURST as usize {
+            assert!(limiter.allow_at(now));
+        }
+        assert!(!limiter.allow_at(now));
+        assert!(limiter.allow_at(now + Duration::from_secs_f32(1.0 / CHAT_RATE)));
+        assert!(!limiter.allow_at(now + Duration::from_secs_f32(1.0 / CHAT_RATE)));
+    }
+}
@@ -1,38 +1,11 @@
 use crate::particles::ParticleMap;
-use bevy::{
-    asset::AssetServer,
-    ecs::{
-        component::Component,
-        schedule::{IntoSystemConfigs, SystemConfigs},
-        system::{Commands, Query},
-    },
-    math::Vec3,
-    transform::components::Transform,
-};
-use resources::data::Projectile;
+use bevy::{asset::AssetServer, ecs::system::Commands, math::Vec3};
 
 pub mod message;
 pub mod pickup;
 pub mod projectiles;
 
-#[derive(Component)]
-pub struct ProjectileEntity {
-    pub dir: Vec3,
-    pub data: Projectile,
-}
-impl ProjectileEntity {
-    pub fn systems() -> SystemConfigs {
-        (Self::update, Self::collision).into_configs()
-    }
-
-    pub fn update(mut query: Query<(&ProjectileEntity, &mut Transform)>) {
-        for (ent, mut trans) in &mut query {
-            trans.translation += ent.dir * ent.data.speed;
-        }
-    }
-
-    pub fn collision(_query: Query<&ProjectileEntity>) {}
-}
+pub use projectiles::ProjectileEntity;
 
 pub fn hitscan_hit_gfx(
     asset_server: &AssetServer,
@@ -1,58 +1,98 @@
-use crate::{net::SimulationEvent, player::Player};
+use crate::{game_mode::items, net::SimulationEvent, player::Player};
 use bevy::{
     ecs::{
         component::Component,
-        event::{EventReader, EventWriter},
+        entity::Entity,
+        event::EventWriter,
         schedule::{IntoSystemConfigs, SystemConfigs},
-        system::{Commands, Query, Res},
+        system::{Query, Res, Resource},
     },
+    render::view::Visibility,
     time::Time,
     transform::components::Transform,
 };
-use bevy_rapier3d::{pipeline::CollisionEvent, rapier::geometry::CollisionEventFlags};
-use resources::data::PickupData;
+use bevy_rapier3d::plugin::RapierContext;
+use macros::error_return;
+use resources::{AmmoMap, data::PickupData};
+use std::collections::HashMap;
 
 #[derive(Debug, Component)]
 pub struct PickupEntity {
     pub id: u64,
     pub data: PickupData,
+    /// Taken pickups are hidden until they come back
+    pub available: bool,
+    /// Seconds until a taken pickup comes back, only counted on the server
+    pub respawn_timer: f32,
 }
 impl PickupEntity {
     pub fn systems() -> SystemConfigs {
         (PickupEntity::update, PickupEntity::handle_pickups).into_configs()
     }
 
-    pub fn new(id: u64, data: PickupData) -> Self {
-        Self { id, data }
+    pub fn new(id: u64, data: PickupData, available: bool) -> Self {
+        Self {
+            id,
+            data,
+            available,
+            respawn_timer: 0.0,
+        }
+    }
+
+    pub fn visibility(available: bool) -> Visibility {
+        match available {
+            true => Visibility::Inherited,
+            false => Visibility::Hidden,
+        }
     }
 
+    /// Hands available pickups to the first touching player who wants them.
+    /// Only the server's pickups can be touched.
     pub fn handle_pickups(
-        mut commands: Commands,
-        pickups: Query<&PickupEntity>,
-        mut players: Query<&mut Player>,
-        mut reader: EventReader<CollisionEvent>,
+        mut pickups: Query<(Entity, &mut PickupEntity, &mut Visibility)>,
+        players: Query<&Player>,
+        rapier_context: Query<&RapierContext>,
+        ammo_map: Res<AmmoMap>,
         mut server_event: EventWriter<SimulationEvent>,
     ) {
-        for event in reader.read() {
-            if let CollisionEvent::Started(ent_pickup, player, CollisionEventFlags::SENSOR) = event
-            {
-                if let (Ok(player), Ok(pickup)) =
-                    (players.get_mut(*player), pickups.get(*ent_pickup))
-                {
-                    server_event.send(SimulationEvent::PlayerPicksUpPickup {
-                        id: pickup.id,
-                        player: player.id,
-                        pickup: pickup.data.gives.clone(),
-                    });
-
-                    commands.entity(*ent_pickup).despawn();
+        let rapier_context = error_return!(rapier_context.get_single());
+        for (entity, mut pickup, mut visibility) in &mut pickups {
+            if !pickup.available {
+                continue;
+            }
+            // Checked every frame, so players standing on a pickup get it when it comes back
+            let touching = rapier_context
+                .intersection_pairs_with(entity)
+                .filter(|(_, _, intersecting)| *intersecting)
+                .map(|(a, b, _)| if a == entity { b } else { a });
+            for other in touching {
+                let Ok(player) = players.get(other) else {
+                    continue;
+                };
+                // Pickups stay where they are for players they can't do anything for
+                if player.spectating || !items::wants_pickup(player, &pickup.data, &ammo_map) {
+                    continue;
                 }
+                pickup.available = false;
+                pickup.respawn_timer = pickup.data.respawn_time();
+                *visibility = Self::visibility(false);
+                server_event.send(SimulationEvent::PlayerPicksUpPickup {
+                    id: pickup.id,
+                    player: player.id,
+                    data: pickup.data.clone(),
+                });
+                break;
             }
         }
     }
+
     pub fn update(mut query: Query<(&mut PickupEntity, &mut Transform)>, time: Res<Time>) {
         for (_pe, mut trans) in query.iter_mut() {
             trans.rotate_y(time.delta_secs());
         }
     }
 }
+
+/// The pickup entities on clients by the id the server gave them
+#[derive(Debug, Resource, Default)]
+pub struct PickupIndex(pub HashMap<u64, Entity>);
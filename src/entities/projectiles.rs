@@ -1 +1,281 @@
+use crate::{
+    entities::pickup::PickupEntity,
+    game_mode::damage,
+    kill_feed::{DeathCause, Hurter},
+    net::{ServerChannel, ServerMessage},
+    particles::ParticleMap,
+    player::Player,
+    queries::NetWorld,
+};
+use bevy::prelude::*;
+use bevy_rapier3d::{
+    geometry::Collider, pipeline::QueryFilter, plugin::RapierContext, prelude::ShapeCastOptions,
+};
+use bevy_renet::renet::RenetServer;
+use faststr::FastStr;
+use macros::{error_continue, error_return};
+use resources::data::Projectile;
+use std::collections::HashMap;
 
+#[derive(Component)]
+pub struct ProjectileEntity {
+    /// The server's entity for the projectile, clients find theirs by it in [ProjectileIndex]
+    pub id: u64,
+    /// The player who fired it
+    pub owner: u64,
+    pub weapon: Option<FastStr>,
+    pub dir: Vec3,
+    pub data: Projectile,
+    /// From the attack that fired it, only known on the server
+    pub knockback: f32,
+    /// Seconds left before it's removed without exploding
+    pub lifetime: f32,
+}
+impl ProjectileEntity {
+    pub fn systems() -> SystemConfigs {
+        (Self::update,).into_configs()
+    }
+
+    /// Spawns a projectile flying from `origin` towards `dir`.
+    /// `id` is only given on clients, the server's projectiles go by their entity.
+    #[allow(clippy::too_many_arguments)]
+    pub fn spawn(
+        commands: &mut Commands,
+        materials: &mut Assets<StandardMaterial>,
+        asset_server: &AssetServer,
+        data: &Projectile,
+        id: Option<u64>,
+        owner: u64,
+        weapon: Option<FastStr>,
+        knockback: f32,
+        origin: Vec3,
+        dir: Vec3,
+    ) -> Entity {
+        let dir = dir.normalize_or_zero();
+
+        // Fix mesh rotation
+        let mut trans = Transform::from_translation(origin).looking_at(origin + dir, Vec3::Y);
+        trans.scale = Vec3::splat(data.scale);
+        trans.rotate_x(data.rotation[0].to_radians());
+        trans.rotate_y(data.rotation[1].to_radians());
+        trans.rotate_z(data.rotation[2].to_radians());
+
+        let entity = commands
+            .spawn((
+                Mesh3d(asset_server.load(&data.model_file)),
+                MeshMaterial3d(materials.add(StandardMaterial {
+                    base_color_texture: Some(asset_server.load(&data.texture_file)),
+                    perceptual_roughness: 1.0,
+                    reflectance: 0.0,
+                    ..default()
+                })),
+                trans,
+            ))
+            .id();
+        commands.entity(entity).insert(ProjectileEntity {
+            id: id.unwrap_or(entity.to_bits()),
+            owner,
+            weapon,
+            dir,
+            data: data.clone(),
+            knockback,
+            lifetime: data.lifetime,
+        });
+        entity
+    }
+
+    /// Moves projectiles along on clients and removes the ones that flew for too long.
+    /// The server moves its own in [collide], after checking what they fly into.
+    pub fn update(
+        mut commands: Commands,
+        mut query: Query<(Entity, &mut ProjectileEntity, &mut Transform)>,
+        mut index: ResMut<ProjectileIndex>,
+        server: Option<Res<RenetServer>>,
+        time: Res<Time>,
+    ) {
+        let delta = time.delta_secs();
+        for (entity, mut projectile, mut trans) in &mut query {
+            projectile.lifetime -= delta;
+            if projectile.lifetime <= 0.0 {
+                index.0.remove(&projectile.id);
+                commands.entity(entity).despawn_recursive();
+                continue;
+            }
+            if server.is_none() {
+                trans.translation += projectile.dir * projectile.data.speed * delta;
+            }
+        }
+    }
+}
+
+/// The projectile entities on clients by the id the server gave them
+#[derive(Debug, Resource, Default)]
+pub struct ProjectileIndex(pub HashMap<u64, Entity>);
+
+/// Sweeps the server's projectiles along the path they're about to fly this frame,
+/// and blows them up on whatever is in the way or moves them along.
+/// Runs after the projectiles fired this frame are spawned, so their first stretch is swept too.
+#[allow(clippy::type_complexity)]
+pub fn collide(
+    mut server: ResMut<RenetServer>,
+    mut nw: NetWorld,
+    mut projectiles: Query<
+        (Entity, &ProjectileEntity, &mut Transform),
+        (Without<Player>, Without<Camera3d>, Without<PickupEntity>),
+    >,
+) {
+    let delta = nw.time.delta_secs();
+    let mut impacts = Vec::new();
+    {
+        let rapier_context: &RapierContext = error_return!(nw.rapier_context.get_single());
+        for (entity, projectile, mut trans) in &mut projectiles {
+            // Pickups are sensors, and nobody shoots themselves
+            let mut filter = QueryFilter::new().exclude_sensors();
+            if let Some(owner) = nw.lobby.get(&projectile.owner) {
+                filter = filter.exclude_collider(owner.entity);
+            }
+            let velocity = projectile.dir * projectile.data.speed;
+            let hit = rapier_context.cast_shape(
+                trans.translation,
+                Quat::IDENTITY,
+                velocity,
+                &Collider::ball(projectile.data.radius),
+                ShapeCastOptions {
+                    max_time_of_impact: delta,
+                    stop_at_penetration: true,
+                    ..default()
+                },
+                filter,
+            );
+            match hit {
+                Some((hit, cast)) => {
+                    let position = trans.translation + velocity * cast.time_of_impact;
+                    impacts.push((entity, hit, position));
+                }
+                None => trans.translation += velocity * delta,
+            }
+        }
+    }
+
+    for (entity, hit, position) in impacts {
+        let (_, projectile, _) = error_continue!(projectiles.get(entity));
+        explode(&mut server, &mut nw, projectile, hit, position);
+        nw.commands.entity(entity).despawn_recursive();
+        spawn_explosion(
+            &mut nw.commands,
+            &nw.asset_server,
+            &nw.particles,
+            &projectile.data,
+            position,
+        );
+        server.broadcast_message(
+            ServerChannel::ServerMessages as u8,
+            error_continue!(
+                ServerMessage::ExplodeProjectile {
+                    id: projectile.id,
+                    projectile: projectile.data.id.clone(),
+                    translation: position,
+                }
+                .bytes()
+            ),
+        );
+    }
+}
+
+/// Hurts the player a projectile flew into with its full damage, and everyone around the explosion,
+/// including whoever fired it, with splash damage and knockback falling off towards the edge of its radius.
+/// Walls and floors between the explosion and a player keep the splash away from them.
+fn explode(
+    server: &mut RenetServer,
+    nw: &mut NetWorld,
+    projectile: &ProjectileEntity,
+    hit: Entity,
+    position: Vec3,
+) {
+    let data = &projectile.data;
+    let hurter = || Hurter {
+        id: projectile.owner,
+        weapon: projectile.weapon.clone(),
+        cause: DeathCause::Projectile,
+    };
+
+    if nw.players.contains(hit) {
+        let knockback = projectile.dir * projectile.knockback;
+        damage::damage(server, nw, hit, data.damage, knockback, hurter());
+    }
+    if data.splash_radius <= 0.0 {
+        return;
+    }
+    let rapier_context: &RapierContext = error_return!(nw.rapier_context.get_single());
+
+    // Only the map shields players from the blast, not the players in between
+    let is_map = |entity| !nw.players.contains(entity);
+    let filter = QueryFilter::new().exclude_sensors().predicate(&is_map);
+    let splashed = nw
+        .players
+        .iter()
+        .filter(|(entity, _, _)| *entity != hit)
+        .filter_map(|(entity, _, trans)| {
+            let offset = trans.translation - position;
+            let distance = offset.length();
+            let falloff = 1.0 - distance / data.splash_radius;
+            let away = offset.normalize_or(Vec3::Y);
+            let shielded = rapier_context
+                .cast_ray(position, away, distance, true, filter)
+                .is_some();
+            (falloff > 0.0 && !shielded).then_some((entity, away, falloff))
+        })
+        .collect::<Vec<_>>();
+    for (entity, away, falloff) in splashed {
+        let knockback = away * projectile.knockback * falloff;
+        damage::damage(
+            server,
+            nw,
+            entity,
+            data.splash_damage * falloff,
+            knockback,
+            hurter(),
+        );
+    }
+}
+
+/// The fire and noise of a projectile blowing up, on the server as well as on the clients
+pub fn spawn_explosion(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    particles: &ParticleMap,
+    data: &Projectile,
+    position: Vec3,
+) {
+    particles.spawn_explosion(asset_server, commands, position);
+    if let Some(sound) = &data.explosion_sound {
+        commands.spawn((
+            Transform::from_translation(position),
+            AudioPlayer::<AudioSource>(asset_server.load(sound.clone())),
+            PlaybackSettings::DESPAWN.with_spatial(true),
+        ));
+    }
+}
+
+/// Tells everyone about the projectiles fired on the server
+pub fn send_projectiles(
+    mut server: ResMut<RenetServer>,
+    projectiles: Query<(&ProjectileEntity, &Transform), Added<ProjectileEntity>>,
+) {
+    for (projectile, trans) in &projectiles {
+        server.broadcast_message(
+            ServerChannel::ServerMessages as u8,
+            error_continue!(
+                ServerMessage::SpawnProjectile {
+                    id: projectile.id,
+                    projectile: projectile.data.id.clone(),
+                    owner: projectile.owner,
+                    weapon: projectile.weapon.clone(),
+                    translation: trans.translation,
+                    dir: projectile.dir,
+                }
+                .bytes()
+            ),
+        );
+    }
+}
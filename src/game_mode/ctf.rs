@@ -0,0 +1,176 @@
+use super::{FlagState, GameMode, MatchState, TEAM_COLOURS, TEAM_COUNT, TEAM_NAMES};
+use crate::{
+    net::{Lobby, server::transmit_message},
+    player::Player,
+    queries::NetWorld,
+};
+use bevy::prelude::*;
+use bevy_renet::renet::RenetServer;
+use macros::option_continue;
+
+/// How close a player has to get to a flag to take, return or capture it
+const FLAG_RADIUS: f32 = 1.5;
+/// Seconds a dropped flag lies around before it returns to its base
+const FLAG_RETURN_TIME: f32 = 30.0;
+/// Points a player gets for capturing a flag
+const CAPTURE_SCORE: i64 = 5;
+/// How far above the carrier's origin a carried flag is shown
+const CARRY_OFFSET: Vec3 = Vec3::new(0.0, 1.0, 0.0);
+
+/// The base of a team's flag, from `item_flag_team1` and `item_flag_team2`
+#[derive(Debug, Component)]
+pub struct Flag {
+    pub team: u8,
+    pub base: Vec3,
+}
+
+/// Drops the flag carried by `id` where they died, returns the team of the flag
+pub fn drop_flag(state: &mut MatchState, id: u64, position: Vec3) -> Option<u8> {
+    let team = state
+        .flags
+        .iter()
+        .position(|f| *f == FlagState::Carried(id))?;
+    state.flags[team] = FlagState::Dropped {
+        position,
+        return_in: FLAG_RETURN_TIME,
+    };
+    Some(team as u8)
+}
+
+/// Takes, returns and captures flags for players touching them
+pub fn update_flags(server: &mut RenetServer, nw: &mut NetWorld, flags: &Query<&Flag>, delta: f32) {
+    let mut bases = [None; TEAM_COUNT];
+    for flag in flags {
+        if let Some(base) = bases.get_mut(flag.team as usize) {
+            *base = Some(flag.base);
+        }
+    }
+
+    let mut messages = Vec::new();
+    for (team, flag) in nw.match_state.flags.iter_mut().enumerate() {
+        let returned = match flag {
+            FlagState::Home => false,
+            FlagState::Carried(id) => !nw
+                .lobby
+                .get(id)
+                .is_some_and(|info| !info.spectating && info.team.is_some()),
+            FlagState::Dropped { return_in, .. } => {
+                *return_in -= delta;
+                *return_in <= 0.0
+            }
+        };
+        if returned {
+            *flag = FlagState::Home;
+            messages.push(format!("THE {} FLAG WAS RETURNED", TEAM_NAMES[team]));
+        }
+    }
+
+    let players = nw
+        .lobby
+        .iter()
+        .filter(|(_, info)| !info.spectating)
+        .filter_map(|(id, info)| {
+            let (_, _, trans) = nw.players.get(info.entity).ok()?;
+            Some((*id, info.team?, info.name.to_lowercase(), trans.translation))
+        })
+        .collect::<Vec<_>>();
+
+    let state = &mut nw.match_state;
+    for (id, own_team, name, position) in players {
+        for (team, base) in bases.iter().enumerate() {
+            let base = option_continue!(*base);
+            let touching = |pos: Vec3| pos.distance(position) < FLAG_RADIUS;
+            let own = team == own_team as usize;
+            match state.flags[team] {
+                FlagState::Home if !own && touching(base) => {
+                    state.flags[team] = FlagState::Carried(id);
+                    messages.push(format!("{name} TOOK THE {} FLAG", TEAM_NAMES[team]));
+                }
+                FlagState::Dropped {
+                    position: dropped, ..
+                } if touching(dropped) => {
+                    if own {
+                        state.flags[team] = FlagState::Home;
+                        messages.push(format!("{name} RETURNED THE {} FLAG", TEAM_NAMES[team]));
+                    } else {
+                        state.flags[team] = FlagState::Carried(id);
+                        messages.push(format!("{name} TOOK THE {} FLAG", TEAM_NAMES[team]));
+                    }
+                }
+                // A flag can only be captured while your own is safe at home
+                FlagState::Home if own && touching(base) => {
+                    let carried = state
+                        .flags
+                        .iter()
+                        .position(|f| *f == FlagState::Carried(id));
+                    if let Some(carried) = carried {
+                        state.flags[carried] = FlagState::Home;
+                        state.team_scores[team] += 1;
+                        if let Some(info) = nw.lobby.get_mut(&id) {
+                            info.score += CAPTURE_SCORE;
+                        }
+                        messages.push(format!("{name} CAPTURED THE {} FLAG", TEAM_NAMES[carried]));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    for message in messages {
+        info!("{message}");
+        transmit_message(server, nw, message);
+    }
+}
+
+pub fn spawn_flag_models(
+    mut commands: Commands,
+    flags: Query<(Entity, &Flag), Added<Flag>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for (entity, flag) in &flags {
+        let colour = TEAM_COLOURS
+            .get(flag.team as usize)
+            .copied()
+            .unwrap_or(Color::WHITE);
+        commands.entity(entity).insert((
+            Mesh3d(meshes.add(Cuboid::new(0.4, 0.8, 0.1))),
+            MeshMaterial3d(materials.add(StandardMaterial {
+                base_color: colour,
+                unlit: true,
+                ..default()
+            })),
+            Visibility::Hidden,
+        ));
+    }
+}
+
+/// Shows the flags at their base, on their carrier or where they were dropped
+pub fn update_flag_models(
+    state: Res<MatchState>,
+    lobby: Res<Lobby>,
+    players: Query<&Transform, (With<Player>, Without<Flag>)>,
+    mut flags: Query<(&Flag, &mut Transform, &mut Visibility)>,
+) {
+    for (flag, mut trans, mut visibility) in &mut flags {
+        let flag_state = option_continue!(state.flags.get(flag.team as usize));
+        let position = match flag_state {
+            FlagState::Home => Some(flag.base),
+            FlagState::Carried(id) => lobby
+                .get(id)
+                .and_then(|info| players.get(info.entity).ok())
+                .map(|t| t.translation + CARRY_OFFSET),
+            FlagState::Dropped { position, .. } => Some(*position),
+        };
+        let shown = state.mode == GameMode::CaptureTheFlag && position.is_some();
+        if let Some(position) = position {
+            trans.translation = position;
+        }
+        visibility.set_if_neq(if shown {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        });
+    }
+}
@@ -0,0 +1,93 @@
+use crate::{
+    kill_feed::{DeathCause, Hurter},
+    net::{ServerChannel, ServerMessage},
+    player::Player,
+    queries::NetWorld,
+};
+use bevy::prelude::*;
+use bevy_renet::renet::RenetServer;
+use macros::error_return;
+
+/// Tells a player how much health and armour they have. The host shares its player with the server.
+pub fn send_health(server: &mut RenetServer, player: &Player, host_id: u64) {
+    if player.id == host_id {
+        return;
+    }
+    server.send_message(
+        player.id,
+        ServerChannel::ServerMessages as u8,
+        error_return!(
+            ServerMessage::SetHealth {
+                health: player.health,
+                armour: player.armour,
+            }
+            .bytes()
+        ),
+    );
+}
+
+/// Players hurting themselves take this part of the damage, so rocket jumps don't cost too much
+const SELF_DAMAGE_SCALE: f32 = 0.5;
+
+/// Hurts a player, scaled by the attacker's powerups and partly taken by the victim's armour,
+/// and knocks them away by `knockback`, the direction scaled by the force in units per second.
+/// Everything that hurts players on the server goes through here, the server's frag checker
+/// takes care of the ones who die.
+pub fn damage(
+    server: &mut RenetServer,
+    nw: &mut NetWorld,
+    victim: Entity,
+    amount: f32,
+    knockback: Vec3,
+    hurter: Hurter,
+) {
+    let scale = nw
+        .lobby
+        .get(&hurter.id)
+        .and_then(|info| nw.players.get(info.entity).ok())
+        .map_or(1.0, |(_, attacker, _)| attacker.damage_scale());
+
+    let (_, mut player, _) = error_return!(nw.players.get_mut(victim));
+    if player.spectating {
+        return;
+    }
+    let scale = match hurter.id == player.id {
+        true => scale * SELF_DAMAGE_SCALE,
+        false => scale,
+    };
+    player.last_hurter = hurter;
+    player.take_damage(amount * scale);
+    send_health(server, &player, nw.current_id.0);
+    push(server, &mut player, nw.current_id.0, knockback);
+}
+
+/// Hurts a player who landed at `speed` in units per second,
+/// by `Movement::fall_damage` for every unit per second over `Movement::fall_damage_speed`
+pub fn fall(server: &mut RenetServer, nw: &mut NetWorld, victim: Entity, speed: f32) {
+    let amount = (speed - nw.movement.fall_damage_speed) * nw.movement.fall_damage;
+    if amount <= 0.0 {
+        return;
+    }
+    let hurter = Hurter {
+        id: 0,
+        weapon: None,
+        cause: DeathCause::Fall,
+    };
+    damage(server, nw, victim, amount, Vec3::ZERO, hurter);
+}
+
+/// Knocks a player away, on their own client since that's where players move
+fn push(server: &mut RenetServer, player: &mut Player, host_id: u64, velocity: Vec3) {
+    if velocity == Vec3::ZERO {
+        return;
+    }
+    if player.id == host_id {
+        player.push(velocity);
+        return;
+    }
+    server.send_message(
+        player.id,
+        ServerChannel::ServerMessages as u8,
+        error_return!(ServerMessage::Push { velocity }.bytes()),
+    );
+}
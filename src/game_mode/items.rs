@@ -0,0 +1,176 @@
+use super::damage::send_health;
+use crate::{
+    entities::pickup::PickupEntity,
+    net::{ClientMessage, ServerChannel, ServerMessage, update_world},
+    player::{HEALTH_DECAY, MAX_HEALTH, Player, PlayerController},
+    queries::NetWorld,
+};
+use bevy::prelude::*;
+use bevy_renet::renet::RenetServer;
+use faststr::FastStr;
+use macros::{error_continue, error_return, option_return};
+use resources::{
+    AmmoMap,
+    data::{PickupData, PickupType, Powerup},
+};
+
+/// Whether picking up `data` would do anything for the player, the rest stays where it is
+pub fn wants_pickup(player: &Player, data: &PickupData, ammo_map: &AmmoMap) -> bool {
+    match data.pickup_type {
+        // Weapons don't stack, players who have one leave it for the others
+        PickupType::Weapon => !player.has_weapon(&data.gives),
+        PickupType::Powerup => true,
+        PickupType::Ammo => ammo_map
+            .0
+            .get(&data.gives)
+            .is_some_and(|ammo| player.can_take_ammo(ammo)),
+        PickupType::Health => player.health < data.limit as f32,
+        PickupType::Armour => {
+            player.armour * player.armour_absorb < data.amount as f32 * data.absorb
+        }
+    }
+}
+
+/// Gives a player whatever a pickup they touched gives
+pub fn pick_up(server: &mut RenetServer, nw: &mut NetWorld, client_id: u64, data: &PickupData) {
+    match data.pickup_type {
+        PickupType::Weapon => give_weapon(server, nw, client_id, &data.gives),
+        PickupType::Ammo => give_ammo(server, nw, client_id, &data.gives, data.amount),
+        PickupType::Health | PickupType::Armour => {
+            let entity = option_return!(nw.lobby.get(&client_id)).entity;
+            let (_, mut player, _) = error_return!(nw.players.get_mut(entity));
+            let amount = data.amount as f32;
+            let given = match data.pickup_type {
+                PickupType::Health => player.give_health(amount, data.limit as f32),
+                _ => player.give_armour(amount, data.absorb),
+            };
+            if given {
+                send_health(server, &player, nw.current_id.0);
+            }
+        }
+        PickupType::Powerup => {
+            let powerup = error_return!(data.gives.parse::<Powerup>());
+            let entity = option_return!(nw.lobby.get(&client_id)).entity;
+            let (_, mut player, _) = error_return!(nw.players.get_mut(entity));
+            player.give_powerup(powerup, data.duration);
+            let time = player.powerups.get(&powerup).copied().unwrap_or_default();
+
+            let text = format!("YOU GOT {}", powerup.name());
+            if client_id == nw.current_id.0 {
+                player.display_message(&mut nw.commands, &nw.asset_server, text);
+                return;
+            }
+            for message in [
+                ServerMessage::SetPowerup { powerup, time },
+                ServerMessage::Message { text },
+            ] {
+                server.send_message(
+                    client_id,
+                    ServerChannel::ServerMessages as u8,
+                    error_continue!(message.bytes()),
+                );
+            }
+        }
+    }
+}
+
+/// Gives a player a weapon along with some ammo for its first attack, and tells everyone about it
+fn give_weapon(server: &mut RenetServer, nw: &mut NetWorld, client_id: u64, weapon: &FastStr) {
+    let weapon_ammo = nw.weapon_map.0.get(weapon).and_then(|weapon| {
+        let (ammo, _) = weapon.attack1.ammo()?;
+        Some((ammo.clone(), weapon.pickup_ammo))
+    });
+    if let Some((ammo, amount)) = weapon_ammo {
+        give_ammo(server, nw, client_id, &ammo, amount);
+    }
+
+    let message = ClientMessage::PickupWeapon {
+        weapon: weapon.clone(),
+    };
+    update_world(client_id, &message, nw);
+    server.broadcast_message(
+        ServerChannel::NetworkedEntities as u8,
+        error_return!(
+            ServerMessage::PlayerUpdate {
+                id: client_id,
+                message,
+            }
+            .bytes()
+        ),
+    );
+}
+
+/// Gives a player ammo, and tells them how much they have now
+fn give_ammo(
+    server: &mut RenetServer,
+    nw: &mut NetWorld,
+    client_id: u64,
+    ammo: &FastStr,
+    amount: u32,
+) {
+    let data = option_return!(nw.ammo_map.0.get(ammo));
+    let entity = option_return!(nw.lobby.get(&client_id)).entity;
+    let (_, mut player, _) = error_return!(nw.players.get_mut(entity));
+    if !player.give_ammo(data, amount) || client_id == nw.current_id.0 {
+        return;
+    }
+    let amount = player.ammo.get(ammo).copied().unwrap_or_default();
+    server.send_message(
+        client_id,
+        ServerChannel::ServerMessages as u8,
+        error_return!(
+            ServerMessage::SetAmmo {
+                ammo: ammo.clone(),
+                amount
+            }
+            .bytes()
+        ),
+    );
+}
+
+/// Brings back taken pickups once their time is up, for the server and everyone else
+pub fn respawn_pickups(
+    time: Res<Time>,
+    mut server: ResMut<RenetServer>,
+    mut pickups: Query<(&mut PickupEntity, &mut Visibility)>,
+) {
+    for (mut pickup, mut visibility) in &mut pickups {
+        if pickup.available {
+            continue;
+        }
+        pickup.respawn_timer -= time.delta_secs();
+        if pickup.respawn_timer > 0.0 {
+            continue;
+        }
+        pickup.available = true;
+        *visibility = PickupEntity::visibility(true);
+        server.broadcast_message(
+            ServerChannel::ServerMessages as u8,
+            error_continue!(ServerMessage::RespawnPickup { id: pickup.id }.bytes()),
+        );
+    }
+}
+
+/// Counts down everyone's powerups and takes away health above the maximum on the server
+pub fn tick_players(mut server: ResMut<RenetServer>, mut nw: NetWorld) {
+    let delta = nw.time.delta_secs();
+    let host_id = nw.current_id.0;
+    for (_, mut player, _) in &mut nw.players {
+        player.tick_powerups(delta);
+        if player.health <= MAX_HEALTH {
+            continue;
+        }
+        let before = player.health.ceil();
+        player.health = (player.health - HEALTH_DECAY * delta).max(MAX_HEALTH);
+        if player.health.ceil() != before {
+            send_health(&mut server, &player, host_id);
+        }
+    }
+}
+
+/// Counts down the own powerups on clients, the server tells them when they get new ones
+pub fn tick_own_powerups(time: Res<Time>, mut players: Query<&mut Player, With<PlayerController>>) {
+    for mut player in &mut players {
+        player.tick_powerups(time.delta_secs());
+    }
+}
@@ -0,0 +1,169 @@
+use super::{MatchPhase, MatchState, broadcast, respawn_players};
+use crate::{
+    mainmenu::get_mapfiles,
+    map_gen::ReloadMap,
+    net::{ClientMessage, ServerMessage},
+    queries::NetWorld,
+};
+use bevy::prelude::*;
+use bevy_renet::renet::RenetServer;
+use macros::error_return;
+use resources::{CurrentMap, MapDoneLoading, inputs::PlayerInput};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// How many maps can be voted for during the intermission
+pub const VOTE_OPTIONS: usize = 5;
+const MAP_DIR: &str = "assets/maps";
+
+/// A map that can be voted for, and how many players voted for it
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct VoteOption {
+    pub map: PathBuf,
+    pub votes: u32,
+}
+
+/// Present on the server while it loads a new map, the match starts over once it's done
+#[derive(Debug, Resource)]
+pub struct MapChanging;
+
+/// The map files the server can switch to
+pub fn available_maps() -> Vec<PathBuf> {
+    let mut maps = error_return!(get_mapfiles(MAP_DIR))
+        .into_iter()
+        .filter(|m| m.extension().is_some_and(|e| e == "map"))
+        .collect::<Vec<_>>();
+    maps.sort();
+    maps
+}
+
+/// Finds a map by its path or its file name without the extension
+pub fn find_map(name: &str) -> Option<PathBuf> {
+    available_maps().into_iter().find(|m| {
+        m == Path::new(name)
+            || m.file_stem()
+                .is_some_and(|s| s.to_string_lossy().eq_ignore_ascii_case(name))
+    })
+}
+
+/// The name a map is shown with
+pub fn map_name(map: &Path) -> String {
+    map.file_stem()
+        .map(|s| s.to_string_lossy().to_uppercase())
+        .unwrap_or_default()
+}
+
+/// Tells everyone to load `map`. The server loads it as well, and starts a new match when it's done.
+pub fn change_map(server: &mut RenetServer, nw: &mut NetWorld, map: PathBuf) {
+    info!("changing map to {map:?}");
+    broadcast(server, ServerMessage::SetMap(map.clone()));
+    // Nothing should run out while the map loads
+    nw.match_state.time_left = None;
+    nw.commands.insert_resource(CurrentMap(map));
+    nw.commands.insert_resource(ReloadMap);
+    nw.commands.insert_resource(MapChanging);
+}
+
+/// Sends the pickups of the new map once the server has loaded it, and starts over with a warmup.
+/// Everyone keeps their stats until the match starts.
+pub fn finish_map_change(
+    mut server: ResMut<RenetServer>,
+    changing: Option<Res<MapChanging>>,
+    done_loading: Res<MapDoneLoading>,
+    mut nw: NetWorld,
+) {
+    if changing.is_none() || !done_loading.is_changed() || !done_loading.0 {
+        return;
+    }
+    nw.commands.remove_resource::<MapChanging>();
+
+    for (pickup, trans) in &nw.pickups_query {
+        broadcast(
+            &mut server,
+            ServerMessage::SpawnPickup {
+                id: pickup.id,
+                translation: trans.translation,
+                data: pickup.data.clone(),
+                available: pickup.available,
+            },
+        );
+    }
+    *nw.match_state = MatchState::new(&nw.settings);
+    respawn_players(&mut server, &mut nw);
+}
+
+/// Picks the maps for the vote at the end of a match.
+/// The next map in the rotation comes first, and wins if nobody votes.
+pub fn start_vote(nw: &mut NetWorld, current: &Path) {
+    let first = nw
+        .settings
+        .map_after(current)
+        .unwrap_or_else(|| current.to_path_buf());
+    let mut others = available_maps()
+        .into_iter()
+        .filter(|m| *m != first && m != current)
+        .collect::<Vec<_>>();
+
+    let mut options = vec![first];
+    while options.len() < VOTE_OPTIONS && !others.is_empty() {
+        let index = (nw.game_entropy.get_f32() * others.len() as f32) as usize;
+        options.push(others.swap_remove(index.min(others.len() - 1)));
+    }
+    nw.match_state.vote = options
+        .into_iter()
+        .map(|map| VoteOption { map, votes: 0 })
+        .collect();
+    for info in nw.lobby.values_mut() {
+        info.vote = None;
+    }
+}
+
+/// Counts the vote of a player, who can change their mind until the intermission is over
+pub fn vote(nw: &mut NetWorld, client_id: u64, choice: usize) {
+    if nw.match_state.phase != MatchPhase::Intermission || choice >= nw.match_state.vote.len() {
+        return;
+    }
+    let Some(info) = nw.lobby.get_mut(&client_id) else {
+        return;
+    };
+    info.vote = Some(choice);
+
+    for option in &mut nw.match_state.vote {
+        option.votes = 0;
+    }
+    for choice in nw.lobby.values().filter_map(|info| info.vote) {
+        nw.match_state.vote[choice].votes += 1;
+    }
+}
+
+/// The map with the most votes, the first one wins ties
+pub fn vote_winner(state: &MatchState) -> Option<PathBuf> {
+    state
+        .vote
+        .iter()
+        .rev()
+        .max_by_key(|option| option.votes)
+        .map(|option| option.map.clone())
+}
+
+/// Votes with the number keys during the intermission
+pub fn vote_keys(
+    state: Res<MatchState>,
+    input: Res<PlayerInput>,
+    mut client_events: EventWriter<ClientMessage>,
+) {
+    let keys = [
+        input.weapon_slot1_just_pressed,
+        input.weapon_slot2_just_pressed,
+        input.weapon_slot3_just_pressed,
+        input.weapon_slot4_just_pressed,
+        input.weapon_slot5_just_pressed,
+    ];
+    let choice = keys
+        .iter()
+        .take(state.vote.len())
+        .position(|pressed| *pressed);
+    if let Some(choice) = choice {
+        client_events.send(ClientMessage::Vote { choice });
+    }
+}
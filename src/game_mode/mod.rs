@@ -0,0 +1,530 @@
+use crate::{
+    net::{
+        Lobby, NetState, ServerChannel, ServerMessage, config::ServerSettings,
+        server::transmit_message,
+    },
+    player::{Player, PlayerController, PlayerMpModel},
+    queries::NetWorld,
+};
+use bevy::{ecs::schedule::SystemConfigs, prelude::*};
+use bevy_renet::renet::RenetServer;
+use macros::{error_continue, error_return, option_continue, option_return};
+use resources::CurrentMap;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+pub mod ctf;
+pub mod damage;
+pub mod items;
+pub mod maps;
+pub mod spawns;
+
+pub const TEAM_COUNT: usize = 2;
+pub const TEAM_NAMES: [&str; TEAM_COUNT] = ["RED", "BLUE"];
+pub const TEAM_COLOURS: [Color; TEAM_COUNT] =
+    [Color::srgb(1.0, 0.3, 0.3), Color::srgb(0.3, 0.4, 1.0)];
+
+/// The rules a server plays by, set with `mode` in the server settings
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GameMode {
+    #[default]
+    Deathmatch,
+    TeamDeathmatch,
+    CaptureTheFlag,
+}
+impl GameMode {
+    pub fn is_team_based(self) -> bool {
+        self != GameMode::Deathmatch
+    }
+}
+impl FromStr for GameMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_lowercase()[..] {
+            "dm" | "deathmatch" => Ok(GameMode::Deathmatch),
+            "tdm" | "teamdeathmatch" => Ok(GameMode::TeamDeathmatch),
+            "ctf" | "capturetheflag" => Ok(GameMode::CaptureTheFlag),
+            _ => Err("unknown game mode, expected dm, tdm or ctf".to_string()),
+        }
+    }
+}
+impl std::fmt::Display for GameMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GameMode::Deathmatch => write!(f, "DEATHMATCH"),
+            GameMode::TeamDeathmatch => write!(f, "TEAM DEATHMATCH"),
+            GameMode::CaptureTheFlag => write!(f, "CAPTURE THE FLAG"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MatchPhase {
+    /// Frags don't count yet
+    #[default]
+    Warmup,
+    Playing,
+    /// The match is over, everyone looks through the `info_end_camera`
+    Intermission,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum FlagState {
+    #[default]
+    Home,
+    Carried(u64),
+    Dropped {
+        position: Vec3,
+        /// Seconds until the flag goes back to its base by itself
+        return_in: f32,
+    },
+}
+
+/// The state of the match, kept by the server and sent to the clients whenever it changes
+#[derive(Debug, Resource, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct MatchState {
+    pub mode: GameMode,
+    pub phase: MatchPhase,
+    /// Seconds left in the current phase, None if it has no time limit
+    pub time_left: Option<f32>,
+    /// Frags in team deathmatch, captures in capture the flag
+    pub team_scores: [i64; TEAM_COUNT],
+    pub flags: [FlagState; TEAM_COUNT],
+    pub winner: Option<String>,
+    /// The maps to vote for during the intermission
+    pub vote: Vec<maps::VoteOption>,
+}
+impl MatchState {
+    pub fn new(settings: &ServerSettings) -> Self {
+        Self {
+            mode: settings.mode,
+            phase: MatchPhase::Warmup,
+            time_left: Some(settings.warmup),
+            ..default()
+        }
+    }
+
+    /// Counts a frag for the team scores
+    pub fn team_frag(&mut self, killer: Option<u8>, victim: Option<u8>) {
+        if self.phase != MatchPhase::Playing || self.mode != GameMode::TeamDeathmatch {
+            return;
+        }
+        let (Some(killer), Some(victim)) = (killer, victim) else {
+            return;
+        };
+        let score = option_return!(self.team_scores.get_mut(killer as usize));
+        // Killing your own team costs a frag
+        *score += if killer == victim { -1 } else { 1 };
+    }
+
+    /// Who won if the match is over, either through the frag/capture limit or the time limit
+    pub fn winner(&self, lobby: &Lobby, settings: &ServerSettings) -> Option<String> {
+        let limit = match self.mode {
+            GameMode::Deathmatch | GameMode::TeamDeathmatch => settings.fraglimit,
+            GameMode::CaptureTheFlag => settings.capturelimit,
+        } as i64;
+        let timed_out = self.time_left.is_some_and(|t| t <= 0.0);
+
+        if !self.mode.is_team_based() {
+            let players = lobby.values().filter(|i| !i.spectating);
+            // Suicides and team kills count against the player, so it's the score and not the kills
+            let best = players.clone().map(|i| i.score).max().unwrap_or_default();
+            if !timed_out && (limit <= 0 || best < limit) {
+                return None;
+            }
+            let mut leaders = players.filter(|i| i.score == best);
+            return Some(match (leaders.next(), leaders.next()) {
+                (Some(leader), None) => format!("{} WINS", leader.name.to_lowercase()),
+                _ => "DRAW".to_string(),
+            });
+        }
+
+        let best = *self.team_scores.iter().max()?;
+        if !timed_out && (limit <= 0 || best < limit) {
+            return None;
+        }
+        let mut leaders = (0..TEAM_COUNT).filter(|t| self.team_scores[*t] == best);
+        Some(match (leaders.next(), leaders.next()) {
+            (Some(leader), None) => format!("{} TEAM WINS", TEAM_NAMES[leader]),
+            _ => "DRAW".to_string(),
+        })
+    }
+
+    /// Everything except the timers, which the clients count down themselves
+    fn without_timers(&self) -> Self {
+        let mut state = self.clone();
+        state.time_left = None;
+        for flag in &mut state.flags {
+            if let FlagState::Dropped { return_in, .. } = flag {
+                *return_in = 0.0;
+            }
+        }
+        state
+    }
+}
+
+/// Where players look from during the intermission, from `info_end_camera`
+#[derive(Debug, Component)]
+pub struct EndCamera {
+    pub position: Vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+}
+
+pub fn team_name(team: Option<u8>) -> &'static str {
+    team.and_then(|t| TEAM_NAMES.get(t as usize))
+        .copied()
+        .unwrap_or("NO")
+}
+
+fn broadcast(server: &mut RenetServer, message: ServerMessage) {
+    server.broadcast_message(
+        ServerChannel::ServerMessages as u8,
+        error_return!(message.bytes()),
+    );
+}
+
+/// Puts players without a team on the smallest one, or takes the teams away outside of team modes
+fn assign_teams(server: &mut RenetServer, nw: &mut NetWorld) {
+    let team_based = nw.match_state.mode.is_team_based();
+    let mut counts = [0; TEAM_COUNT];
+    for info in nw.lobby.values().filter(|i| !i.spectating) {
+        if let Some(team) = info.team {
+            counts[team as usize] += 1;
+        }
+    }
+
+    let unassigned = nw
+        .lobby
+        .iter()
+        .filter(|(_, i)| match team_based {
+            true => i.team.is_none() && !i.spectating,
+            false => i.team.is_some(),
+        })
+        .map(|(id, _)| *id)
+        .collect::<Vec<_>>();
+    for id in unassigned {
+        let team = team_based.then(|| if counts[0] <= counts[1] { 0 } else { 1 });
+        if let Some(team) = team {
+            counts[team as usize] += 1;
+        }
+        let info = option_continue!(nw.lobby.get_mut(&id));
+        info.team = team;
+        let name = info.name.clone();
+        broadcast(server, ServerMessage::SetTeam { id, team });
+        if team.is_some() {
+            // Over to the spawns of the new team
+            if nw.match_state.phase != MatchPhase::Intermission {
+                spawns::respawn(server, nw, id);
+            }
+            transmit_message(
+                server,
+                nw,
+                format!(
+                    "{} JOINED THE {} TEAM",
+                    name.to_lowercase(),
+                    team_name(team)
+                ),
+            );
+        }
+    }
+}
+
+/// Respawns everyone with fresh scores
+fn start_match(server: &mut RenetServer, nw: &mut NetWorld) {
+    for info in nw.lobby.values_mut() {
+        info.kills = 0;
+        info.deaths = 0;
+        info.score = 0;
+    }
+    nw.match_state.team_scores = Default::default();
+    nw.match_state.flags = Default::default();
+    nw.match_state.winner = None;
+    respawn_players(server, nw);
+}
+
+/// Throws the current match away and starts over with a warmup, picking up any changed settings
+pub fn restart_match(server: &mut RenetServer, nw: &mut NetWorld) {
+    *nw.match_state = MatchState::new(&nw.settings);
+    start_match(server, nw);
+}
+
+fn respawn_players(server: &mut RenetServer, nw: &mut NetWorld) {
+    let players = nw
+        .lobby
+        .iter()
+        .filter(|(_, info)| !info.spectating)
+        .map(|(id, _)| *id)
+        .collect::<Vec<_>>();
+    for id in players {
+        spawns::respawn(server, nw, id);
+    }
+}
+
+/// Moves the match through its phases, and keeps the clients up to date
+pub fn update_match(
+    mut server: ResMut<RenetServer>,
+    flags: Query<&ctf::Flag>,
+    map: Res<CurrentMap>,
+    mut last_sent: Local<Option<MatchState>>,
+    mut nw: NetWorld,
+) {
+    assign_teams(&mut server, &mut nw);
+
+    let delta = nw.time.delta_secs();
+    if let Some(time_left) = &mut nw.match_state.time_left {
+        *time_left -= delta;
+    }
+    let time_up = nw.match_state.time_left.is_some_and(|t| t <= 0.0);
+
+    match nw.match_state.phase {
+        MatchPhase::Warmup if time_up => {
+            start_match(&mut server, &mut nw);
+            nw.match_state.phase = MatchPhase::Playing;
+            let timelimit = nw.settings.timelimit;
+            nw.match_state.time_left = (timelimit > 0.0).then_some(timelimit * 60.0);
+            let mode = nw.match_state.mode;
+            transmit_message(&mut server, &mut nw, format!("{mode} HAS STARTED"));
+        }
+        MatchPhase::Warmup => {}
+        MatchPhase::Playing => {
+            if nw.match_state.mode == GameMode::CaptureTheFlag {
+                ctf::update_flags(&mut server, &mut nw, &flags, delta);
+            }
+            if let Some(winner) = nw.match_state.winner(&nw.lobby, &nw.settings) {
+                info!("match over: {winner}");
+                nw.match_state.phase = MatchPhase::Intermission;
+                nw.match_state.time_left = Some(nw.settings.intermission);
+                nw.match_state.winner = Some(winner.clone());
+                transmit_message(&mut server, &mut nw, winner);
+                maps::start_vote(&mut nw, &map.0);
+            }
+        }
+        MatchPhase::Intermission if time_up => {
+            let next = maps::vote_winner(&nw.match_state);
+            nw.match_state.vote.clear();
+            if let Some(next) = next
+                && next != map.0
+            {
+                let name = maps::map_name(&next);
+                transmit_message(&mut server, &mut nw, format!("NEXT MAP: {name}"));
+                maps::change_map(&mut server, &mut nw, next);
+                return;
+            }
+            // Scores stay on the board during the warmup
+            respawn_players(&mut server, &mut nw);
+            nw.match_state.phase = MatchPhase::Warmup;
+            nw.match_state.time_left = Some(nw.settings.warmup);
+            nw.match_state.winner = None;
+        }
+        MatchPhase::Intermission => {}
+    }
+
+    let state = nw.match_state.without_timers();
+    if last_sent.as_ref() != Some(&state) {
+        broadcast(
+            &mut server,
+            ServerMessage::MatchState(nw.match_state.clone()),
+        );
+        *last_sent = Some(state);
+    }
+}
+
+/// Clients count the timers down between updates from the server
+pub fn count_down(mut state: ResMut<MatchState>, time: Res<Time>) {
+    let delta = time.delta_secs();
+    if let Some(time_left) = &mut state.time_left {
+        *time_left = (*time_left - delta).max(0.0);
+    }
+    for flag in &mut state.flags {
+        if let FlagState::Dropped { return_in, .. } = flag {
+            *return_in = (*return_in - delta).max(0.0);
+        }
+    }
+}
+
+pub fn in_intermission(state: Res<MatchState>) -> bool {
+    state.phase == MatchPhase::Intermission
+}
+
+/// Looks through the end camera during the intermission
+pub fn intermission_camera(
+    state: Res<MatchState>,
+    end_cameras: Query<&EndCamera>,
+    mut players: Query<(&Player, &mut Transform), (With<PlayerController>, Without<Camera3d>)>,
+    mut cameras: Query<&mut Transform, With<Camera3d>>,
+) {
+    if state.phase != MatchPhase::Intermission {
+        return;
+    }
+    let end_camera = option_return!(end_cameras.iter().next());
+    for (player, mut trans) in &mut players {
+        let camera = option_continue!(player.children.camera);
+        let mut camera = error_continue!(cameras.get_mut(camera));
+        trans.translation = end_camera.position - camera.translation;
+        trans.rotation = Quat::from_rotation_y(end_camera.yaw);
+        camera.rotation = Quat::from_rotation_x(end_camera.pitch);
+    }
+}
+
+/// Paints player models in their team colour
+pub fn update_team_colours(
+    lobby: Res<Lobby>,
+    players: Query<&Player>,
+    models: Query<(&Parent, &MeshMaterial3d<StandardMaterial>), With<PlayerMpModel>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for (parent, material) in &models {
+        let player = option_continue!(players.get(parent.get()).ok());
+        let team = lobby.get(&player.id).and_then(|i| i.team);
+        let colour = team
+            .map(|t| TEAM_COLOURS[t as usize])
+            .unwrap_or(Color::WHITE);
+        if option_continue!(materials.get(&material.0)).base_color != colour {
+            option_continue!(materials.get_mut(&material.0)).base_color = colour;
+        }
+    }
+}
+
+fn format_time(secs: f32) -> String {
+    let secs = secs.max(0.0).ceil() as u32;
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+pub fn update_match_hud(
+    state: Res<MatchState>,
+    q_players: Query<&Player, With<PlayerController>>,
+    mut text: Query<&mut Text>,
+) {
+    for player in &q_players {
+        let hud = option_continue!(player.children.match_hud);
+        let mut hud = error_continue!(text.get_mut(hud));
+
+        let time = state.time_left.map(format_time);
+        let mut lines = Vec::new();
+        match state.phase {
+            MatchPhase::Warmup => lines.push(match time {
+                Some(time) => format!("WARMUP {time}"),
+                None => "WARMUP".to_string(),
+            }),
+            MatchPhase::Playing => lines.extend(time),
+            MatchPhase::Intermission => lines.extend(state.winner.clone()),
+        }
+        if state.phase == MatchPhase::Intermission && !state.vote.is_empty() {
+            lines.push("VOTE FOR THE NEXT MAP".to_string());
+            for (i, option) in state.vote.iter().enumerate() {
+                let name = maps::map_name(&option.map);
+                lines.push(format!("{} {name} ({})", i + 1, option.votes));
+            }
+        }
+        if state.mode.is_team_based() {
+            lines.push(format!(
+                "{} {} - {} {}",
+                TEAM_NAMES[0], state.team_scores[0], state.team_scores[1], TEAM_NAMES[1]
+            ));
+        }
+        hud.0 = lines.join("\n");
+    }
+}
+
+pub fn systems() -> SystemConfigs {
+    (
+        count_down.run_if(not(in_state(NetState::Server))),
+        items::tick_own_powerups.run_if(not(in_state(NetState::Server))),
+        intermission_camera
+            .after(Player::update_cam_hort)
+            .after(Player::update_cam_vert)
+            .after(Player::spectate),
+        update_team_colours,
+        update_match_hud,
+        ctf::spawn_flag_models,
+        ctf::update_flag_models,
+        maps::vote_keys.run_if(in_intermission),
+    )
+        .into_configs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::PlayerInfo;
+
+    /// Players with these scores and as many kills, without suicides
+    fn lobby(scores: &[i64]) -> Lobby {
+        let mut lobby = Lobby::default();
+        for (id, score) in scores.iter().enumerate() {
+            let mut info = PlayerInfo::new(Entity::PLACEHOLDER, format!("p{id}").into());
+            info.kills = (*score).max(0) as u64;
+            info.score = *score;
+            lobby.insert(id as u64, info);
+        }
+        lobby
+    }
+
+    #[test]
+    fn deathmatch_ends_at_fraglimit_or_time() {
+        let settings = ServerSettings {
+            fraglimit: 10,
+            ..default()
+        };
+        let mut state = MatchState {
+            phase: MatchPhase::Playing,
+            time_left: Some(60.0),
+            ..default()
+        };
+        assert_eq!(state.winner(&lobby(&[9, 3]), &settings), None);
+        assert_eq!(
+            state.winner(&lobby(&[10, 3]), &settings),
+            Some("p0 WINS".to_string())
+        );
+        state.time_left = Some(0.0);
+        assert_eq!(
+            state.winner(&lobby(&[4, 4]), &settings),
+            Some("DRAW".to_string())
+        );
+    }
+
+    #[test]
+    fn suicides_count_against_the_winner() {
+        let settings = ServerSettings {
+            fraglimit: 10,
+            ..default()
+        };
+        let state = MatchState {
+            phase: MatchPhase::Playing,
+            time_left: Some(60.0),
+            ..default()
+        };
+        // p0 got to the fraglimit in kills, but killed themselves once on the way
+        let mut players = lobby(&[9, 9]);
+        players.get_mut(&0).unwrap().kills = 10;
+        assert_eq!(state.winner(&players, &settings), None);
+        players.get_mut(&1).unwrap().score = 10;
+        assert_eq!(
+            state.winner(&players, &settings),
+            Some("p1 WINS".to_string())
+        );
+    }
+
+    #[test]
+    fn team_kills_cost_a_frag() {
+        let settings = ServerSettings {
+            fraglimit: 2,
+            ..default()
+        };
+        let mut state = MatchState {
+            mode: GameMode::TeamDeathmatch,
+            phase: MatchPhase::Playing,
+            ..default()
+        };
+        state.team_frag(Some(1), Some(0));
+        state.team_frag(Some(0), Some(0));
+        state.team_frag(Some(1), Some(0));
+        assert_eq!(state.team_scores, [-1, 2]);
+        assert_eq!(
+            state.winner(&lobby(&[]), &settings),
+            Some("BLUE TEAM WINS".to_string())
+        );
+    }
+}
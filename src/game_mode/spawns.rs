@@ -0,0 +1,172 @@
+use crate::{
+    kill_feed::{DeathCause, Hurter},
+    net::{ServerChannel, ServerMessage},
+    player::Player,
+    queries::NetWorld,
+};
+use bevy::prelude::*;
+use bevy_rapier3d::pipeline::QueryFilter;
+use bevy_renet::renet::RenetServer;
+use macros::{error_continue, error_return, option_return};
+use resources::{SpawnKind, SpawnPoint};
+use serde::Deserialize;
+use std::str::FromStr;
+
+/// How a spawn point is picked when a player joins or respawns, set with `spawn_policy` in the server settings
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SpawnPolicy {
+    /// The free spawn farthest away from the closest enemy
+    #[default]
+    Farthest,
+    /// Any spawn, even if someone is standing on it
+    Random,
+    /// A random spawn nobody is standing on
+    Unoccupied,
+}
+impl FromStr for SpawnPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_lowercase()[..] {
+            "farthest" => Ok(SpawnPolicy::Farthest),
+            "random" => Ok(SpawnPolicy::Random),
+            "unoccupied" => Ok(SpawnPolicy::Unoccupied),
+            _ => Err("unknown spawn policy, expected farthest, random or unoccupied".to_string()),
+        }
+    }
+}
+
+/// Players standing on `position`, except for `exclude`
+fn occupants(nw: &NetWorld, position: Vec3, exclude: Option<Entity>) -> Vec<Entity> {
+    let Ok(context) = nw.rapier_context.get_single() else {
+        return Vec::new();
+    };
+    let mut filter = QueryFilter::new().exclude_sensors();
+    if let Some(exclude) = exclude {
+        filter = filter.exclude_collider(exclude);
+    }
+
+    let mut occupants = Vec::new();
+    context.intersections_with_shape(
+        position,
+        Quat::IDENTITY,
+        &Player::collider(),
+        filter,
+        |entity| {
+            if nw.players.contains(entity) {
+                occupants.push(entity);
+            }
+            true
+        },
+    );
+    occupants
+}
+
+/// Picks where player `id` spawns next, using the spawns of their team if the map has any.
+/// `entity` is their own player, which doesn't count as standing on a spawn.
+pub fn select_spawn(nw: &mut NetWorld, id: u64, entity: Option<Entity>) -> SpawnPoint {
+    let team = nw
+        .lobby
+        .get(&id)
+        .and_then(|info| info.team)
+        .filter(|_| nw.match_state.mode.is_team_based());
+    let of_kind = |kind: SpawnKind| {
+        nw.player_spawns
+            .0
+            .iter()
+            .filter(|s| s.kind == kind)
+            .copied()
+            .collect::<Vec<_>>()
+    };
+    let mut candidates = team
+        .map(|team| of_kind(SpawnKind::Team(team)))
+        .unwrap_or_default();
+    if candidates.is_empty() {
+        candidates = of_kind(SpawnKind::Deathmatch);
+    }
+    if candidates.is_empty() {
+        candidates = of_kind(SpawnKind::Start);
+    }
+    if candidates.is_empty() {
+        warn!("map has no spawn points");
+        return SpawnPoint::default();
+    }
+
+    let free = candidates
+        .iter()
+        .filter(|s| occupants(nw, s.position, entity).is_empty())
+        .copied()
+        .collect::<Vec<_>>();
+    // Everything is taken, someone is getting telefragged
+    let pool = if free.is_empty() { &candidates } else { &free };
+
+    match nw.settings.spawn_policy {
+        SpawnPolicy::Random => nw.game_entropy.choose_copy(&candidates),
+        SpawnPolicy::Unoccupied => nw.game_entropy.choose_copy(pool),
+        SpawnPolicy::Farthest => {
+            let enemies = nw
+                .lobby
+                .iter()
+                .filter(|(other, info)| **other != id && !info.spectating)
+                .filter(|(_, info)| team.is_none() || info.team != team)
+                .filter_map(|(_, info)| nw.players.get(info.entity).ok())
+                .map(|(_, _, trans)| trans.translation)
+                .collect::<Vec<_>>();
+            if enemies.is_empty() {
+                return nw.game_entropy.choose_copy(pool);
+            }
+            let distance = |spawn: &SpawnPoint| {
+                enemies
+                    .iter()
+                    .map(|e| e.distance(spawn.position))
+                    .fold(f32::INFINITY, f32::min)
+            };
+            pool.iter()
+                .max_by(|a, b| distance(*a).total_cmp(&distance(*b)))
+                .copied()
+                .unwrap_or_default()
+        }
+    }
+}
+
+/// Kills everyone standing where player `id` is about to spawn
+pub fn telefrag(nw: &mut NetWorld, id: u64, entity: Option<Entity>, position: Vec3) {
+    for occupant in occupants(nw, position, entity) {
+        let (_, mut player, _) = error_continue!(nw.players.get_mut(occupant));
+        if player.id == id {
+            continue;
+        }
+        player.last_hurter = Hurter {
+            id,
+            weapon: None,
+            cause: DeathCause::Telefrag,
+        };
+        player.health = 0.0;
+    }
+}
+
+/// Sends player `id` to a fresh spawn with full health. Only called on the server.
+pub fn respawn(server: &mut RenetServer, nw: &mut NetWorld, id: u64) {
+    let entity = option_return!(nw.lobby.get(&id)).entity;
+    let spawn = select_spawn(nw, id, Some(entity));
+    telefrag(nw, id, Some(entity), spawn.position);
+
+    let (_, mut player, mut trans) = error_return!(nw.players.get_mut(entity));
+    player.reset(&nw.ammo_map);
+    trans.translation = spawn.position;
+    if id == nw.current_id.0 {
+        trans.rotation = Quat::from_rotation_y(spawn.yaw);
+    } else {
+        server.send_message(
+            id,
+            ServerChannel::ServerMessages as u8,
+            error_return!(
+                ServerMessage::Reset {
+                    position: spawn.position,
+                    yaw: spawn.yaw
+                }
+                .bytes()
+            ),
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use crate::{
+    game_mode::TEAM_COLOURS,
+    net::Lobby,
+    player::{Player, PlayerController},
+};
+use bevy::{prelude::*, text::FontSmoothing};
+use faststr::FastStr;
+use macros::option_return;
+use resources::WeaponMap;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// How many frags are shown at once
+const FEED_LENGTH: usize = 5;
+/// How long a frag stays in the feed
+const FEED_TIME: Duration = Duration::from_secs(6);
+const FONT_SIZE: f32 = 16.0;
+const ICON_SIZE: f32 = 16.0;
+
+/// What killed a player
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DeathCause {
+    Hitscan,
+    Projectile,
+    Fall,
+    Lava,
+    Telefrag,
+    #[default]
+    Suicide,
+}
+impl std::fmt::Display for DeathCause {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            DeathCause::Hitscan => "hitscan",
+            DeathCause::Projectile => "projectile",
+            DeathCause::Fall => "fell",
+            DeathCause::Lava => "lava",
+            DeathCause::Telefrag => "telefrag",
+            DeathCause::Suicide => "suicide",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Whatever hurt a player last, they're credited with the frag if the player dies
+#[derive(Debug, Clone, Default)]
+pub struct Hurter {
+    /// 0 if the player hurt themselves or the world did
+    pub id: u64,
+    pub weapon: Option<FastStr>,
+    pub cause: DeathCause,
+}
+
+/// Sent when a player dies, on the server as well as on the clients
+#[derive(Debug, Serialize, Deserialize, Clone, Event)]
+pub struct Frag {
+    pub victim: u64,
+    /// None for suicides and accidents
+    pub attacker: Option<u64>,
+    pub weapon: Option<FastStr>,
+    pub cause: DeathCause,
+}
+impl Frag {
+    pub fn new(victim: u64, hurter: Hurter) -> Self {
+        Self {
+            victim,
+            attacker: (hurter.id != 0 && hurter.id != victim).then_some(hurter.id),
+            weapon: hurter.weapon,
+            cause: hurter.cause,
+        }
+    }
+}
+
+fn spawn_name(c: &mut ChildBuilder, lobby: &Lobby, id: u64, font: &Handle<Font>) {
+    let (name, colour) = match lobby.get(&id) {
+        Some(info) => (
+            info.name.to_lowercase(),
+            info.team
+                .map(|team| TEAM_COLOURS[team as usize])
+                .unwrap_or(Color::WHITE),
+        ),
+        None => (id.to_string(), Color::WHITE),
+    };
+    spawn_text(c, name, colour, font);
+}
+
+fn spawn_text(c: &mut ChildBuilder, text: impl Into<String>, colour: Color, font: &Handle<Font>) {
+    c.spawn((
+        Text::new(text),
+        TextFont {
+            font: font.clone(),
+            font_size: FONT_SIZE,
+            font_smoothing: FontSmoothing::None,
+        },
+        TextColor(colour),
+    ));
+}
+
+/// Adds new frags to the kill feed in the top right corner, and removes old ones
+pub fn update_kill_feed(
+    mut commands: Commands,
+    mut frags: EventReader<Frag>,
+    lobby: Res<Lobby>,
+    weapon_map: Res<WeaponMap>,
+    asset_server: Res<AssetServer>,
+    q_players: Query<&Player, With<PlayerController>>,
+    mut rows: Local<VecDeque<(Instant, Entity)>>,
+) {
+    let player = option_return!(q_players.iter().next());
+    let feed = option_return!(player.children.kill_feed);
+    let font = asset_server.load("ui/Color Basic.otf");
+    for frag in frags.read() {
+        let weapon = frag.weapon.as_ref().and_then(|w| weapon_map.0.get(w));
+        let row = commands
+            .spawn(Node {
+                column_gap: Val::Px(6.0),
+                align_items: AlignItems::Center,
+                ..default()
+            })
+            .with_children(|c| {
+                if let Some(attacker) = frag.attacker {
+                    spawn_name(c, &lobby, attacker, &font);
+                }
+                match weapon {
+                    Some(weapon) if !weapon.icon.is_empty() => {
+                        c.spawn((
+                            Node {
+                                width: Val::Px(ICON_SIZE * 2.0),
+                                height: Val::Px(ICON_SIZE),
+                                ..default()
+                            },
+                            ImageNode::new(asset_server.load(weapon.icon.to_string())),
+                        ));
+                    }
+                    Some(weapon) => {
+                        let name = format!("[{}]", weapon.fancy_name.to_uppercase());
+                        spawn_text(c, name, Color::WHITE, &font);
+                    }
+                    None => {
+                        let cause = format!("[{}]", frag.cause.to_string().to_uppercase());
+                        spawn_text(c, cause, Color::WHITE, &font);
+                    }
+                }
+                spawn_name(c, &lobby, frag.victim, &font);
+            })
+            .id();
+        commands.entity(feed).add_child(row);
+        rows.push_back((Instant::now(), row));
+    }
+
+    let expired = |rows: &VecDeque<(Instant, Entity)>| {
+        rows.front()
+            .is_some_and(|(added, _)| added.elapsed() > FEED_TIME)
+            || rows.len() > FEED_LENGTH
+    };
+    while expired(&rows) {
+        let (_, row) = option_return!(rows.pop_front());
+        if let Some(mut row) = commands.get_entity(row) {
+            row.despawn_recursive();
+        }
+    }
+}
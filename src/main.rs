@@ -2,6 +2,7 @@
 extern crate macros;
 use crate::net::{
     SimulationEvent,
+    handshake::AssetHash,
     steam::{SteamClient, try_steam},
 };
 use bevy::{
@@ -21,11 +22,15 @@ use bevy_renet::{
 };
 use bevy_scene_hook::reload::Plugin as HookPlugin;
 use bevy_simple_text_input::TextInputPlugin;
-use net::ClientMessage;
+use kill_feed::Frag;
+use net::{ClientMessage, rcon::RconRequest};
 use plugins::{ClientPlugin, GameStage, MainMenuStage, Resources, ServerPlugin, StartupStage};
 use steamworks::{AppId, SingleClient};
 
+mod chat;
 mod entities;
+mod game_mode;
+mod kill_feed;
 mod mainmenu;
 mod map_gen;
 mod net;
@@ -34,6 +39,7 @@ mod player;
 mod plugins;
 mod queries;
 mod qwak_host_functions;
+mod scoreboard;
 mod startup;
 
 const APP_ID: AppId = AppId(480);
@@ -43,9 +49,11 @@ fn steam_callbacks(client: NonSend<SingleClient>) {
 }
 
 fn main() {
-    println!("Running with asset hash: {}", integrity::get_asset_hash());
+    let asset_hash = integrity::get_asset_hash();
+    println!("Running with asset hash: {asset_hash}");
 
     let mut app = App::new();
+    app.insert_resource(AssetHash(asset_hash));
     app.add_plugins(
         DefaultPlugins
             .set({
@@ -71,7 +79,9 @@ fn main() {
     // app.add_plugins(bevy_inspector_egui::quick::WorldInspectorPlugin::new());
 
     app.add_event::<ClientMessage>()
-        .add_event::<SimulationEvent>();
+        .add_event::<SimulationEvent>()
+        .add_event::<Frag>()
+        .add_event::<RconRequest>();
 
     app.add_plugins(Resources);
     app.add_plugins(RapierPhysicsPlugin::<NoUserData>::default());
@@ -1,14 +1,23 @@
 use crate::{
     APP_ID,
-    net::{self, NetState, steam::SteamClient},
+    net::{
+        self, NetState,
+        client::{AutoReconnect, ConnectionState, Reconnect, SpectateOnJoin},
+        demo::{self, RecordDemos},
+        discovery::ServerBrowser,
+        steam::SteamClient,
+    },
     plugins::Qwaks,
 };
 use bevy::{ecs::system::SystemState, prelude::*};
-use bevy_simple_text_input::{TextInput, TextInputSettings, TextInputTextFont, TextInputValue};
-use macros::{error_continue, error_return};
+use bevy_simple_text_input::{
+    TextInput, TextInputPlaceholder, TextInputSettings, TextInputTextFont, TextInputValue,
+};
+use macros::{error_continue, error_return, option_return};
 use resources::{CurrentMap, CurrentStage};
 use std::{
     fs, io,
+    net::SocketAddr,
     path::{Path, PathBuf},
 };
 use steamworks::FriendFlags;
@@ -21,15 +30,79 @@ pub enum ButtonEvent {
     Solo,
     StartMp,
     JoinMp,
+    RefreshServers,
+    JoinServer(SocketAddr),
+    ToggleReconnect,
+    DismissError,
+    ToggleRecordDemos,
+    ToggleSpectate,
+    PlayDemo(PathBuf),
 }
 
 #[derive(Debug, Component)]
 pub struct LevelButton(PathBuf);
 
+#[derive(Debug, Component)]
+pub struct AddressInput;
+
+#[derive(Debug, Component)]
+pub struct PasswordInput;
+
 #[derive(Debug, Component)]
 pub struct FriendButton(u64);
 
-fn get_mapfiles<P: AsRef<Path>>(dir: P) -> io::Result<Vec<PathBuf>> {
+#[derive(Debug, Component)]
+pub struct ServerList;
+
+#[derive(Debug, Component)]
+pub struct ErrorDialog;
+
+#[derive(Debug, Component)]
+pub struct ErrorText;
+
+#[derive(Debug, Component)]
+pub struct ReconnectToggle;
+
+#[derive(Debug, Component)]
+pub struct RecordToggle;
+
+#[derive(Debug, Component)]
+pub struct SpectateToggle;
+
+/// What the error dialog should say, if it should be shown at all
+fn error_text(
+    connection: Option<&ConnectionState>,
+    reconnect: Option<&Reconnect>,
+) -> Option<String> {
+    let reconnect = reconnect.map(|r| r.describe());
+    match (connection?, reconnect) {
+        (ConnectionState::Disconnected { reason, .. }, Some(reconnect)) => {
+            Some(format!("Disconnected: {reason}\n{reconnect}"))
+        }
+        (ConnectionState::Disconnected { reason, .. }, None) => {
+            Some(format!("Disconnected: {reason}"))
+        }
+        (ConnectionState::Connecting, Some(reconnect)) => Some(reconnect),
+        _ => None,
+    }
+}
+
+fn reconnect_text(auto_reconnect: &AutoReconnect) -> String {
+    format!(
+        "Auto reconnect: {}",
+        if auto_reconnect.0 { "on" } else { "off" }
+    )
+}
+
+fn record_text(record: &RecordDemos) -> String {
+    format!("Record demos: {}", if record.0 { "on" } else { "off" })
+}
+
+fn spectate_text(spectate: &SpectateOnJoin) -> String {
+    format!("Join as spectator: {}", if spectate.0 { "on" } else { "off" })
+}
+
+pub fn get_mapfiles<P: AsRef<Path>>(dir: P) -> io::Result<Vec<PathBuf>> {
     let mut files = Vec::new();
 
     let dir = fs::read_dir(dir)?;
@@ -50,17 +123,33 @@ fn get_mapfiles<P: AsRef<Path>>(dir: P) -> io::Result<Vec<PathBuf>> {
 pub fn buttons(world: &mut World) {
     let mut state: SystemState<(
         Query<(&Interaction, &ButtonEvent), (Changed<Interaction>, With<Button>)>,
-        Query<&TextInputValue>,
+        Query<&TextInputValue, With<AddressInput>>,
+        Query<&TextInputValue, With<PasswordInput>>,
         ResMut<NextState<CurrentStage>>,
         ResMut<NextState<NetState>>,
         Option<Res<SteamClient>>,
+        Option<ResMut<ServerBrowser>>,
+        ResMut<AutoReconnect>,
+        ResMut<RecordDemos>,
+        ResMut<SpectateOnJoin>,
     )> = SystemState::new(world);
     // yea this is cursed, but i am lazy, bypassing the borrow checker like a baus
     let world_copy = unsafe { &mut *(world as *mut World) };
 
-    let (query, text_inputs, mut next_state, mut next_net_state, steam_client) =
-        state.get_mut(world);
-    let input = &error_return!(text_inputs.get_single()).0;
+    let (
+        query,
+        address_input,
+        password_input,
+        mut next_state,
+        mut next_net_state,
+        steam_client,
+        mut browser,
+        mut auto_reconnect,
+        mut record_demos,
+        mut spectate_on_join,
+    ) = state.get_mut(world);
+    let input = &error_return!(address_input.get_single()).0;
+    let password = &error_return!(password_input.get_single()).0;
 
     for (interaction, event) in &query {
         if !matches!(interaction, Interaction::Pressed) {
@@ -78,7 +167,48 @@ pub fn buttons(world: &mut World) {
                 }
             }
             ButtonEvent::JoinMp => {
-                net::client::init_client(world_copy, &mut next_net_state, input, &steam_client);
+                net::client::init_client(
+                    world_copy,
+                    &mut next_net_state,
+                    input,
+                    password,
+                    steam_client.as_deref(),
+                );
+            }
+            ButtonEvent::RefreshServers => {
+                if let Some(browser) = &mut browser {
+                    error_continue!(browser.refresh());
+                }
+            }
+            ButtonEvent::JoinServer(addr) => {
+                net::client::init_client(
+                    world_copy,
+                    &mut next_net_state,
+                    &addr.to_string(),
+                    password,
+                    steam_client.as_deref(),
+                );
+            }
+            ButtonEvent::ToggleReconnect => {
+                auto_reconnect.0 = !auto_reconnect.0;
+            }
+            ButtonEvent::ToggleRecordDemos => {
+                record_demos.0 = !record_demos.0;
+            }
+            ButtonEvent::ToggleSpectate => {
+                spectate_on_join.0 = !spectate_on_join.0;
+            }
+            ButtonEvent::PlayDemo(path) => {
+                demo::start_playback(world_copy, path);
+            }
+            ButtonEvent::DismissError => {
+                // Also cancels any pending reconnect
+                world_copy.remove_resource::<Reconnect>();
+                if let Some(ConnectionState::Disconnected { .. }) =
+                    world_copy.get_resource::<ConnectionState>()
+                {
+                    world_copy.remove_resource::<ConnectionState>();
+                }
             }
         }
     }
@@ -89,6 +219,53 @@ pub fn clear(query: Query<(Entity, &MainMenuEnt)>, mut commands: Commands) {
         commands.entity(ent).despawn_recursive();
     }
     commands.insert_resource(AmbientLight::default());
+    commands.remove_resource::<ServerBrowser>();
+}
+
+pub fn update_server_browser(
+    mut commands: Commands,
+    browser: Option<ResMut<ServerBrowser>>,
+    list: Query<Entity, With<ServerList>>,
+) {
+    let mut browser = option_return!(browser);
+    // A refresh empties the list, which has to show even before any server answers
+    let cleared = browser.take_cleared();
+    if !error_return!(browser.poll()) && !cleared {
+        return;
+    }
+
+    let list = error_return!(list.get_single());
+    commands.entity(list).despawn_descendants();
+    commands.entity(list).with_children(|c| {
+        for server in &browser.servers {
+            let text = format!(
+                "{} ({}) {}/{} - {}ms",
+                server.info.name,
+                server.info.map,
+                server.info.players,
+                server.info.max_players,
+                server.ping.as_millis()
+            );
+            if server.is_compatible() {
+                c.spawn(Button)
+                    .insert(Text::new(text))
+                    .insert(TextFont {
+                        font_size: 16.0,
+                        ..default()
+                    })
+                    .insert(ButtonEvent::JoinServer(server.addr));
+            } else {
+                c.spawn((
+                    Text::new(format!("{text} [incompatible]")),
+                    TextFont {
+                        font_size: 16.0,
+                        ..default()
+                    },
+                    TextColor(Color::srgb(0.5, 0.5, 0.5)),
+                ));
+            }
+        }
+    });
 }
 
 #[allow(clippy::type_complexity)]
@@ -107,7 +284,7 @@ pub fn update_level_buttons(
 #[allow(clippy::type_complexity)]
 pub fn update_id_buttons(
     query: Query<(&Interaction, &FriendButton), (Changed<Interaction>, With<Button>)>,
-    mut text_input: Query<&mut TextInputValue>,
+    mut text_input: Query<&mut TextInputValue, With<AddressInput>>,
 ) {
     for (interaction, button) in &query {
         if matches!(interaction, Interaction::Pressed) {
@@ -118,6 +295,51 @@ pub fn update_id_buttons(
     }
 }
 
+#[allow(clippy::type_complexity)]
+pub fn update_error_dialog(
+    connection: Option<Res<ConnectionState>>,
+    reconnect: Option<Res<Reconnect>>,
+    auto_reconnect: Res<AutoReconnect>,
+    mut dialog: Query<&mut Visibility, With<ErrorDialog>>,
+    mut error: Query<&mut Text, (With<ErrorText>, Without<ReconnectToggle>)>,
+    mut toggle: Query<&mut Text, (With<ReconnectToggle>, Without<ErrorText>)>,
+) {
+    if auto_reconnect.is_changed() {
+        for mut text in &mut toggle {
+            text.0 = reconnect_text(&auto_reconnect);
+        }
+    }
+
+    let text = error_text(connection.as_deref(), reconnect.as_deref());
+    for mut visibility in &mut dialog {
+        *visibility = match text {
+            Some(_) => Visibility::Inherited,
+            None => Visibility::Hidden,
+        };
+    }
+    for mut error in &mut error {
+        error.0 = text.clone().unwrap_or_default();
+    }
+}
+
+pub fn update_toggles(
+    record_demos: Res<RecordDemos>,
+    spectate_on_join: Res<SpectateOnJoin>,
+    mut record: Query<&mut Text, (With<RecordToggle>, Without<SpectateToggle>)>,
+    mut spectate: Query<&mut Text, (With<SpectateToggle>, Without<RecordToggle>)>,
+) {
+    if record_demos.is_changed() {
+        for mut text in &mut record {
+            text.0 = record_text(&record_demos);
+        }
+    }
+    if spectate_on_join.is_changed() {
+        for mut text in &mut spectate {
+            text.0 = spectate_text(&spectate_on_join);
+        }
+    }
+}
+
 pub fn update_point_light(mut query: Query<&mut PointLight>) {
     for mut light in query.iter_mut() {
         light.intensity += 0.1;
@@ -129,6 +351,9 @@ pub fn update_point_light(mut query: Query<&mut PointLight>) {
 pub fn setup(
     mut commands: Commands,
     steam_client: Option<Res<SteamClient>>,
+    auto_reconnect: Res<AutoReconnect>,
+    record_demos: Res<RecordDemos>,
+    spectate_on_join: Res<SpectateOnJoin>,
 
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
@@ -141,6 +366,7 @@ pub fn setup(
     });
 
     let map_files = error_return!(get_mapfiles("assets/maps"));
+    let demos = demo::get_demos();
     let friends = steam_client
         .as_ref()
         .map(|sc| sc.friends().get_friends(FriendFlags::ALL))
@@ -156,6 +382,18 @@ pub fn setup(
         })
         .unwrap_or_default();
 
+    // LAN servers can only be joined without Steam
+    let has_browser = steam_client.is_none();
+    if has_browser {
+        match ServerBrowser::new() {
+            Ok(mut browser) => {
+                error_return!(browser.refresh());
+                commands.insert_resource(browser);
+            }
+            Err(e) => error!("failed to start server browser: {e}"),
+        }
+    }
+
     commands
         .spawn(Camera2d)
         .insert(Camera {
@@ -249,6 +487,26 @@ pub fn setup(
                         font_size: 32.0,
                         ..default()
                     }),
+                    AddressInput,
+                ));
+
+                c.spawn(Node::default()).insert((
+                    TextInput,
+                    TextInputValue::default(),
+                    TextInputPlaceholder {
+                        value: "Password".to_string(),
+                        ..default()
+                    },
+                    TextInputSettings {
+                        retain_on_submit: true,
+                        mask_character: Some('*'),
+                        ..default()
+                    },
+                    TextInputTextFont(TextFont {
+                        font_size: 32.0,
+                        ..default()
+                    }),
+                    PasswordInput,
                 ));
 
                 c.spawn(Button)
@@ -257,8 +515,98 @@ pub fn setup(
                         ..default()
                     }))
                     .insert(ButtonEvent::JoinMp);
+
+                c.spawn(Button)
+                    .insert((Text::new(reconnect_text(&auto_reconnect)), TextFont {
+                        font_size: 16.0,
+                        ..default()
+                    }))
+                    .insert(ButtonEvent::ToggleReconnect)
+                    .insert(ReconnectToggle);
+
+                c.spawn(Button)
+                    .insert((Text::new(record_text(&record_demos)), TextFont {
+                        font_size: 16.0,
+                        ..default()
+                    }))
+                    .insert(ButtonEvent::ToggleRecordDemos)
+                    .insert(RecordToggle);
+
+                c.spawn(Button)
+                    .insert((Text::new(spectate_text(&spectate_on_join)), TextFont {
+                        font_size: 16.0,
+                        ..default()
+                    }))
+                    .insert(ButtonEvent::ToggleSpectate)
+                    .insert(SpectateToggle);
             });
 
+            c.spawn((
+                Node {
+                    position_type: PositionType::Absolute,
+                    left: Val::Vw(30.0),
+                    top: Val::Vh(30.0),
+                    width: Val::Vw(40.0),
+                    padding: UiRect::all(Val::Px(16.0)),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                BackgroundColor(Color::srgba(0.1, 0.0, 0.0, 0.9)),
+                Visibility::Hidden,
+                ErrorDialog,
+            ))
+            .with_children(|c| {
+                c.spawn((
+                    Text::default(),
+                    TextFont {
+                        font_size: 24.0,
+                        ..default()
+                    },
+                    TextColor(Color::srgb(1.0, 0.3, 0.3)),
+                    ErrorText,
+                ));
+
+                c.spawn(Button)
+                    .insert((Text::new("OK"), TextFont {
+                        font_size: 32.0,
+                        ..default()
+                    }))
+                    .insert(ButtonEvent::DismissError);
+            });
+
+            if has_browser {
+                c.spawn(Node {
+                    position_type: PositionType::Absolute,
+                    width: Val::Px(400.0),
+                    border: UiRect::all(Val::Px(2.0)),
+                    height: Val::Vh(100.0),
+                    left: Val::Px(0.0),
+                    flex_direction: FlexDirection::Column,
+                    ..default()
+                })
+                .with_children(|c| {
+                    c.spawn((Text::new("Servers:".to_string()), TextFont {
+                        font_size: 32.0,
+                        ..default()
+                    }));
+
+                    c.spawn(Button)
+                        .insert(Text::new("Refresh"))
+                        .insert(TextFont {
+                            font_size: 16.0,
+                            ..default()
+                        })
+                        .insert(ButtonEvent::RefreshServers);
+
+                    c.spawn(Node {
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    })
+                    .insert(ServerList);
+                });
+            }
+
             c.spawn(Node {
                 position_type: PositionType::Absolute,
                 right: Val::Px(0.0),
@@ -335,6 +683,24 @@ pub fn setup(
                     })
                     .insert(FriendButton(friend.id().raw()));
                 }
+
+                c.spawn((Text::new("Demos:".to_string()), TextFont {
+                    font_size: 32.0,
+                    ..default()
+                }));
+                for demo in demos {
+                    let name = demo
+                        .file_stem()
+                        .map(|n| n.to_string_lossy().to_string())
+                        .unwrap_or_default();
+                    c.spawn(Button)
+                        .insert(Text::new(name))
+                        .insert(TextFont {
+                            font_size: 16.0,
+                            ..default()
+                        })
+                        .insert(ButtonEvent::PlayDemo(demo));
+                }
             });
         })
         .insert(MainMenuEnt);
@@ -1,8 +1,15 @@
-use crate::{entities::pickup::PickupEntity, map_gen::SCALE_FIX};
+use crate::{
+    entities::pickup::PickupEntity,
+    game_mode::{EndCamera, ctf::Flag},
+    map_gen::{MapEnt, SCALE_FIX},
+};
 use bevy::{
     asset::{AssetServer, Assets},
     color::Color,
-    ecs::system::{Commands, Res, ResMut},
+    ecs::{
+        entity::Entity,
+        system::{Commands, Res, ResMut},
+    },
     log::error,
     math::{EulerRot, Quat, Vec3},
     pbr::{DirectionalLight, MeshMaterial3d, PointLight, StandardMaterial},
@@ -14,7 +21,7 @@ use bevy_rapier3d::{
     geometry::{ActiveCollisionTypes, ActiveEvents, Collider, Sensor},
 };
 use faststr::FastStr;
-use resources::{PickupMap, PlayerSpawnpoint, data::PickupData};
+use resources::{PickupMap, PlayerSpawnpoints, SpawnKind, SpawnPoint, data::PickupData};
 use std::collections::HashMap;
 
 use super::Interactable;
@@ -40,6 +47,28 @@ fn parse_vec(str: &str) -> Vec3 {
     Vec3::new(x, z, -y) / SCALE_FIX
 }
 
+/// Reads `mangle` ("pitch yaw roll") or `angle` (just the yaw) as a bevy yaw and pitch in radians
+fn parse_angles(attributes: &HashMap<FastStr, FastStr>) -> (f32, f32) {
+    let (pitch, yaw) = if let Some(mangle) = attributes.get(&FastStr::from("mangle")) {
+        let mut splat = mangle
+            .split_whitespace()
+            .map(|s| s.parse::<f32>().unwrap_or_default());
+        (
+            splat.next().unwrap_or_default(),
+            splat.next().unwrap_or_default(),
+        )
+    } else {
+        let angle = attributes
+            .get(&FastStr::from("angle"))
+            .and_then(|a| a.parse::<f32>().ok())
+            .unwrap_or_default();
+        (0.0, angle)
+    };
+
+    // A yaw of 0 looks along the map's x axis, and a positive pitch looks down
+    ((yaw - 90.0).to_radians(), -pitch.to_radians())
+}
+
 #[allow(clippy::too_many_arguments)]
 pub fn spawn_entity(
     id: u64,
@@ -47,7 +76,7 @@ pub fn spawn_entity(
     asset_server: &Res<AssetServer>,
     attributes: HashMap<FastStr, FastStr>,
     commands: &mut Commands,
-    player_spawn: &mut ResMut<PlayerSpawnpoint>,
+    player_spawns: &mut ResMut<PlayerSpawnpoints>,
     pickup_map: &PickupMap,
     materials: &mut ResMut<Assets<StandardMaterial>>,
 ) -> Option<Interactable> {
@@ -63,6 +92,8 @@ pub fn spawn_entity(
                 });
             }
         }
+        // The brushes are turned into `Ladder`s in `load_map`
+        Some("func_ladder") => {}
         Some("light") => {
             let light_level = attributes
                 .get(&FastStr::from("light"))
@@ -82,6 +113,7 @@ pub fn spawn_entity(
                     ..Default::default()
                 },
                 Transform::from_translation(pos),
+                MapEnt,
             ));
         }
         Some("directional_light") => {
@@ -100,9 +132,15 @@ pub fn spawn_entity(
                     ..Default::default()
                 },
                 trans,
+                MapEnt,
             ));
         }
-        Some("info_player_start") => {
+        Some(
+            class @ ("info_player_start"
+            | "info_player_deathmatch"
+            | "info_player_team1"
+            | "info_player_team2"),
+        ) => {
             let mut pos = attributes
                 .get(&FastStr::from("origin"))
                 .map(|p| parse_vec(p))
@@ -110,7 +148,47 @@ pub fn spawn_entity(
 
             pos.y += 0.5;
 
-            player_spawn.0 = pos;
+            let kind = match class {
+                "info_player_deathmatch" => SpawnKind::Deathmatch,
+                "info_player_team1" => SpawnKind::Team(0),
+                "info_player_team2" => SpawnKind::Team(1),
+                _ => SpawnKind::Start,
+            };
+            let (yaw, _) = parse_angles(&attributes);
+            player_spawns.0.push(SpawnPoint {
+                position: pos,
+                yaw,
+                kind,
+            });
+        }
+        Some("info_end_camera") => {
+            let position = attributes
+                .get(&FastStr::from("origin"))
+                .map(|p| parse_vec(p))
+                .unwrap_or_default();
+            let (yaw, pitch) = parse_angles(&attributes);
+
+            commands.spawn((
+                EndCamera {
+                    position,
+                    yaw,
+                    pitch,
+                },
+                MapEnt,
+            ));
+        }
+        Some(class @ ("item_flag_team1" | "item_flag_team2")) => {
+            let base = attributes
+                .get(&FastStr::from("origin"))
+                .map(|p| parse_vec(p))
+                .unwrap_or_default();
+            let team = if class == "item_flag_team1" { 0 } else { 1 };
+
+            commands.spawn((
+                Flag { team, base },
+                Transform::from_translation(base),
+                MapEnt,
+            ));
         }
         Some(x) if pickup_map.0.contains_key(&FastStr::from(x)) && !is_client => {
             let data = pickup_map.0.get(&FastStr::from(x)).unwrap();
@@ -120,22 +198,24 @@ pub fn spawn_entity(
                 .map(|p| parse_vec(p))
                 .unwrap_or_default();
 
-            spawn_pickup(id, true, pos, asset_server, data, commands, materials);
+            spawn_pickup(id, true, true, pos, asset_server, data, commands, materials);
         }
         _ => error!("unhandled entity: {attributes:?}"),
     };
     None
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn spawn_pickup(
     id: u64,
     host: bool,
+    available: bool,
     pos: Vec3,
     asset_server: &Res<AssetServer>,
     data: &PickupData,
     commands: &mut Commands,
     materials: &mut ResMut<Assets<StandardMaterial>>,
-) {
+) -> Entity {
     let PickupData {
         pickup_model,
         texture_file,
@@ -172,5 +252,7 @@ pub fn spawn_pickup(
     };
     pickup
         .insert(trans)
-        .insert(PickupEntity::new(id, data.clone()));
+        .insert(PickupEntity::visibility(available))
+        .insert(PickupEntity::new(id, data.clone(), available))
+        .id()
 }
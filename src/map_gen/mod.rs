@@ -3,6 +3,20 @@ use self::{
     poly::Poly,
     vertex::Vertex,
 };
+use crate::{
+    chat::{ChatHistory, ChatState},
+    entities::{
+        ProjectileEntity,
+        pickup::{PickupEntity, PickupIndex},
+        projectiles::ProjectileIndex,
+    },
+    net::{
+        Lobby,
+        demo::{DemoHud, DemoPlayback},
+    },
+    particles::ParticleLifetime,
+    player::{Player, PlayerHud},
+};
 use bevy::{
     prelude::*,
     render::{
@@ -10,13 +24,18 @@ use bevy::{
         render_asset::RenderAssetUsages,
         render_resource::{PrimitiveTopology, encase::rts_array::Length},
     },
+    window::{CursorGrabMode, PrimaryWindow},
 };
-use bevy_rapier3d::geometry::Collider;
+use bevy_rapier3d::geometry::{Collider, Sensor};
 use bevy_renet::renet::RenetClient;
 use entities::spawn_entity;
+use faststr::FastStr;
 use macros::error_return;
 use map_parser::parser::Brush;
-use resources::{CurrentMap, MapDoneLoading, PickupMap, PlayerSpawnpoint, TextureMap};
+use resources::{
+    CurrentMap, MapDoneLoading, Paused, PickupMap, PlayerSpawnpoints, TextureLoadingState,
+    TextureMap, TexturesLoading,
+};
 
 pub mod entities;
 mod interactable;
@@ -36,9 +55,18 @@ fn vec_fix(mut v: Vec3) -> Vec3 {
     v
 }
 
+/// Marks entities which are part of the loaded map
+#[derive(Debug, Component)]
+pub struct MapEnt;
+
+/// The brushes of `func_ladder` entities, invisible volumes players climb while they're inside
+#[derive(Debug, Component)]
+pub struct Ladder;
+
 #[allow(clippy::too_many_arguments)]
 pub fn load_map(
     client: Option<Res<RenetClient>>,
+    demo: Option<Res<DemoPlayback>>,
     asset_server: Res<AssetServer>,
     mut commands: Commands,
     images: Res<Assets<Image>>,
@@ -48,22 +76,28 @@ pub fn load_map(
     pickup_map: Res<PickupMap>,
     texture_map: Res<TextureMap>,
     mut done_loading: ResMut<MapDoneLoading>,
-    mut player_spawn: ResMut<PlayerSpawnpoint>,
+    mut player_spawns: ResMut<PlayerSpawnpoints>,
 ) {
     let map = error_return!(std::fs::read_to_string(&current_map.0));
     let map = error_return!(map_parser::parse(&map));
 
     let t = std::time::Instant::now();
     info!("Loading map...");
+    player_spawns.0.clear();
 
     for (id, entity) in map.into_iter().enumerate() {
+        let ladder = entity
+            .attributes
+            .get(&FastStr::from("classname"))
+            .is_some_and(|class| &class[..] == "func_ladder");
         let interactable = spawn_entity(
             id as u64,
-            client.is_some(),
+            // Pickups come from the server, or from the demo
+            client.is_some() || demo.is_some(),
             &asset_server,
             entity.attributes,
             &mut commands,
-            &mut player_spawn,
+            &mut player_spawns,
             &pickup_map,
             &mut materials,
         );
@@ -132,7 +166,7 @@ pub fn load_map(
                         ..default()
                     }
                 };
-                if new_mesh.count_vertices() != 0 {
+                if new_mesh.count_vertices() != 0 && !ladder {
                     new_mesh.duplicate_vertices();
                     new_mesh.compute_flat_normals();
 
@@ -140,6 +174,7 @@ pub fn load_map(
                         Mesh3d(meshes.add(new_mesh)),
                         MeshMaterial3d(materials.add(mat)),
                         Transform::default(),
+                        MapEnt,
                     ));
                 }
             }
@@ -154,7 +189,10 @@ pub fn load_map(
                 // brush_poly.dedup();
 
                 if let Some(col) = Collider::convex_hull(&brush_poly) {
-                    let mut com = commands.spawn(col);
+                    let mut com = commands.spawn((col, MapEnt));
+                    if ladder {
+                        com.insert((Ladder, Sensor));
+                    }
                     if let Some(interactable) = &interactable {
                         com.insert((*interactable).clone());
                     }
@@ -169,6 +207,93 @@ pub fn load_map(
     done_loading.0 = true;
 }
 
+/// Inserted to swap the loaded map for `CurrentMap` without leaving the game
+#[derive(Debug, Resource)]
+pub struct ReloadMap;
+
+/// Despawns the current map so that `CurrentMap` gets loaded in its place.
+/// Unlike [unload_map] the players, the lobby and the chat are kept.
+#[allow(clippy::type_complexity)]
+pub fn reload_map(
+    mut commands: Commands,
+    entities: Query<
+        Entity,
+        Or<(
+            With<MapEnt>,
+            With<PickupEntity>,
+            With<ProjectileEntity>,
+            With<ParticleLifetime>,
+        )>,
+    >,
+    mut loading_state: ResMut<TextureLoadingState>,
+    mut textures_loading: ResMut<TexturesLoading>,
+    mut done_loading: ResMut<MapDoneLoading>,
+    mut pickup_index: ResMut<PickupIndex>,
+    mut projectile_index: ResMut<ProjectileIndex>,
+) {
+    info!("Changing map...");
+    for ent in &entities {
+        commands.entity(ent).despawn_recursive();
+    }
+    pickup_index.0.clear();
+    projectile_index.0.clear();
+    *loading_state = TextureLoadingState::NotLoaded;
+    textures_loading.0.clear();
+    done_loading.0 = false;
+    commands.remove_resource::<ReloadMap>();
+}
+
+/// Despawns everything belonging to the current game when leaving it,
+/// so that a map can be loaded again from scratch.
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+pub fn unload_map(
+    mut commands: Commands,
+    entities: Query<
+        Entity,
+        Or<(
+            With<MapEnt>,
+            With<Player>,
+            With<PlayerHud>,
+            With<PickupEntity>,
+            With<ProjectileEntity>,
+            With<ParticleLifetime>,
+            With<DemoHud>,
+        )>,
+    >,
+    lobby: Option<ResMut<Lobby>>,
+    mut loading_state: ResMut<TextureLoadingState>,
+    mut textures_loading: ResMut<TexturesLoading>,
+    mut done_loading: ResMut<MapDoneLoading>,
+    mut paused: ResMut<Paused>,
+    mut chat: ResMut<ChatState>,
+    mut chat_history: ResMut<ChatHistory>,
+    mut windows: Query<&mut Window, With<PrimaryWindow>>,
+    mut pickup_index: ResMut<PickupIndex>,
+    mut projectile_index: ResMut<ProjectileIndex>,
+) {
+    info!("Unloading map...");
+    for ent in &entities {
+        commands.entity(ent).despawn_recursive();
+    }
+    pickup_index.0.clear();
+    projectile_index.0.clear();
+    if let Some(mut lobby) = lobby {
+        lobby.clear();
+    }
+
+    *loading_state = TextureLoadingState::NotLoaded;
+    textures_loading.0.clear();
+    done_loading.0 = false;
+    *chat = ChatState::default();
+    chat_history.clear();
+
+    paused.0 = true;
+    if let Ok(mut window) = windows.get_single_mut() {
+        window.cursor_options.grab_mode = CursorGrabMode::None;
+        window.cursor_options.visible = true;
+    }
+}
+
 fn sort_verticies_cw(polys: Vec<Poly>) -> Vec<Poly> {
     let mut poly_center = Vec3::ZERO;
     let mut total = 0;
@@ -1,51 +1,87 @@
 use super::{
-    connection_config, update_world, CurrentClientId, IsSteam, NetState, ServerChannel,
-    ServerMessage, SteamClient, PROTOCOL_ID,
+    ClientChannel, ClientMessage, CurrentClientId, NetState, PROTOCOL_ID, ServerChannel,
+    ServerMessage, SteamClient, connection_config,
+    demo::{DemoRecorder, RecordDemos},
+    handshake::{AssetHash, Handshake},
+    stats::NetStats,
+    update_world,
 };
 use crate::{
-    entities::{hitscan_hit_gfx, pickup::PickupEntity},
-    map_gen,
+    chat::ChatLine,
+    entities::{
+        ProjectileEntity, hitscan_hit_gfx,
+        pickup::{PickupEntity, PickupIndex},
+        projectiles::{self, ProjectileIndex},
+    },
+    game_mode::{MatchPhase, MatchState},
+    map_gen::{self, ReloadMap},
     net::{Lobby, PlayerInfo},
     player::Player,
     queries::NetWorld,
 };
 use bevy::{
     ecs::{
-        entity::Entity,
         event::EventReader,
         schedule::{common_conditions::resource_exists, IntoSystemConfigs, SystemConfigs},
-        system::{Query, Res, ResMut},
-        world::World,
+        system::{Commands, Res, ResMut, Resource, SystemParam},
+        world::{Mut, World},
     },
     hierarchy::DespawnRecursiveExt,
     log::{error, info},
-    prelude::NextState,
+    math::{Quat, Vec3},
+    prelude::{NextState, State},
+    transform::components::Transform,
 };
 use bevy_renet::{
     netcode::{ClientAuthentication, NetcodeClientTransport, NetcodeTransportError},
     renet::RenetClient,
     steam::SteamTransportError,
 };
-use macros::{error_continue, error_return, option_continue};
+use macros::{error_continue, error_return, option_continue, option_return};
 use renet_steam::SteamClientTransport;
-use resources::{CurrentMap, CurrentStage};
-use std::{net::UdpSocket, time::SystemTime};
+use resources::{CurrentMap, CurrentStage, MapDoneLoading};
+use std::{
+    net::UdpSocket,
+    time::{Duration, Instant, SystemTime},
+};
 use steamworks::SteamId;
 
-pub fn handle_messages(
-    pickups: Query<(Entity, &PickupEntity)>,
-    mut client: ResMut<RenetClient>,
-    mut current_stage: ResMut<CurrentMap>,
-    mut state: ResMut<NextState<CurrentStage>>,
-    mut nw: NetWorld,
-) {
-    while let Some(message) = client.receive_message(ServerChannel::ServerMessages as u8) {
-        let message = error_continue!(ServerMessage::from_bytes(&message));
+/// Applies messages from the server to the world.
+/// Shared by the network client and demo playback.
+#[derive(SystemParam)]
+pub struct MessageHandler<'w, 's> {
+    pub pickup_index: ResMut<'w, PickupIndex>,
+    pub projectile_index: ResMut<'w, ProjectileIndex>,
+    current_map: ResMut<'w, CurrentMap>,
+    stage: Res<'w, State<CurrentStage>>,
+    state: ResMut<'w, NextState<CurrentStage>>,
+    done_loading: Res<'w, MapDoneLoading>,
+    /// None while playing a demo
+    connection: Option<ResMut<'w, ConnectionState>>,
+    pub nw: NetWorld<'w, 's>,
+}
+impl MessageHandler<'_, '_> {
+    fn show_pickup(&mut self, id: u64, available: bool) {
+        let entity = *option_return!(self.pickup_index.0.get(&id));
+        // The pickup may be gone along with the map it was on
+        let mut pickup = option_return!(self.nw.commands.get_entity(entity));
+        pickup.try_insert(PickupEntity::visibility(available));
+    }
+
+    /// `own_id` is the player that messages meant only for this client apply to,
+    /// which is not the current player when playing a demo.
+    pub fn handle(&mut self, message: ServerMessage, own_id: u64) {
+        let nw = &mut self.nw;
         match message {
             ServerMessage::SetMap(map) => {
                 info!("setting map to: {map:?}");
-                current_stage.0 = map;
-                state.set(CurrentStage::InGame);
+                self.current_map.0 = map;
+                if *self.stage.get() == CurrentStage::InGame {
+                    // The server changed maps, the players stay
+                    nw.commands.insert_resource(ReloadMap);
+                } else {
+                    self.state.set(CurrentStage::InGame);
+                }
             }
             ServerMessage::SpawnPlayer {
                 id,
@@ -54,85 +90,220 @@ pub fn handle_messages(
                 name,
             } => {
                 if id != nw.current_id.0 {
-                    let entity = Player::spawn(&mut nw, false, translation, id, weapons, None);
+                    let entity = Player::spawn(
+                        nw,
+                        false,
+                        Transform::from_translation(translation),
+                        id,
+                        weapons,
+                        None,
+                    );
                     nw.lobby.insert(id, PlayerInfo::new(entity, name));
                 }
             }
             ServerMessage::DespawnPlayer { id } => {
-                let player = option_continue!(nw.lobby.get(&id)).entity;
+                let player = option_return!(nw.lobby.get(&id)).entity;
                 nw.commands.entity(player).despawn_recursive();
                 nw.lobby.remove(&id);
             }
-            ServerMessage::Reset => {
-                let player = option_continue!(nw.lobby.get(&nw.current_id.0)).entity;
-                let (_, mut player, mut trans) = error_continue!(nw.players.get_mut(player));
-                player.health = 100.0;
-                player.armour = 0.0;
-                player.last_hurter = 0;
-                trans.translation = nw.player_spawn.0;
+            ServerMessage::Reset { position, yaw } => {
+                // Applied once the map is there, after joining or changing maps
+                let info = nw.lobby.get(&own_id).filter(|_| self.done_loading.0);
+                let Some(info) = info else {
+                    nw.commands.insert_resource(JoinSpawn { position, yaw });
+                    return;
+                };
+                let (_, mut player, mut trans) = error_return!(nw.players.get_mut(info.entity));
+                player.reset(&nw.ammo_map);
+                trans.translation = position;
+                trans.rotation = Quat::from_rotation_y(yaw);
             }
             ServerMessage::SpawnPickup {
                 id,
                 translation,
                 data,
+                available,
             } => {
-                map_gen::entities::spawn_pickup(
+                let entity = map_gen::entities::spawn_pickup(
                     id,
                     false,
+                    available,
                     translation,
                     &nw.asset_server,
                     &data,
                     &mut nw.commands,
                     &mut nw.materials,
                 );
+                self.pickup_index.0.insert(id, entity);
             }
             ServerMessage::Message { text } => {
-                let player = option_continue!(nw.lobby.get(&nw.current_id.0)).entity;
-                let (_, player, _) = error_continue!(nw.players.get(player));
+                let player = option_return!(nw.lobby.get(&nw.current_id.0)).entity;
+                let (_, player, _) = error_return!(nw.players.get(player));
                 player.display_message(&mut nw.commands, &nw.asset_server, text);
             }
-            ServerMessage::KillStat { death, hurter } => {
-                if let Some(info) = nw.lobby.get_mut(&death) {
+            ServerMessage::Disconnect { reason } => match &mut self.connection {
+                Some(connection) => {
+                    info!("disconnected by server: {reason}");
+                    **connection = ConnectionState::Disconnected {
+                        reason,
+                        retry: false,
+                    };
+                }
+                None => info!("recording client was disconnected: {reason}"),
+            },
+            ServerMessage::Chat { name, text, team } => {
+                nw.chat.push(ChatLine::new(name, text, team));
+            }
+            ServerMessage::PlayerRenamed { id, name } => {
+                let info = option_return!(nw.lobby.get_mut(&id));
+                info.name = name;
+            }
+            ServerMessage::SetSpectating { id, spectating } => {
+                // Our own player is not spawned until the map is loaded, it uses `SpectateOnJoin`
+                let info = option_return!(nw.lobby.get_mut(&id));
+                info.spectating = spectating;
+                Player::set_spectating(&mut nw.commands, info.entity, spectating);
+            }
+            ServerMessage::Movement(movement) => *nw.movement = movement,
+            ServerMessage::MatchState(state) => {
+                // The server resets the scores when a match starts
+                let started = nw.match_state.phase != MatchPhase::Playing;
+                if started && state.phase == MatchPhase::Playing {
+                    for info in nw.lobby.values_mut() {
+                        info.kills = 0;
+                        info.deaths = 0;
+                        info.score = 0;
+                    }
+                }
+                *nw.match_state = state;
+            }
+            ServerMessage::SetTeam { id, team } => {
+                let info = option_return!(nw.lobby.get_mut(&id));
+                info.team = team;
+            }
+            ServerMessage::SetAmmo { ammo, amount } => {
+                let player = option_return!(nw.lobby.get(&own_id)).entity;
+                let (_, mut player, _) = error_return!(nw.players.get_mut(player));
+                player.ammo.insert(ammo, amount);
+            }
+            ServerMessage::SetMagazine { weapon, loaded } => {
+                let player = option_return!(nw.lobby.get(&own_id)).entity;
+                let (_, mut player, _) = error_return!(nw.players.get_mut(player));
+                let mut weapons = player.weapons.iter_mut().flatten();
+                let weapon = option_return!(weapons.find(|w| w.data.id == weapon));
+                weapon.loaded = loaded;
+            }
+            ServerMessage::Scoreboard(rows) => {
+                for row in rows {
+                    let info = option_continue!(nw.lobby.get_mut(&row.id));
+                    info.score = row.score;
+                    info.kills = row.kills;
+                    info.deaths = row.deaths;
+                    info.ping = row.ping;
+                }
+            }
+            ServerMessage::Frag(frag) => {
+                if let Some(info) = nw.lobby.get_mut(&frag.victim) {
                     info.deaths += 1;
                 }
-                if let Some(hurter) = hurter
-                    && let Some(info) = nw.lobby.get_mut(&hurter)
+                if let Some(attacker) = frag.attacker
+                    && let Some(info) = nw.lobby.get_mut(&attacker)
                 {
                     info.kills += 1;
                 }
+                nw.frags.send(frag);
             }
-            x => {
-                error!("unhandled ServerMessages message: {x:?}")
-            }
-        }
-    }
-
-    while let Some(message) = client.receive_message(ServerChannel::NetworkedEntities as u8) {
-        let message = error_continue!(ServerMessage::from_bytes(&message));
-        #[allow(clippy::single_match)]
-        match message {
             ServerMessage::PlayerUpdate { id, message } => {
-                update_world(id, &message, &mut nw);
-            }
-            ServerMessage::DespawnPickup { id } => {
-                // TODO: Improve this
-                for (ent, pickup) in &pickups {
-                    if pickup.id == id {
-                        nw.commands.entity(ent).despawn_recursive();
-                    }
-                }
+                update_world(id, &message, nw);
             }
+            ServerMessage::HidePickup { id } => self.show_pickup(id, false),
+            ServerMessage::RespawnPickup { id } => self.show_pickup(id, true),
             ServerMessage::HitscanHits { hits } => {
                 hitscan_hit_gfx(&nw.asset_server, &mut nw.commands, &hits, &nw.particles)
             }
-            ServerMessage::Hit { amount } => {
-                let player = option_continue!(nw.lobby.get(&nw.current_id.0)).entity;
-                let (_, mut player, _) = error_continue!(nw.players.get_mut(player));
-                player.health -= amount;
+            ServerMessage::SetHealth { health, armour } => {
+                let player = option_return!(nw.lobby.get(&own_id)).entity;
+                let (_, mut player, _) = error_return!(nw.players.get_mut(player));
+                player.health = health;
+                player.armour = armour;
+            }
+            ServerMessage::SetPowerup { powerup, time } => {
+                let player = option_return!(nw.lobby.get(&own_id)).entity;
+                let (_, mut player, _) = error_return!(nw.players.get_mut(player));
+                player.powerups.insert(powerup, time);
+            }
+            ServerMessage::SpawnProjectile {
+                id,
+                projectile,
+                owner,
+                weapon,
+                translation,
+                dir,
+            } => {
+                let data = option_return!(nw.projectile_map.0.get(&projectile));
+                let entity = ProjectileEntity::spawn(
+                    &mut nw.commands,
+                    &mut nw.materials,
+                    &nw.asset_server,
+                    data,
+                    Some(id),
+                    owner,
+                    weapon,
+                    0.0,
+                    translation,
+                    dir,
+                );
+                self.projectile_index.0.insert(id, entity);
+            }
+            ServerMessage::ExplodeProjectile {
+                id,
+                projectile,
+                translation,
+            } => {
+                // Gone already if the client removed it for flying too long
+                let entity = self.projectile_index.0.remove(&id);
+                if let Some(projectile) = entity.and_then(|e| nw.commands.get_entity(e)) {
+                    projectile.despawn_recursive();
+                }
+                let data = option_return!(nw.projectile_map.0.get(&projectile));
+                projectiles::spawn_explosion(
+                    &mut nw.commands,
+                    &nw.asset_server,
+                    &nw.particles,
+                    data,
+                    translation,
+                );
+            }
+            ServerMessage::Push { velocity } => {
+                let player = option_return!(nw.lobby.get(&own_id)).entity;
+                let (_, mut player, _) = error_return!(nw.players.get_mut(player));
+                player.push(velocity);
             }
-            x => {
-                error!("unhandled NetworkedEntities message: {x:?}")
+        }
+    }
+}
+
+pub fn handle_messages(
+    mut client: ResMut<RenetClient>,
+    mut recorder: Option<ResMut<DemoRecorder>>,
+    mut stats: ResMut<NetStats>,
+    mut handler: MessageHandler,
+) {
+    for channel in [
+        ServerChannel::ServerMessages as u8,
+        ServerChannel::NetworkedEntities as u8,
+    ] {
+        while let Some(message) = client.receive_message(channel) {
+            let message_len = message.len();
+            if let Some(recorder) = &mut recorder
+                && let Err(e) = recorder.record(channel, &message)
+            {
+                error!("failed to record demo: {e}");
             }
+            let message = error_continue!(ServerMessage::from_bytes(&message));
+            stats.received(channel, &message, message_len);
+            let own_id = handler.nw.current_id.0;
+            handler.handle(message, own_id);
         }
     }
 }
@@ -141,7 +312,8 @@ pub fn init_client(
     world: &mut World,
     next_state: &mut NextState<NetState>,
     ip: &String,
-    steam_client: &Option<Res<SteamClient>>,
+    password: &str,
+    steam_client: Option<&SteamClient>,
 ) -> bool {
     info!("joining: {ip}");
     let client = RenetClient::new(connection_config());
@@ -159,7 +331,7 @@ pub fn init_client(
         let current_time = error_return!(SystemTime::now().duration_since(SystemTime::UNIX_EPOCH));
 
         let server_addr = error_return!(ip.parse());
-        let socket = error_return!(UdpSocket::bind("127.0.0.1:0"));
+        let socket = error_return!(UdpSocket::bind("0.0.0.0:0"));
 
         let client_id = current_time.as_micros() as u64;
 
@@ -179,35 +351,249 @@ pub fn init_client(
         world.insert_resource(transport);
         world.insert_resource(CurrentClientId(client_id));
     }
+    if world.resource::<RecordDemos>().0 {
+        let client_id = world.resource::<CurrentClientId>().0;
+        match DemoRecorder::create(client_id) {
+            Ok(recorder) => world.insert_resource(recorder),
+            Err(e) => error!("failed to start recording demo: {e}"),
+        }
+    }
+    let handshake = Handshake::new(
+        world.resource::<AssetHash>(),
+        password,
+        world.resource::<SpectateOnJoin>().0,
+    );
+    world.insert_resource(handshake);
+    world.insert_resource(ServerAddress {
+        address: ip.clone(),
+        password: password.to_string(),
+    });
+    world.resource_mut::<NetStats>().reset();
+    world.insert_resource(ConnectionState::Connecting);
     world.insert_resource(client);
     world.insert_resource(Lobby::default());
+    world.insert_resource(MatchState::default());
+    world.remove_resource::<JoinSpawn>();
     next_state.set(NetState::Client);
     info!("started client");
     true
 }
 
-pub fn systems() -> SystemConfigs {
-    (handle_messages,).into_configs()
+/// How many times to try reconnecting before giving up
+const MAX_RECONNECT_ATTEMPTS: u32 = 5;
+/// Delay before the first reconnect attempt, doubled after each failed attempt
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// The state of the connection to the server.
+/// Kept after disconnecting, so that the main menu can show what went wrong.
+#[derive(Debug, Resource, Clone, PartialEq)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    /// `retry` is false if the server told us why it disconnected us,
+    /// in which case reconnecting would not help.
+    Disconnected {
+        reason: String,
+        retry: bool,
+    },
+}
+
+/// The server the client last tried to join
+#[derive(Debug, Resource, Clone)]
+pub struct ServerAddress {
+    pub address: String,
+    pub password: String,
+}
+
+/// Whether to automatically reconnect after losing the connection
+#[derive(Debug, Resource, Default)]
+pub struct AutoReconnect(pub bool);
+
+/// Whether to join servers as a spectator
+#[derive(Debug, Resource, Default)]
+pub struct SpectateOnJoin(pub bool);
+
+/// Where the server wants our player to spawn, if it told us before the map finished loading
+#[derive(Debug, Resource)]
+pub struct JoinSpawn {
+    pub position: Vec3,
+    pub yaw: f32,
+}
+
+/// A pending reconnect attempt
+#[derive(Debug, Resource)]
+pub struct Reconnect {
+    pub attempt: u32,
+    pub at: Instant,
+}
+impl Reconnect {
+    fn after(attempt: u32) -> Self {
+        let delay = RECONNECT_DELAY
+            .saturating_mul(2u32.saturating_pow(attempt - 1))
+            .min(MAX_RECONNECT_DELAY);
+        Self {
+            attempt,
+            at: Instant::now() + delay,
+        }
+    }
+
+    /// Schedules the attempt after `previous`, or none if we have tried enough times
+    fn next(previous: Option<&Reconnect>) -> Option<Self> {
+        let attempt = previous.map(|r| r.attempt).unwrap_or_default() + 1;
+        (attempt <= MAX_RECONNECT_ATTEMPTS).then(|| Self::after(attempt))
+    }
+
+    pub fn describe(&self) -> String {
+        let left = self.at.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            format!(
+                "Reconnecting (attempt {}/{MAX_RECONNECT_ATTEMPTS})...",
+                self.attempt
+            )
+        } else {
+            format!(
+                "Reconnecting in {}s (attempt {}/{MAX_RECONNECT_ATTEMPTS})",
+                left.as_secs() + 1,
+                self.attempt
+            )
+        }
+    }
+}
+
+/// Sends the handshake as soon as the connection is up
+pub fn send_handshake(
+    mut commands: Commands,
+    mut client: ResMut<RenetClient>,
+    handshake: Option<Res<Handshake>>,
+) {
+    let handshake = option_return!(handshake);
+    if !client.is_connected() {
+        return;
+    }
+    client.send_message(
+        ClientChannel::Command as u8,
+        error_return!(ClientMessage::Handshake(handshake.clone()).bytes()),
+    );
+    commands.remove_resource::<Handshake>();
 }
 
-pub fn errors() -> SystemConfigs {
-    (panic_on_error_system.run_if(resource_exists::<NetcodeClientTransport>),).into_configs()
+fn lost_connection(connection: &mut ConnectionState, reason: String) {
+    // The reason sent by the server is more useful than the transport error
+    if !matches!(connection, ConnectionState::Disconnected { .. }) {
+        *connection = ConnectionState::Disconnected {
+            reason,
+            retry: true,
+        };
+    }
 }
 
-pub fn errors_steam() -> SystemConfigs {
-    (panic_on_error_system_steam.run_if(resource_exists::<IsSteam>),).into_configs()
+pub fn disconnect_on_error_system(
+    mut renet_error: EventReader<NetcodeTransportError>,
+    mut connection: ResMut<ConnectionState>,
+) {
+    if let Some(e) = renet_error.read().next() {
+        lost_connection(&mut connection, format!("{e}"));
+    }
+}
+
+pub fn disconnect_on_error_system_steam(
+    mut renet_error: EventReader<SteamTransportError>,
+    mut connection: ResMut<ConnectionState>,
+) {
+    if let Some(e) = renet_error.read().next() {
+        lost_connection(&mut connection, format!("{e}"));
+    }
 }
 
-pub fn panic_on_error_system(mut renet_error: EventReader<NetcodeTransportError>) {
-    #[allow(clippy::never_loop)]
-    for e in renet_error.read() {
-        panic!("{}", e);
+/// Tracks the connection, and tears down the client and returns to the main menu once it is lost.
+#[allow(clippy::too_many_arguments)]
+pub fn update_connection_state(
+    mut commands: Commands,
+    client: Res<RenetClient>,
+    mut connection: ResMut<ConnectionState>,
+    auto_reconnect: Res<AutoReconnect>,
+    reconnect: Option<Res<Reconnect>>,
+    mut net_state: ResMut<NextState<NetState>>,
+    mut stage: ResMut<NextState<CurrentStage>>,
+) {
+    if *connection == ConnectionState::Connecting && client.is_connected() {
+        info!("connected to server");
+        *connection = ConnectionState::Connected;
+        commands.remove_resource::<Reconnect>();
+        return;
+    }
+    if client.is_disconnected() {
+        let reason = client
+            .disconnect_reason()
+            .map(|r| format!("{r}"))
+            .unwrap_or_else(|| "connection lost".to_string());
+        lost_connection(&mut connection, reason);
+    }
+
+    let ConnectionState::Disconnected { reason, retry } = &*connection else {
+        return;
+    };
+    error!("disconnected: {reason}");
+
+    match (*retry && auto_reconnect.0)
+        .then(|| Reconnect::next(reconnect.as_deref()))
+        .flatten()
+    {
+        Some(next) => commands.insert_resource(next),
+        None => commands.remove_resource::<Reconnect>(),
     }
+
+    commands.remove_resource::<RenetClient>();
+    commands.remove_resource::<NetcodeClientTransport>();
+    commands.remove_resource::<SteamClientTransport>();
+    commands.remove_resource::<Handshake>();
+    commands.remove_resource::<DemoRecorder>();
+    net_state.set(NetState::Offline);
+    stage.set(CurrentStage::MainMenu);
 }
 
-pub fn panic_on_error_system_steam(mut renet_error: EventReader<SteamTransportError>) {
-    #[allow(clippy::never_loop)]
-    for e in renet_error.read() {
-        panic!("{}", e);
+/// Joins the last server again once a scheduled reconnect is due
+pub fn reconnect(world: &mut World) {
+    let reconnect = option_return!(world.get_resource::<Reconnect>());
+    if Instant::now() < reconnect.at || world.contains_resource::<RenetClient>() {
+        return;
     }
+    info!("reconnect attempt {}", reconnect.attempt);
+    let next = Reconnect::next(Some(reconnect));
+    let server = option_return!(world.get_resource::<ServerAddress>()).clone();
+
+    let steam_client = world.get_resource::<SteamClient>().cloned();
+
+    // The state is taken out of the world while `init_client` has it
+    let joined = world.resource_scope(|world, mut next_net_state: Mut<NextState<NetState>>| {
+        init_client(
+            world,
+            &mut next_net_state,
+            &server.address,
+            &server.password,
+            steam_client.as_ref(),
+        )
+    });
+    if joined {
+        // If this attempt fails `update_connection_state` schedules the next one
+        return;
+    }
+    match next {
+        Some(next) => world.insert_resource(next),
+        None => {
+            world.remove_resource::<Reconnect>();
+        }
+    }
+}
+
+pub fn systems() -> SystemConfigs {
+    (
+        (handle_messages, send_handshake),
+        disconnect_on_error_system.run_if(resource_exists::<NetcodeClientTransport>),
+        disconnect_on_error_system_steam.run_if(resource_exists::<SteamClientTransport>),
+        update_connection_state,
+    )
+        .chain()
+        .into_configs()
 }
@@ -0,0 +1,289 @@
+use super::simulator::NetConditions;
+use crate::game_mode::{GameMode, spawns::SpawnPolicy};
+use bevy::{
+    ecs::system::Resource,
+    log::{error, info},
+};
+use macros::error_return;
+use renet_steam::AccessPermission;
+use resources::data::Movement;
+use serde::Deserialize;
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    path::{Path, PathBuf},
+};
+
+const DEFAULT_CONFIG: &str = "server.json";
+const DEFAULT_BAN_FILE: &str = "bans.json";
+
+/// Who is allowed to join a Steam hosted server
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SteamAccess {
+    #[default]
+    Public,
+    FriendsOnly,
+    Private,
+}
+impl From<SteamAccess> for AccessPermission {
+    fn from(value: SteamAccess) -> Self {
+        match value {
+            SteamAccess::Public => AccessPermission::Public,
+            SteamAccess::FriendsOnly => AccessPermission::FriendsOnly,
+            SteamAccess::Private => AccessPermission::Private,
+        }
+    }
+}
+
+/// Settings used when hosting a server.
+///
+/// Loaded from `server.json` (or the file given with `--config`), and
+/// then overridden by any command line flags.
+/// The file is kept outside of `assets` so that it does not affect the asset hash.
+#[derive(Debug, Deserialize, Resource, Clone)]
+#[serde(default)]
+pub struct ServerSettings {
+    pub name: String,
+    pub bind_address: IpAddr,
+    pub port: u16,
+    /// Addresses clients connect through, derived from the bind address if empty
+    pub public_addresses: Vec<SocketAddr>,
+    pub max_clients: usize,
+    pub password: Option<String>,
+    /// Password for the remote console, rcon is turned off if there is none
+    pub rcon_password: Option<String>,
+    /// Where the ban list is kept, outside of `assets` like the settings
+    pub ban_file: PathBuf,
+    pub steam_access: SteamAccess,
+    /// Maps played in order, the map selected in the main menu is used if empty
+    pub maps: Vec<PathBuf>,
+    pub mode: GameMode,
+    pub spawn_policy: SpawnPolicy,
+    /// Frags needed to win a match, 0 means no limit
+    pub fraglimit: u64,
+    /// Match length in minutes, 0 means no limit
+    pub timelimit: f32,
+    /// Flag captures needed to win capture the flag, 0 means no limit
+    pub capturelimit: u64,
+    /// Seconds before a match starts, frags don't count during the warmup
+    pub warmup: f32,
+    /// Seconds the scores are shown after a match ends
+    pub intermission: f32,
+    /// Puts a relay with these network conditions in front of the server, for testing.
+    /// Only takes effect when starting the server, after that the conditions can be changed.
+    pub netsim: Option<NetConditions>,
+    /// Movement physics used instead of `assets/movement.json`, sent to everyone joining
+    pub movement: Option<PathBuf>,
+}
+impl Default for ServerSettings {
+    fn default() -> Self {
+        Self {
+            name: "Ondth Server".to_string(),
+            bind_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: 8000,
+            public_addresses: Vec::new(),
+            max_clients: 64,
+            password: None,
+            rcon_password: None,
+            ban_file: PathBuf::from(DEFAULT_BAN_FILE),
+            steam_access: SteamAccess::Public,
+            maps: Vec::new(),
+            mode: GameMode::Deathmatch,
+            spawn_policy: SpawnPolicy::Farthest,
+            fraglimit: 0,
+            timelimit: 0.0,
+            capturelimit: 3,
+            warmup: 15.0,
+            intermission: 10.0,
+            netsim: None,
+            movement: None,
+        }
+    }
+}
+impl ServerSettings {
+    /// Loads the settings using the arguments passed to the game.
+    pub fn load() -> Self {
+        Self::from_args(std::env::args().skip(1))
+    }
+
+    fn from_args(args: impl IntoIterator<Item = String>) -> Self {
+        let args = args.into_iter().collect::<Vec<_>>();
+        let config = args
+            .iter()
+            .position(|a| a == "--config")
+            .and_then(|i| args.get(i + 1))
+            .map(PathBuf::from);
+
+        let mut settings = match &config {
+            Some(path) => Self::from_file(path).unwrap_or_default(),
+            None if Path::new(DEFAULT_CONFIG).exists() => {
+                Self::from_file(DEFAULT_CONFIG).unwrap_or_default()
+            }
+            None => Self::default(),
+        };
+        settings.apply_args(&args);
+        settings
+    }
+
+    fn from_file<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let path = path.as_ref();
+        info!("Loading server settings from {path:?}...");
+        let data = error_return!(std::fs::read_to_string(path));
+        Some(error_return!(serde_json::from_str(&data)))
+    }
+
+    fn apply_args(&mut self, args: &[String]) {
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let Some(name) = arg.strip_prefix("--") else {
+                continue;
+            };
+            let Some(value) = args.next() else {
+                error!("missing value for argument \"{arg}\"");
+                break;
+            };
+            if name == "config" {
+                continue;
+            }
+            if let Err(e) = self.set(name, value) {
+                error!("invalid argument \"{arg} {value}\": {e}");
+            }
+        }
+    }
+
+    /// Whether a setting is only read when the server starts, so changing it later does nothing
+    pub fn startup_only(name: &str) -> bool {
+        matches!(
+            name,
+            "bind" | "port" | "public" | "max-clients" | "ban-file" | "movement"
+        )
+    }
+
+    /// Changes a setting by the name of its flag without the dashes, also used by the rcon `set` command
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name {
+            "name" => {
+                self.name = value.to_string();
+                Ok(())
+            }
+            "password" => {
+                self.password = Some(value.to_string());
+                Ok(())
+            }
+            "rcon-password" => {
+                self.rcon_password = Some(value.to_string());
+                Ok(())
+            }
+            "ban-file" => {
+                self.ban_file = value.into();
+                Ok(())
+            }
+            "map" => {
+                self.maps.push(value.into());
+                Ok(())
+            }
+            "bind" => value
+                .parse()
+                .map(|v| self.bind_address = v)
+                .map_err(|e| format!("{e}")),
+            "public" => value
+                .parse()
+                .map(|v| self.public_addresses.push(v))
+                .map_err(|e| format!("{e}")),
+            "port" => value
+                .parse()
+                .map(|v| self.port = v)
+                .map_err(|e| format!("{e}")),
+            "max-clients" => value
+                .parse()
+                .map(|v| self.max_clients = v)
+                .map_err(|e| format!("{e}")),
+            "mode" => value.parse().map(|v| self.mode = v),
+            "spawn-policy" => value.parse().map(|v| self.spawn_policy = v),
+            "fraglimit" => value
+                .parse()
+                .map(|v| self.fraglimit = v)
+                .map_err(|e| format!("{e}")),
+            "timelimit" => value
+                .parse()
+                .map(|v| self.timelimit = v)
+                .map_err(|e| format!("{e}")),
+            "capturelimit" => value
+                .parse()
+                .map(|v| self.capturelimit = v)
+                .map_err(|e| format!("{e}")),
+            "warmup" => value
+                .parse()
+                .map(|v| self.warmup = v)
+                .map_err(|e| format!("{e}")),
+            "intermission" => value
+                .parse()
+                .map(|v| self.intermission = v)
+                .map_err(|e| format!("{e}")),
+            "netsim" => value.parse().map(|v| self.netsim = Some(v)),
+            "movement" => {
+                self.movement = Some(value.into());
+                Ok(())
+            }
+            _ => Err("unknown setting".to_string()),
+        }
+    }
+
+    /// The first argument which is not a flag or the value of a flag
+    pub fn positional_arg() -> Option<String> {
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            if arg.starts_with("--") {
+                args.next();
+            } else {
+                return Some(arg);
+            }
+        }
+        None
+    }
+
+    /// The server's movement physics, the default ones if there are none or they can't be loaded
+    pub fn load_movement(&self) -> Movement {
+        let Some(path) = &self.movement else {
+            return Movement::default();
+        };
+        info!("Loading movement physics from {path:?}...");
+        Movement::from_file(path).unwrap_or_else(|e| {
+            error!("failed to load movement physics from {path:?}: {e}");
+            Movement::default()
+        })
+    }
+
+    pub fn bind_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.bind_address, self.port)
+    }
+
+    pub fn public_addrs(&self) -> Vec<SocketAddr> {
+        if !self.public_addresses.is_empty() {
+            return self.public_addresses.clone();
+        }
+        if !self.bind_address.is_unspecified() {
+            return vec![self.bind_addr()];
+        }
+        let mut addrs = vec![SocketAddr::new(Ipv4Addr::LOCALHOST.into(), self.port)];
+        if let Some(ip) = lan_ip() {
+            addrs.push(SocketAddr::new(ip, self.port));
+        }
+        addrs
+    }
+
+    /// The map which should be played after `current` in the rotation
+    pub fn map_after(&self, current: &Path) -> Option<PathBuf> {
+        let index = self.maps.iter().position(|m| m == current);
+        let next = index.map(|i| (i + 1) % self.maps.len()).unwrap_or(0);
+        self.maps.get(next).cloned()
+    }
+}
+
+/// Finds the address of the interface used for outgoing traffic.
+/// Connecting a UDP socket does not send anything.
+fn lan_ip() -> Option<IpAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("8.8.8.8:80").ok()?;
+    let ip = socket.local_addr().ok()?.ip();
+    (!ip.is_unspecified()).then_some(ip)
+}
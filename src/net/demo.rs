@@ -0,0 +1,400 @@
+use super::{
+    CurrentClientId, Lobby, ServerMessage,
+    client::{ConnectionState, MessageHandler},
+    handshake::{GAME_VERSION, PROTOCOL_VERSION},
+};
+use crate::{
+    entities::{ProjectileEntity, pickup::PickupEntity},
+    game_mode::MatchState,
+};
+use bevy::prelude::*;
+use macros::{error_continue, error_return, option_return};
+use resources::{CurrentStage, MapDoneLoading, inputs::PlayerInput};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, ErrorKind},
+    path::{Path, PathBuf},
+    time::{Instant, SystemTime},
+};
+
+/// Where recorded demos are saved
+pub const DEMO_DIR: &str = "demos";
+/// The id of the player watching a demo, never used by real players
+pub const DEMO_SPECTATOR_ID: u64 = u64::MAX;
+/// How far the seek keys jump, in seconds
+const SEEK_STEP: f32 = 10.0;
+const MIN_SPEED: f32 = 0.125;
+const MAX_SPEED: f32 = 8.0;
+
+/// Written at the start of every demo
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DemoHeader {
+    pub protocol: u64,
+    pub game_version: String,
+    /// The client whose connection was recorded
+    pub recorded_by: u64,
+}
+
+/// A single message as it was received from the server
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DemoFrame {
+    /// Seconds since the first message of the demo
+    pub time: f32,
+    pub channel: u8,
+    pub message: Vec<u8>,
+}
+
+/// Whether to record a demo every time we join a server
+#[derive(Debug, Resource, Default)]
+pub struct RecordDemos(pub bool);
+
+/// Records every message received from the server, removed (and flushed) on disconnect.
+/// Recording starts when joining, so the demo contains the `SetMap` and all the spawns.
+#[derive(Debug, Resource)]
+pub struct DemoRecorder {
+    file: BufWriter<File>,
+    started: Option<Instant>,
+}
+impl DemoRecorder {
+    pub fn create(recorded_by: u64) -> bincode::Result<Self> {
+        fs::create_dir_all(DEMO_DIR)?;
+        let secs = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let path = Path::new(DEMO_DIR).join(format!("{secs}.demo"));
+        info!("recording demo to {path:?}");
+
+        let mut file = BufWriter::new(File::create(path)?);
+        bincode::serialize_into(
+            &mut file,
+            &DemoHeader {
+                protocol: PROTOCOL_VERSION,
+                game_version: GAME_VERSION.to_string(),
+                recorded_by,
+            },
+        )?;
+        Ok(Self {
+            file,
+            started: None,
+        })
+    }
+
+    pub fn record(&mut self, channel: u8, message: &[u8]) -> bincode::Result<()> {
+        let started = *self.started.get_or_insert_with(Instant::now);
+        bincode::serialize_into(
+            &mut self.file,
+            &DemoFrame {
+                time: started.elapsed().as_secs_f32(),
+                channel,
+                message: message.to_vec(),
+            },
+        )
+    }
+}
+
+/// A demo being played back
+#[derive(Debug, Resource)]
+pub struct DemoPlayback {
+    pub header: DemoHeader,
+    frames: Vec<DemoFrame>,
+    /// Index of the next frame to apply
+    next: usize,
+    pub time: f32,
+    pub speed: f32,
+    pub paused: bool,
+    /// Skips effects while catching up after a seek
+    seeking: bool,
+    /// Set when seeking backwards, the world has to be rebuilt from the start
+    rewind: bool,
+}
+impl DemoPlayback {
+    fn new(header: DemoHeader, frames: Vec<DemoFrame>) -> Self {
+        Self {
+            header,
+            frames,
+            next: 0,
+            time: 0.0,
+            speed: 1.0,
+            paused: false,
+            seeking: false,
+            rewind: false,
+        }
+    }
+
+    pub fn load(path: &Path) -> bincode::Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+        let header: DemoHeader = bincode::deserialize_from(&mut file)?;
+        if header.protocol != PROTOCOL_VERSION {
+            return Err(Box::new(bincode::ErrorKind::Custom(format!(
+                "demo was recorded with protocol {} (game {}), we have {PROTOCOL_VERSION}",
+                header.protocol, header.game_version
+            ))));
+        }
+
+        let mut frames = Vec::new();
+        loop {
+            match bincode::deserialize_from(&mut file) {
+                Ok(frame) => frames.push(frame),
+                Err(e) => {
+                    let eof = match &*e {
+                        bincode::ErrorKind::Io(io) => io.kind() == ErrorKind::UnexpectedEof,
+                        _ => false,
+                    };
+                    // A demo cut off by a crash can still be played up to there
+                    if !eof {
+                        warn!("demo {path:?} is damaged, playing what is readable: {e}");
+                    }
+                    break;
+                }
+            }
+        }
+        info!("loaded demo {path:?} with {} messages", frames.len());
+        Ok(Self::new(header, frames))
+    }
+
+    pub fn length(&self) -> f32 {
+        self.frames.last().map(|f| f.time).unwrap_or_default()
+    }
+
+    pub fn finished(&self) -> bool {
+        self.next >= self.frames.len()
+    }
+
+    pub fn seek(&mut self, time: f32) {
+        let time = time.clamp(0.0, self.length());
+        if time < self.time {
+            self.next = 0;
+            self.rewind = true;
+        }
+        self.time = time;
+        self.seeking = true;
+    }
+
+    fn advance(&mut self, delta: f32) {
+        if !self.paused {
+            self.time = (self.time + delta * self.speed).min(self.length());
+        }
+    }
+
+    /// Returns the next frame that is due, and moves past it
+    fn next_due(&mut self) -> Option<&DemoFrame> {
+        let frame = self.frames.get(self.next).filter(|f| f.time <= self.time)?;
+        self.next += 1;
+        Some(frame)
+    }
+}
+
+/// Marks the text showing the playback state
+#[derive(Debug, Component)]
+pub struct DemoHud;
+
+/// Lists the recorded demos, newest first
+pub fn get_demos() -> Vec<PathBuf> {
+    let mut demos = fs::read_dir(DEMO_DIR)
+        .map(|dir| {
+            dir.filter_map(|f| f.ok())
+                .map(|f| f.path())
+                .filter(|f| f.extension().is_some_and(|e| e == "demo"))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    demos.sort();
+    demos.reverse();
+    demos
+}
+
+/// Starts playing a demo, the recorded `SetMap` then loads the map.
+/// No server connection or Steam is needed, the demo is watched as a spectator.
+pub fn start_playback(world: &mut World, path: &Path) -> bool {
+    let playback = error_return!(DemoPlayback::load(path));
+    info!(
+        "playing demo {path:?} recorded by {}",
+        playback.header.recorded_by
+    );
+    world.insert_resource(playback);
+    world.insert_resource(CurrentClientId(DEMO_SPECTATOR_ID));
+    world.insert_resource(Lobby::default());
+    world.insert_resource(MatchState::default());
+    // Left over from the last connection, and would show up in the main menu afterwards
+    world.remove_resource::<ConnectionState>();
+    true
+}
+
+/// Applies the messages which are due
+pub fn play_demo(
+    mut commands: Commands,
+    mut demo: ResMut<DemoPlayback>,
+    time: Res<Time>,
+    map_done_loading: Res<MapDoneLoading>,
+    entities: Query<Entity, Or<(With<PickupEntity>, With<ProjectileEntity>)>>,
+    mut handler: MessageHandler,
+) {
+    if demo.rewind {
+        demo.rewind = false;
+        for (entity, player, _) in &handler.nw.players {
+            if player.id != DEMO_SPECTATOR_ID {
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+        for entity in &entities {
+            commands.entity(entity).despawn_recursive();
+        }
+        handler.pickup_index.0.clear();
+        handler.projectile_index.0.clear();
+        handler.nw.lobby.retain(|id, _| *id == DEMO_SPECTATOR_ID);
+        handler.nw.chat.clear();
+        *handler.nw.match_state = MatchState::default();
+        // The despawns have to be applied before the demo is played again
+        return;
+    }
+
+    // Time stands still while the map loads
+    if map_done_loading.0 {
+        demo.advance(time.delta_secs());
+    }
+
+    let recorded_by = demo.header.recorded_by;
+    while let Some(frame) = demo.next_due() {
+        let message = error_continue!(ServerMessage::from_bytes(&frame.message));
+        if demo.seeking && matches!(message, ServerMessage::HitscanHits { .. }) {
+            continue;
+        }
+        handler.handle(message, recorded_by);
+    }
+    demo.seeking = false;
+}
+
+pub fn demo_controls(
+    input: Res<PlayerInput>,
+    mut demo: ResMut<DemoPlayback>,
+    mut stage: ResMut<NextState<CurrentStage>>,
+    mut commands: Commands,
+) {
+    if input.demo_pause_just_pressed {
+        demo.paused = !demo.paused;
+    }
+    if input.demo_faster_just_pressed {
+        demo.speed = (demo.speed * 2.0).min(MAX_SPEED);
+    }
+    if input.demo_slower_just_pressed {
+        demo.speed = (demo.speed / 2.0).max(MIN_SPEED);
+    }
+    if input.demo_forward_just_pressed {
+        let time = demo.time + SEEK_STEP;
+        demo.seek(time);
+    }
+    if input.demo_back_just_pressed {
+        let time = demo.time - SEEK_STEP;
+        demo.seek(time);
+    }
+    if input.demo_stop_just_pressed {
+        info!("stopping demo playback");
+        commands.remove_resource::<DemoPlayback>();
+        stage.set(CurrentStage::MainMenu);
+    }
+}
+
+fn format_time(time: f32) -> String {
+    let secs = time as u32;
+    format!("{:02}:{:02}", secs / 60, secs % 60)
+}
+
+pub fn update_demo_hud(
+    mut commands: Commands,
+    demo: Res<DemoPlayback>,
+    mut hud: Query<&mut Text, With<DemoHud>>,
+) {
+    let text = format!(
+        "DEMO {} / {} x{}{}",
+        format_time(demo.time),
+        format_time(demo.length()),
+        demo.speed,
+        if demo.paused {
+            " (paused)"
+        } else if demo.finished() {
+            " (finished)"
+        } else {
+            ""
+        }
+    );
+
+    if hud.is_empty() {
+        commands.spawn((
+            Text::new(text),
+            TextFont {
+                font_size: 16.0,
+                ..default()
+            },
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Px(10.0),
+                right: Val::Px(10.0),
+                ..default()
+            },
+            DemoHud,
+        ));
+        return;
+    }
+    option_return!(hud.iter_mut().next()).0 = text;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn demo(times: &[f32]) -> DemoPlayback {
+        let header = DemoHeader {
+            protocol: PROTOCOL_VERSION,
+            game_version: GAME_VERSION.to_string(),
+            recorded_by: 1,
+        };
+        let frames = times
+            .iter()
+            .map(|&time| DemoFrame {
+                time,
+                channel: 0,
+                message: Vec::new(),
+            })
+            .collect();
+        DemoPlayback::new(header, frames)
+    }
+
+    fn due(demo: &mut DemoPlayback) -> Vec<f32> {
+        let mut times = Vec::new();
+        while let Some(frame) = demo.next_due() {
+            times.push(frame.time);
+        }
+        times
+    }
+
+    #[test]
+    fn frames_are_applied_in_time() {
+        let mut demo = demo(&[0.0, 0.5, 1.0, 2.0]);
+        assert_eq!(due(&mut demo), vec![0.0]);
+        demo.advance(0.25);
+        demo.speed = 4.0;
+        demo.advance(0.25);
+        assert_eq!(due(&mut demo), vec![0.5, 1.0]);
+        demo.paused = true;
+        demo.advance(10.0);
+        assert!(due(&mut demo).is_empty());
+        demo.paused = false;
+        demo.advance(10.0);
+        assert_eq!(demo.time, 2.0);
+        assert_eq!(due(&mut demo), vec![2.0]);
+        assert!(demo.finished());
+    }
+
+    #[test]
+    fn seeking_back_replays_from_the_start() {
+        let mut demo = demo(&[0.0, 1.0, 2.0, 3.0]);
+        demo.seek(2.5);
+        assert!(!demo.rewind);
+        assert_eq!(due(&mut demo), vec![0.0, 1.0, 2.0]);
+        demo.seek(1.0);
+        assert!(demo.rewind);
+        assert_eq!(due(&mut demo), vec![0.0, 1.0]);
+    }
+}
@@ -0,0 +1,310 @@
+use super::{config::ServerSettings, handshake::PROTOCOL_VERSION};
+use crate::net::Lobby;
+use bevy::ecs::system::{Res, Resource};
+use macros::{error_return, option_return};
+use resources::CurrentMap;
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{self, ErrorKind},
+    net::{Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    time::{Duration, Instant},
+};
+
+/// Port servers listen on for LAN discovery queries
+pub const DISCOVERY_PORT: u16 = 8001;
+const QUERY_MAGIC: &[u8; 8] = b"ONDTHQRY";
+const QUERY_LEN: usize = QUERY_MAGIC.len() + 8;
+/// Longest server and map names sent in answers, in characters
+const MAX_INFO_NAME: usize = 64;
+/// Fits an answer with the longest names, at up to 4 bytes a character, and the rest of it
+const RESPONSE_LEN: usize = 2 * 4 * MAX_INFO_NAME + 256;
+
+/// What a server tells clients looking for games on the LAN
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ServerInfo {
+    pub name: String,
+    pub map: String,
+    pub players: usize,
+    pub max_players: usize,
+    pub protocol: u64,
+    /// The port the game itself is hosted on
+    pub port: u16,
+}
+impl ServerInfo {
+    /// Cuts the names down to `MAX_INFO_NAME`, so that the answer fits the client's buffer
+    fn capped(mut self) -> Self {
+        self.name = self.name.chars().take(MAX_INFO_NAME).collect();
+        self.map = self.map.chars().take(MAX_INFO_NAME).collect();
+        self
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DiscoveryResponse {
+    token: u64,
+    info: ServerInfo,
+}
+
+fn is_ignorable(e: &io::Error) -> bool {
+    // Windows reports ICMP port unreachable as a reset on the next read
+    matches!(e.kind(), ErrorKind::ConnectionReset)
+}
+
+/// Answers discovery queries on the server side
+#[derive(Debug, Resource)]
+pub struct DiscoveryResponder {
+    socket: UdpSocket,
+}
+impl DiscoveryResponder {
+    pub fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self { socket })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Answers all pending queries, `info` is only called if there is at least one.
+    /// Returns the amount of queries answered.
+    pub fn answer(&self, info: impl Fn() -> ServerInfo) -> io::Result<usize> {
+        let mut buf = [0; QUERY_LEN];
+        let mut info_cache = None;
+        let mut answered = 0;
+        loop {
+            let (len, from) = match self.socket.recv_from(&mut buf) {
+                Ok(o) => o,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(answered),
+                Err(e) if is_ignorable(&e) => continue,
+                Err(e) => return Err(e),
+            };
+            if len != QUERY_LEN || &buf[..QUERY_MAGIC.len()] != QUERY_MAGIC {
+                continue;
+            }
+            let mut token = [0; 8];
+            token.copy_from_slice(&buf[QUERY_MAGIC.len()..]);
+
+            let response = DiscoveryResponse {
+                token: u64::from_le_bytes(token),
+                info: info_cache.get_or_insert_with(|| info().capped()).clone(),
+            };
+            let bytes = bincode::serialize(&response).map_err(io::Error::other)?;
+            self.socket.send_to(&bytes, from)?;
+            answered += 1;
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FoundServer {
+    pub addr: SocketAddr,
+    pub info: ServerInfo,
+    pub ping: Duration,
+}
+impl FoundServer {
+    pub fn is_compatible(&self) -> bool {
+        self.info.protocol == PROTOCOL_VERSION
+    }
+}
+
+/// Looks for servers on the LAN from the client side
+#[derive(Debug, Resource)]
+pub struct ServerBrowser {
+    socket: UdpSocket,
+    token: u64,
+    sent: Instant,
+    /// Set by `query` emptying the list, see `take_cleared`
+    cleared: bool,
+    pub servers: Vec<FoundServer>,
+}
+impl ServerBrowser {
+    pub fn new() -> io::Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.set_broadcast(true)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            token: 0,
+            sent: Instant::now(),
+            cleared: false,
+            servers: Vec::new(),
+        })
+    }
+
+    /// Clears the list of found servers and sends out a new query to `targets`.
+    pub fn query(&mut self, targets: &[SocketAddr]) -> io::Result<()> {
+        self.token = self.token.wrapping_add(1);
+        self.sent = Instant::now();
+        self.servers.clear();
+        self.cleared = true;
+
+        let mut packet = [0; QUERY_LEN];
+        packet[..QUERY_MAGIC.len()].copy_from_slice(QUERY_MAGIC);
+        packet[QUERY_MAGIC.len()..].copy_from_slice(&self.token.to_le_bytes());
+
+        let mut res = Ok(());
+        for target in targets {
+            if let Err(e) = self.socket.send_to(&packet, target) {
+                res = Err(e);
+            }
+        }
+        res
+    }
+
+    /// Queries the LAN broadcast address, and this machine.
+    pub fn refresh(&mut self) -> io::Result<()> {
+        self.query(&[
+            (Ipv4Addr::BROADCAST, DISCOVERY_PORT).into(),
+            (Ipv4Addr::LOCALHOST, DISCOVERY_PORT).into(),
+        ])
+    }
+
+    /// Whether the list was emptied by a query since the last call, and needs showing again
+    /// even if no server answers
+    pub fn take_cleared(&mut self) -> bool {
+        std::mem::take(&mut self.cleared)
+    }
+
+    /// Reads all responses to the latest query, returns true if a new server was found.
+    pub fn poll(&mut self) -> io::Result<bool> {
+        let mut buf = [0; RESPONSE_LEN];
+        let mut changed = false;
+        loop {
+            let (len, from) = match self.socket.recv_from(&mut buf) {
+                Ok(o) => o,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(changed),
+                Err(e) if is_ignorable(&e) => continue,
+                Err(e) => return Err(e),
+            };
+            let Ok(response) = bincode::deserialize::<DiscoveryResponse>(&buf[..len]) else {
+                continue;
+            };
+            if response.token != self.token {
+                continue;
+            }
+
+            let addr = SocketAddr::new(from.ip(), response.info.port);
+            if self.servers.iter().any(|s| s.addr == addr) {
+                continue;
+            }
+            self.servers.push(FoundServer {
+                addr,
+                info: response.info,
+                ping: self.sent.elapsed(),
+            });
+            changed = true;
+        }
+    }
+}
+
+pub fn answer_discovery(
+    responder: Option<Res<DiscoveryResponder>>,
+    settings: Res<ServerSettings>,
+    map: Res<CurrentMap>,
+    lobby: Res<Lobby>,
+) {
+    let responder = option_return!(responder);
+    error_return!(responder.answer(|| {
+        ServerInfo {
+            name: settings.name.clone(),
+            map: map
+                .0
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            players: lobby.len(),
+            max_players: settings.max_clients,
+            protocol: PROTOCOL_VERSION,
+            port: settings.port,
+        }
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info() -> ServerInfo {
+        ServerInfo {
+            name: "test server".to_string(),
+            map: "Test".to_string(),
+            players: 3,
+            max_players: 8,
+            protocol: PROTOCOL_VERSION,
+            port: 8000,
+        }
+    }
+
+    fn poll_until_found(browser: &mut ServerBrowser, responder: &DiscoveryResponder) {
+        for _ in 0..100 {
+            responder.answer(info).unwrap();
+            if browser.poll().unwrap() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("server was never discovered");
+    }
+
+    #[test]
+    fn discover_over_loopback() {
+        let responder = DiscoveryResponder::bind("127.0.0.1:0").unwrap();
+        let mut browser = ServerBrowser::new().unwrap();
+        browser.query(&[responder.local_addr().unwrap()]).unwrap();
+
+        poll_until_found(&mut browser, &responder);
+        assert_eq!(browser.servers.len(), 1);
+        let found = &browser.servers[0];
+        assert_eq!(found.info, info());
+        assert_eq!(found.addr, "127.0.0.1:8000".parse().unwrap());
+        assert!(found.is_compatible());
+    }
+
+    #[test]
+    fn stale_responses_are_ignored() {
+        let responder = DiscoveryResponder::bind("127.0.0.1:0").unwrap();
+        let mut browser = ServerBrowser::new().unwrap();
+        let target = [responder.local_addr().unwrap()];
+
+        // Both queries get answered, but only the latest one counts
+        browser.query(&target).unwrap();
+        browser.query(&target).unwrap();
+        poll_until_found(&mut browser, &responder);
+
+        assert_eq!(browser.servers.len(), 1);
+    }
+
+    #[test]
+    fn long_names_are_cut_to_fit() {
+        let responder = DiscoveryResponder::bind("127.0.0.1:0").unwrap();
+        let mut browser = ServerBrowser::new().unwrap();
+        browser.query(&[responder.local_addr().unwrap()]).unwrap();
+
+        let long_info = || ServerInfo {
+            name: "ö".repeat(1000),
+            ..info()
+        };
+        for _ in 0..100 {
+            responder.answer(long_info).unwrap();
+            if browser.poll().unwrap() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(browser.servers.len(), 1);
+        assert_eq!(browser.servers[0].info.name, "ö".repeat(MAX_INFO_NAME));
+    }
+
+    #[test]
+    fn garbage_is_not_answered() {
+        let responder = DiscoveryResponder::bind("127.0.0.1:0").unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .send_to(b"hello there", responder.local_addr().unwrap())
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+
+        assert_eq!(responder.answer(info).unwrap(), 0);
+    }
+}
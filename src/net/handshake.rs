@@ -0,0 +1,137 @@
+use super::{ServerChannel, ServerMessage};
+use bevy::ecs::system::Resource;
+use bevy_renet::renet::RenetServer;
+use macros::error_return;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// Bumped whenever the network messages change.
+/// The netcode `PROTOCOL_ID` stays the same so that old clients can be told why they were rejected.
+pub const PROTOCOL_VERSION: u64 = 19;
+pub const GAME_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// How long a client has to send its handshake after connecting
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long to wait before disconnecting a kicked client, so that the reason has time to arrive
+const KICK_DELAY: Duration = Duration::from_millis(500);
+
+/// Hash of the asset folder, calculated once on startup
+#[derive(Debug, Resource, Clone)]
+pub struct AssetHash(pub String);
+
+/// The first message a client sends after connecting.
+/// As a resource it is the handshake waiting to be sent to the server.
+#[derive(Debug, Serialize, Deserialize, Clone, Resource)]
+pub struct Handshake {
+    pub protocol: u64,
+    pub game_version: String,
+    pub asset_hash: String,
+    pub password: String,
+    /// Join as a spectator instead of a player
+    pub spectate: bool,
+}
+impl Handshake {
+    pub fn new(asset_hash: &AssetHash, password: &str, spectate: bool) -> Self {
+        Self {
+            protocol: PROTOCOL_VERSION,
+            game_version: GAME_VERSION.to_string(),
+            asset_hash: asset_hash.0.clone(),
+            password: password.to_string(),
+            spectate,
+        }
+    }
+
+    /// Checks a handshake sent by a client against this one,
+    /// returning a reason that can be shown to the player if it does not match.
+    pub fn verify(&self, client: &Handshake, password: Option<&str>) -> Result<(), String> {
+        if client.protocol != self.protocol {
+            return Err(format!(
+                "protocol mismatch: server uses version {}, you use version {}",
+                self.protocol, client.protocol
+            ));
+        }
+        if client.game_version != self.game_version {
+            return Err(format!(
+                "game version mismatch: server runs {}, you run {}",
+                self.game_version, client.game_version
+            ));
+        }
+        if client.asset_hash != self.asset_hash {
+            return Err("your assets differ from the server's".to_string());
+        }
+        if let Some(password) = password
+            && client.password != password
+        {
+            return Err("wrong password".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Keeps track of clients that are connected but not yet admitted into the game,
+/// and of clients that are about to be kicked.
+#[derive(Debug, Resource, Default)]
+pub struct Admission {
+    pending: HashMap<u64, Instant>,
+    kicks: Vec<(u64, Instant)>,
+}
+impl Admission {
+    pub fn connected(&mut self, client_id: u64) {
+        self.pending.insert(client_id, Instant::now());
+    }
+
+    pub fn disconnected(&mut self, client_id: u64) {
+        self.pending.remove(&client_id);
+        self.kicks.retain(|(id, _)| *id != client_id);
+    }
+
+    pub fn is_pending(&self, client_id: u64) -> bool {
+        self.pending.contains_key(&client_id)
+    }
+
+    pub fn is_kicked(&self, client_id: u64) -> bool {
+        self.kicks.iter().any(|(id, _)| *id == client_id)
+    }
+
+    /// Returns true if the client was waiting to be admitted
+    pub fn admit(&mut self, client_id: u64) -> bool {
+        self.pending.remove(&client_id).is_some()
+    }
+
+    /// Tells the client why it is being disconnected, and disconnects it shortly after.
+    pub fn kick(&mut self, server: &mut RenetServer, client_id: u64, reason: String) {
+        self.pending.remove(&client_id);
+        if self.is_kicked(client_id) {
+            return;
+        }
+        self.kicks.push((client_id, Instant::now()));
+        server.send_message(
+            client_id,
+            ServerChannel::ServerMessages as u8,
+            error_return!(ServerMessage::Disconnect { reason }.bytes()),
+        );
+    }
+
+    pub fn update(&mut self, server: &mut RenetServer) {
+        let timed_out = self
+            .pending
+            .iter()
+            .filter(|(_, since)| since.elapsed() > HANDSHAKE_TIMEOUT)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for id in timed_out {
+            self.kick(server, id, "no handshake received".to_string());
+        }
+
+        self.kicks.retain(|(id, since)| {
+            let done = since.elapsed() > KICK_DELAY;
+            if done {
+                server.disconnect(*id);
+            }
+            !done
+        });
+    }
+}
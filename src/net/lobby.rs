@@ -1,3 +1,4 @@
+use crate::chat::ChatLimiter;
 use bevy::ecs::{entity::Entity, system::Resource};
 use faststr::FastStr;
 use std::collections::BTreeMap;
@@ -12,6 +13,17 @@ pub struct PlayerInfo {
     pub name: FastStr,
     pub kills: u64,
     pub deaths: u64,
+    /// Frags minus suicides and team kills, plus capture bonuses
+    pub score: i64,
+    /// Round trip time to the server in milliseconds
+    pub ping: u32,
+    /// None outside of team based game modes
+    pub team: Option<u8>,
+    /// Spectators are left out of damage and scoring
+    pub spectating: bool,
+    pub chat_limiter: ChatLimiter,
+    /// The map this player voted for during the intermission, only known by the server
+    pub vote: Option<usize>,
 }
 impl PlayerInfo {
     pub fn new(entity: Entity, name: FastStr) -> Self {
@@ -20,6 +32,22 @@ impl PlayerInfo {
             name,
             kills: 0,
             deaths: 0,
+            score: 0,
+            ping: 0,
+            team: None,
+            spectating: false,
+            chat_limiter: ChatLimiter::default(),
+            vote: None,
+        }
+    }
+
+    /// Share of kills in all kills and deaths
+    pub fn efficiency(&self) -> f32 {
+        let total = self.kills + self.deaths;
+        if total == 0 {
+            0.0
+        } else {
+            self.kills as f32 / total as f32
         }
     }
 }
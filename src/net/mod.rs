@@ -1,17 +1,26 @@
-use crate::queries::NetWorld;
+use crate::{
+    game_mode::MatchState, kill_feed::Frag, player::Player, queries::NetWorld, scoreboard::ScoreRow,
+};
 use bevy::prelude::*;
 use bevy_renet::renet::*;
 use faststr::FastStr;
 use macros::{error_return, option_return};
-use resources::data::PickupData;
+use resources::data::{Movement, PickupData, Powerup};
 use serde::{Deserialize, Serialize};
 use std::{path::PathBuf, time::Duration};
 
-use self::steam::SteamClient;
+use self::{handshake::Handshake, stats::NetStats, steam::SteamClient};
 
 pub mod client;
+pub mod config;
+pub mod demo;
+pub mod discovery;
+pub mod handshake;
 mod lobby;
+pub mod rcon;
 pub mod server;
+pub mod simulator;
+pub mod stats;
 pub mod steam;
 pub use lobby::*;
 
@@ -19,12 +28,15 @@ pub fn update_world(client_id: u64, message: &ClientMessage, nw: &mut NetWorld)
     match message {
         ClientMessage::UpdatePosition {
             position,
+            crouching,
             rotation,
             cam_rot,
         } => {
             if nw.current_id.0 != client_id {
                 let player = option_return!(nw.lobby.get(&client_id)).entity;
-                let (_, pl, mut tr) = error_return!(nw.players.get_mut(player));
+                let (_, mut pl, mut tr) = error_return!(nw.players.get_mut(player));
+                pl.crouching = *crouching;
+                pl.track_fall(position.y, nw.time.elapsed_secs());
 
                 tr.translation = tr.translation.lerp(*position, nw.time.delta_secs() * 10.0);
                 tr.rotation = Quat::from_array(*rotation);
@@ -98,6 +110,18 @@ pub fn update_world(client_id: u64, message: &ClientMessage, nw: &mut NetWorld)
                 pl.current_weapon = Some((*slot, *row));
             }
         }
+        ClientMessage::Land { speed } => {
+            if nw.current_id.0 != client_id {
+                let player = option_return!(nw.lobby.get(&client_id)).entity;
+                let sound = Player::land_sound(*speed, &nw.movement);
+                nw.commands.entity(player).with_children(|c| {
+                    c.spawn((
+                        AudioPlayer::<AudioSource>(nw.asset_server.load(sound)),
+                        PlaybackSettings::DESPAWN.with_spatial(true),
+                    ));
+                });
+            }
+        }
         x => error!("got event {x:?} from server, this was most likely a bug"),
     }
 }
@@ -106,11 +130,18 @@ pub fn send_messages(
     mut events: EventReader<ClientMessage>,
     client: Option<ResMut<RenetClient>>,
     server: Option<ResMut<RenetServer>>,
+    mut stats: ResMut<NetStats>,
     mut nw: NetWorld,
 ) {
     let mut send: Box<dyn FnMut(ClientMessage)> = if let Some(mut client) = client {
         Box::new(move |message| {
-            client.send_message(ClientChannel::Input as u8, error_return!(message.bytes()));
+            let channel = match message {
+                ClientMessage::Rcon { .. } => ClientChannel::Command,
+                _ => ClientChannel::Input,
+            } as u8;
+            let bytes = error_return!(message.bytes());
+            stats.sent(channel, bytes.len());
+            client.send_message(channel, bytes);
         })
     } else if let Some(mut server) = server {
         Box::new(move |message| {
@@ -141,6 +172,7 @@ pub enum NetState {
 pub enum ClientMessage {
     UpdatePosition {
         position: Vec3,
+        crouching: bool,
         rotation: [f32; 4],
         cam_rot: f32,
     },
@@ -163,6 +195,40 @@ pub enum ClientMessage {
     },
 
     Interact,
+
+    Handshake(Handshake),
+
+    Chat {
+        text: String,
+        team: bool,
+    },
+
+    Spectate {
+        spectating: bool,
+    },
+
+    /// A remote console command, sent over the command channel
+    Rcon {
+        password: String,
+        command: String,
+    },
+
+    /// Picks one of the maps in `MatchState::vote`
+    Vote {
+        choice: usize,
+    },
+
+    /// Landed faster than `Movement::land_speed`, in units per second. The server only trusts
+    /// the host's speed, see `Player::landing_speed`.
+    Land {
+        speed: f32,
+    },
+
+    /// Started reloading the current weapon, see `Player::start_reload`
+    StartReload,
+
+    /// Finished reloading the current weapon, or one round of it, see `Player::finish_reload`
+    Reload,
 }
 impl ClientMessage {
     pub fn bytes(&self) -> Result<Vec<u8>, std::boxed::Box<bincode::ErrorKind>> {
@@ -178,7 +244,7 @@ pub enum SimulationEvent {
     PlayerPicksUpPickup {
         id: u64,
         player: u64,
-        pickup: FastStr,
+        data: PickupData,
     },
 }
 
@@ -202,26 +268,128 @@ pub enum ServerMessage {
         id: u64,
         translation: Vec3,
         data: PickupData,
+        /// False for pickups that were taken and haven't come back yet
+        available: bool,
+    },
+    /// Someone took a pickup, it's hidden until `RespawnPickup`
+    HidePickup {
+        id: u64,
     },
-    DespawnPickup {
+    RespawnPickup {
         id: u64,
     },
     HitscanHits {
         hits: Vec<Vec3>,
     },
-    Hit {
-        amount: f32,
+    /// Clients only show projectiles, the server finds out what they fly into
+    SpawnProjectile {
+        id: u64,
+        projectile: String,
+        owner: u64,
+        weapon: Option<FastStr>,
+        translation: Vec3,
+        dir: Vec3,
+    },
+    ExplodeProjectile {
+        id: u64,
+        projectile: String,
+        translation: Vec3,
+    },
+    /// Knocks the player away from an explosion, in units per second
+    Push {
+        velocity: Vec3,
+    },
+    /// The player's health and armour after being hurt or picking something up
+    SetHealth {
+        health: f32,
+        armour: f32,
+    },
+    /// Sent to a player when they respawn
+    Reset {
+        position: Vec3,
+        /// Rotation around the y axis, in radians
+        yaw: f32,
     },
-    Reset,
     Message {
         text: String,
     },
-    KillStat {
-        death: u64,
-        hurter: Option<u64>,
+    Frag(Frag),
+    /// Sent right before the server disconnects a client
+    Disconnect {
+        reason: String,
+    },
+    Chat {
+        /// None for messages from the server itself
+        name: Option<FastStr>,
+        text: String,
+        team: bool,
+    },
+    PlayerRenamed {
+        id: u64,
+        name: FastStr,
+    },
+    SetSpectating {
+        id: u64,
+        spectating: bool,
+    },
+    MatchState(MatchState),
+    /// The server's movement physics, sent when joining
+    Movement(Movement),
+    SetTeam {
+        id: u64,
+        team: Option<u8>,
+    },
+    /// Everyone's stats, sent every second
+    Scoreboard(Vec<ScoreRow>),
+    /// How much of an ammo type the player has now, after a pickup or a rejected shot
+    SetAmmo {
+        ammo: FastStr,
+        amount: u32,
+    },
+    /// The player picked up a powerup which lasts `time` more seconds
+    SetPowerup {
+        powerup: Powerup,
+        time: f32,
+    },
+    /// How many rounds are in the magazine of a weapon, after a rejected shot or reload
+    SetMagazine {
+        weapon: FastStr,
+        loaded: u32,
     },
 }
 impl ServerMessage {
+    /// The name of the variant, used for the network stats
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::SetMap(_) => "SetMap",
+            Self::SpawnPlayer { .. } => "SpawnPlayer",
+            Self::PlayerUpdate { .. } => "PlayerUpdate",
+            Self::DespawnPlayer { .. } => "DespawnPlayer",
+            Self::SpawnPickup { .. } => "SpawnPickup",
+            Self::HidePickup { .. } => "HidePickup",
+            Self::RespawnPickup { .. } => "RespawnPickup",
+            Self::HitscanHits { .. } => "HitscanHits",
+            Self::SpawnProjectile { .. } => "SpawnProjectile",
+            Self::ExplodeProjectile { .. } => "ExplodeProjectile",
+            Self::Push { .. } => "Push",
+            Self::SetHealth { .. } => "SetHealth",
+            Self::Reset { .. } => "Reset",
+            Self::Message { .. } => "Message",
+            Self::Frag(_) => "Frag",
+            Self::Disconnect { .. } => "Disconnect",
+            Self::Chat { .. } => "Chat",
+            Self::PlayerRenamed { .. } => "PlayerRenamed",
+            Self::SetSpectating { .. } => "SetSpectating",
+            Self::MatchState(_) => "MatchState",
+            Self::Movement(_) => "Movement",
+            Self::SetTeam { .. } => "SetTeam",
+            Self::Scoreboard(_) => "Scoreboard",
+            Self::SetAmmo { .. } => "SetAmmo",
+            Self::SetMagazine { .. } => "SetMagazine",
+            Self::SetPowerup { .. } => "SetPowerup",
+        }
+    }
+
     pub fn bytes(&self) -> Result<Vec<u8>, std::boxed::Box<bincode::ErrorKind>> {
         bincode::serialize(self)
     }
@@ -0,0 +1,321 @@
+use super::{
+    config::ServerSettings, handshake::Admission, server::transmit_message, simulator::NetSimulator,
+};
+use crate::{chat::commands::reply, game_mode, queries::NetWorld};
+use bevy::prelude::*;
+use bevy_renet::{netcode::NetcodeServerTransport, renet::RenetServer};
+use macros::error_return;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+/// Wrong passwords a player can send before they get kicked
+const MAX_FAILED_LOGINS: u32 = 3;
+/// How long wrong passwords are remembered after the last one, reconnecting doesn't reset them
+const FAILED_LOGIN_COOLDOWN: Duration = Duration::from_secs(10 * 60);
+
+/// Who wrong rcon passwords are counted for, the address when it's known and the id otherwise
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum LoginKey {
+    Ip(IpAddr),
+    Id(u64),
+}
+
+const HELP: &str = "rcon commands: status, kick <player> [reason], ban <player> [reason], unban <id, ip or name>, \
+bans, restart, map <name>, set <setting> <value>, say <text>. Put names with spaces in quotes";
+
+/// A remote console command waiting to be run on the server, `client_id` is whoever sent it
+#[derive(Debug, Event)]
+pub struct RconRequest {
+    pub client_id: u64,
+    pub password: String,
+    pub command: String,
+}
+
+/// The rcon password used by this client, set with `/rcon_password` in the chat
+#[derive(Debug, Resource, Default)]
+pub struct RconPassword(pub String);
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Ban {
+    /// The Steam id of the player, or the id their client picked when not using Steam
+    pub id: u64,
+    /// Only known when not using Steam
+    pub ip: Option<IpAddr>,
+    pub name: String,
+    pub reason: String,
+}
+
+/// Players who can't join the server, kept in `ban_file`
+#[derive(Debug, Resource, Default)]
+pub struct BanList {
+    path: PathBuf,
+    bans: Vec<Ban>,
+}
+impl BanList {
+    /// Loads the ban list, or starts an empty one if the file doesn't exist yet
+    pub fn load(path: &Path) -> Self {
+        let bans = match std::fs::read_to_string(path) {
+            Ok(data) => serde_json::from_str(&data).unwrap_or_else(|e| {
+                error!("failed to parse ban list {path:?}: {e}");
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        Self {
+            path: path.to_path_buf(),
+            bans,
+        }
+    }
+
+    fn save(&self) {
+        let data = error_return!(serde_json::to_string_pretty(&self.bans));
+        error_return!(std::fs::write(&self.path, data));
+    }
+
+    pub fn find(&self, id: u64, ip: Option<IpAddr>) -> Option<&Ban> {
+        self.bans
+            .iter()
+            .find(|b| b.id == id || (ip.is_some() && b.ip == ip))
+    }
+
+    fn add(&mut self, ban: Ban) {
+        self.bans.push(ban);
+        self.save();
+    }
+
+    /// Removes the bans matching an id, ip or name
+    fn remove(&mut self, target: &str) -> Vec<Ban> {
+        let (removed, kept) = std::mem::take(&mut self.bans).into_iter().partition(|b| {
+            b.id.to_string() == target
+                || b.ip.is_some_and(|ip| ip.to_string() == target)
+                || b.name.eq_ignore_ascii_case(target)
+        });
+        self.bans = kept;
+        if !removed.is_empty() {
+            self.save();
+        }
+        removed
+    }
+}
+
+/// The address a client connects from. It's unknown with Steam, and behind the net simulator,
+/// which makes everyone come from the loopback address.
+pub fn client_ip(
+    transport: Option<&NetcodeServerTransport>,
+    simulator: Option<&NetSimulator>,
+    client_id: u64,
+) -> Option<IpAddr> {
+    if simulator.is_some() {
+        return None;
+    }
+    transport
+        .and_then(|t| t.client_addr(client_id))
+        .map(|a| a.ip())
+}
+
+/// Finds a player in the lobby by their id or name
+fn find_player(nw: &NetWorld, target: &str) -> Option<u64> {
+    if let Ok(id) = target.parse()
+        && nw.lobby.contains_key(&id)
+    {
+        return Some(id);
+    }
+    nw.lobby
+        .iter()
+        .find(|(_, info)| info.name.eq_ignore_ascii_case(target))
+        .map(|(id, _)| *id)
+}
+
+/// Splits the player or setting off the rest of the arguments,
+/// names with spaces go in quotes, like `kick "big bob" camping`
+fn split_target(args: &str) -> (&str, &str) {
+    if let Some(quoted) = args.strip_prefix('"')
+        && let Some((target, rest)) = quoted.split_once('"')
+    {
+        return (target, rest.trim());
+    }
+    args.split_once(char::is_whitespace)
+        .map(|(t, r)| (t, r.trim()))
+        .unwrap_or((args, ""))
+}
+
+/// Checks the password of rcon requests and runs them.
+/// The host can always use rcon, everyone else needs `rcon_password` from the server settings.
+/// Wrong passwords are counted by address, or by id with Steam, until logging in or the cooldown.
+#[allow(clippy::too_many_arguments)]
+pub fn handle_rcon(
+    mut requests: EventReader<RconRequest>,
+    mut server: ResMut<RenetServer>,
+    mut admission: ResMut<Admission>,
+    mut bans: ResMut<BanList>,
+    transport: Option<Res<NetcodeServerTransport>>,
+    simulator: Option<Res<NetSimulator>>,
+    mut failures: Local<HashMap<LoginKey, (u32, Instant)>>,
+    mut nw: NetWorld,
+) {
+    let ip = |player| client_ip(transport.as_deref(), simulator.as_deref(), player);
+    failures.retain(|_, (_, last)| last.elapsed() < FAILED_LOGIN_COOLDOWN);
+
+    for request in requests.read() {
+        let id = request.client_id;
+        let key = ip(id).map_or(LoginKey::Id(id), LoginKey::Ip);
+        if id != nw.current_id.0 {
+            // Nobody can guess a password that isn't there, so that doesn't count as a try
+            let Some(password) = &nw.settings.rcon_password else {
+                reply(
+                    &mut server,
+                    &mut nw,
+                    id,
+                    "rcon is turned off on this server",
+                );
+                continue;
+            };
+            if *password != request.password {
+                let (failed, last) = failures.entry(key).or_insert((0, Instant::now()));
+                *failed += 1;
+                *last = Instant::now();
+                warn!("client {id} sent a wrong rcon password");
+                if *failed >= MAX_FAILED_LOGINS {
+                    admission.kick(&mut server, id, "too many wrong rcon passwords".to_string());
+                } else {
+                    reply(&mut server, &mut nw, id, "wrong rcon password");
+                }
+                continue;
+            }
+            failures.remove(&key);
+        }
+
+        let (command, args) = request
+            .command
+            .split_once(char::is_whitespace)
+            .map(|(c, a)| (c, a.trim()))
+            .unwrap_or((request.command.as_str(), ""));
+        let (target, rest) = split_target(args);
+        // New passwords stay out of the log
+        match command == "set" && target.contains("password") {
+            true => info!("client {id} ran rcon command set {target} <hidden>"),
+            false => info!("client {id} ran rcon command {}", request.command),
+        }
+
+        match command {
+            "help" => reply(&mut server, &mut nw, id, HELP),
+            "status" => {
+                let lines = nw
+                    .lobby
+                    .iter()
+                    .map(|(player, info)| {
+                        let ip = ip(*player).map(|ip| format!(" {ip}")).unwrap_or_default();
+                        format!(
+                            "{player} {}{ip}: score {}, ping {}",
+                            info.name, info.score, info.ping
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                for line in lines {
+                    reply(&mut server, &mut nw, id, line);
+                }
+            }
+            "kick" | "ban" => {
+                // Without quotes, a name with spaces is the whole line and there's no reason
+                let found = find_player(&nw, target)
+                    .map(|player| (player, rest))
+                    .or_else(|| find_player(&nw, args).map(|player| (player, "")));
+                let Some((player, rest)) = found else {
+                    reply(
+                        &mut server,
+                        &mut nw,
+                        id,
+                        format!("no player called {target}"),
+                    );
+                    continue;
+                };
+                if player == nw.current_id.0 {
+                    reply(&mut server, &mut nw, id, "the host can't be kicked");
+                    continue;
+                }
+                let name = nw
+                    .lobby
+                    .get(&player)
+                    .map(|info| info.name.to_string())
+                    .unwrap_or_default();
+                let reason = if rest.is_empty() { "no reason" } else { rest };
+                if command == "ban" {
+                    bans.add(Ban {
+                        id: player,
+                        ip: ip(player),
+                        name: name.clone(),
+                        reason: reason.to_string(),
+                    });
+                    admission.kick(&mut server, player, format!("banned: {reason}"));
+                    transmit_message(
+                        &mut server,
+                        &mut nw,
+                        format!("{} WAS BANNED", name.to_lowercase()),
+                    );
+                } else {
+                    admission.kick(&mut server, player, format!("kicked: {reason}"));
+                    transmit_message(
+                        &mut server,
+                        &mut nw,
+                        format!("{} WAS KICKED", name.to_lowercase()),
+                    );
+                }
+            }
+            "unban" => {
+                let removed = bans.remove(args.trim_matches('"'));
+                reply(
+                    &mut server,
+                    &mut nw,
+                    id,
+                    format!("removed {} bans", removed.len()),
+                );
+            }
+            "bans" => {
+                let lines = bans
+                    .bans
+                    .iter()
+                    .map(|b| {
+                        let ip = b.ip.map(|ip| format!(" {ip}")).unwrap_or_default();
+                        format!("{} {}{ip}: {}", b.id, b.name, b.reason)
+                    })
+                    .collect::<Vec<_>>();
+                if lines.is_empty() {
+                    reply(&mut server, &mut nw, id, "nobody is banned");
+                }
+                for line in lines {
+                    reply(&mut server, &mut nw, id, line);
+                }
+            }
+            "restart" => {
+                game_mode::restart_match(&mut server, &mut nw);
+                transmit_message(&mut server, &mut nw, "THE MATCH WAS RESTARTED".to_string());
+            }
+            "map" => match game_mode::maps::find_map(args) {
+                Some(map) => game_mode::maps::change_map(&mut server, &mut nw, map),
+                None => reply(&mut server, &mut nw, id, format!("no map called {args}")),
+            },
+            "set" if ServerSettings::startup_only(target) => reply(
+                &mut server,
+                &mut nw,
+                id,
+                format!("{target} can only be set when starting the server"),
+            ),
+            "set" => match nw.settings.set(target, rest) {
+                Ok(()) => reply(&mut server, &mut nw, id, format!("{target} set to {rest}")),
+                Err(e) => reply(&mut server, &mut nw, id, format!("can't set {target}: {e}")),
+            },
+            "say" => transmit_message(&mut server, &mut nw, args.to_uppercase()),
+            _ => reply(
+                &mut server,
+                &mut nw,
+                id,
+                format!("unknown rcon command: {command}, try help"),
+            ),
+        }
+    }
+}
@@ -3,10 +3,21 @@ use super::{
     connection_config, update_world,
 };
 use crate::{
-    entities::hitscan_hit_gfx,
-    net::{CurrentClientId, IsSteam, Lobby, PlayerInfo, ServerChannel, ServerMessage},
+    chat,
+    entities::{hitscan_hit_gfx, projectiles},
+    game_mode::{self, MatchPhase, ctf, damage, items, spawns},
+    kill_feed::{DeathCause, Frag, Hurter},
+    net::{
+        CurrentClientId, IsSteam, Lobby, PlayerInfo, ServerChannel, ServerMessage,
+        config::ServerSettings,
+        discovery::{DISCOVERY_PORT, DiscoveryResponder, answer_discovery},
+        handshake::{Admission, AssetHash, Handshake},
+        rcon::{self, BanList, RconRequest},
+        simulator::{self, NetSimulator},
+    },
     player::Player,
     queries::NetWorld,
+    scoreboard,
 };
 use bevy::{
     ecs::{
@@ -16,8 +27,10 @@ use bevy::{
         world::World,
     },
     hierarchy::DespawnRecursiveExt,
-    log::{error, info},
+    log::{error, info, warn},
+    math::Quat,
     prelude::NextState,
+    transform::components::Transform,
 };
 use bevy_renet::{
     netcode::{NetcodeServerTransport, NetcodeTransportError, ServerAuthentication, ServerConfig},
@@ -25,9 +38,9 @@ use bevy_renet::{
     steam::SteamTransportError,
 };
 use faststr::FastStr;
-use macros::{error_continue, error_return, option_return};
-use qwak_helper_types::MapInteraction;
-use renet_steam::{AccessPermission, SteamServerConfig, SteamServerTransport};
+use macros::{error_continue, error_return, option_continue, option_return};
+use qwak_helper_types::{Frag as QwakFrag, MapInteraction};
+use renet_steam::{SteamServerConfig, SteamServerTransport};
 use resources::{CurrentMap, data::Attack};
 use std::{net::UdpSocket, time::SystemTime};
 use steamworks::SteamId;
@@ -47,152 +60,289 @@ pub fn transmit_message(server: &mut RenetServer, nw: &mut NetWorld, text: Strin
 
 fn frag_checker(server: &mut RenetServer, nw: &mut NetWorld) {
     let mut frags = Vec::new();
-    for (_, mut player, mut trans) in &mut nw.players {
+    for (_, player, trans) in &nw.players {
         if player.health <= 0.0 {
-            player.health = 100.0;
-            player.armour = 0.0;
-
-            if player.id == nw.current_id.0 {
-                trans.translation = nw.player_spawn.0;
-            } else {
-                server.send_message(
-                    player.id,
-                    ServerChannel::ServerMessages as u8,
-                    error_continue!(ServerMessage::Reset.bytes()),
-                );
-            }
-
-            frags.push((player.id, player.last_hurter));
-            player.last_hurter = 0;
+            frags.push((
+                Frag::new(player.id, player.last_hurter.clone()),
+                trans.translation,
+            ));
         }
     }
 
-    for (id, hurter) in frags {
+    for (frag, died_at) in frags {
+        let id = frag.victim;
+        spawns::respawn(server, nw, id);
+        if let Some(team) = ctf::drop_flag(&mut nw.match_state, id, died_at) {
+            transmit_message(
+                server,
+                nw,
+                format!(
+                    "THE {} FLAG WAS DROPPED",
+                    game_mode::TEAM_NAMES[team as usize]
+                ),
+            );
+        }
+        let team = |id: u64| nw.lobby.get(&id).and_then(|i| i.team);
+        let killer_team = frag.attacker.and_then(team);
+        let victim_team = team(id);
+        nw.match_state.team_frag(killer_team, victim_team);
+        // Fragging yourself or your own team costs a point
+        let team_kill = killer_team.is_some() && killer_team == victim_team;
+
+        if let Some(info) = nw.lobby.get_mut(&id) {
+            info.deaths += 1;
+            if frag.attacker.is_none() {
+                info.score -= 1;
+            }
+        }
+        if let Some(attacker) = frag.attacker
+            && let Some(info) = nw.lobby.get_mut(&attacker)
+        {
+            info.kills += 1;
+            info.score += if team_kill { -1 } else { 1 };
+        }
+
         server.broadcast_message(
+            ServerChannel::ServerMessages as u8,
+            error_continue!(ServerMessage::Frag(frag.clone()).bytes()),
+        );
+        expose_to_qwaks(nw, server);
+        error_continue!(nw.plugins.default.player_fragged(QwakFrag(
+            frag.victim,
+            frag.attacker,
+            frag.weapon.as_ref().map(|w| w.to_string()),
+            frag.cause.to_string(),
+        )));
+        nw.frags.send(frag);
+    }
+}
+
+/// Switches a player between playing and spectating, and tells everyone about it
+pub fn set_spectating(
+    server: &mut RenetServer,
+    nw: &mut NetWorld,
+    client_id: u64,
+    spectating: bool,
+) {
+    let info = option_return!(nw.lobby.get_mut(&client_id));
+    if info.spectating == spectating {
+        return;
+    }
+    info.spectating = spectating;
+    let (entity, name) = (info.entity, info.name.clone());
+    Player::set_spectating(&mut nw.commands, entity, spectating);
+
+    // Players come back from spectating fresh, at a spawn
+    if spectating {
+        let (_, mut player, _) = error_return!(nw.players.get_mut(entity));
+        player.reset(&nw.ammo_map);
+    } else {
+        spawns::respawn(server, nw, client_id);
+    }
+
+    server.broadcast_message(
+        ServerChannel::ServerMessages as u8,
+        error_return!(
+            ServerMessage::SetSpectating {
+                id: client_id,
+                spectating
+            }
+            .bytes()
+        ),
+    );
+    let message = if spectating {
+        format!("{} IS NOW SPECTATING", name.to_lowercase())
+    } else {
+        format!("{} JOINED THE GAME", name.to_lowercase())
+    };
+    info!("{message}");
+    transmit_message(server, nw, message);
+}
+
+/// Sends the current state of the game to a client which passed the handshake,
+/// and spawns its player. Returns the join message.
+fn admit_client(
+    server: &mut RenetServer,
+    client_id: u64,
+    handshake: &Handshake,
+    steam: &Option<Res<SteamClient>>,
+    map: &CurrentMap,
+    nw: &mut NetWorld,
+) -> Option<String> {
+    server.send_message(
+        client_id,
+        ServerChannel::ServerMessages as u8,
+        error_return!(ServerMessage::SetMap(map.0.clone()).bytes()),
+    );
+    server.send_message(
+        client_id,
+        ServerChannel::ServerMessages as u8,
+        error_return!(ServerMessage::MatchState(nw.match_state.clone()).bytes()),
+    );
+    server.send_message(
+        client_id,
+        ServerChannel::ServerMessages as u8,
+        error_return!(ServerMessage::Movement(nw.movement.clone()).bytes()),
+    );
+
+    for (pickup, trans) in &nw.pickups_query {
+        server.send_message(
+            client_id,
             ServerChannel::ServerMessages as u8,
             error_continue!(
-                ServerMessage::KillStat {
-                    death: id,
-                    hurter: (hurter != 0).then_some(hurter)
+                ServerMessage::SpawnPickup {
+                    id: pickup.id,
+                    translation: trans.translation,
+                    data: pickup.data.clone(),
+                    available: pickup.available,
+                }
+                .bytes()
+            ),
+        )
+    }
+
+    // Spawn players for newly joined client
+    for (other_id, info) in &nw.lobby {
+        let (_, pl, trans) = error_continue!(nw.players.get(info.entity));
+        server.send_message(
+            client_id,
+            ServerChannel::ServerMessages as u8,
+            error_continue!(
+                ServerMessage::SpawnPlayer {
+                    name: info.name.clone(),
+                    id: *other_id,
+                    translation: trans.translation,
+                    weapons: pl
+                        .weapons
+                        .iter()
+                        .map(|v| v.iter().map(|w| w.data.id.clone()).collect())
+                        .collect()
                 }
                 .bytes()
             ),
         );
-        let id = if let Some(info) = nw.lobby.get_mut(&id) {
-            info.deaths += 1;
-            info.name.clone()
-        } else {
-            format!("{id}").into()
-        };
-        let hurter = if let Some(info) = nw.lobby.get_mut(&hurter) {
-            info.kills += 1;
-            info.name.clone()
-        } else {
-            format!("{hurter}").into()
-        };
-        transmit_message(
-            server,
-            nw,
-            format!(
-                "{} GOT FRAGGED BY {}",
-                id.to_lowercase(),
-                hurter.to_lowercase()
+        if info.spectating {
+            server.send_message(
+                client_id,
+                ServerChannel::ServerMessages as u8,
+                error_continue!(
+                    ServerMessage::SetSpectating {
+                        id: *other_id,
+                        spectating: true
+                    }
+                    .bytes()
+                ),
+            );
+        }
+        if info.team.is_some() {
+            server.send_message(
+                client_id,
+                ServerChannel::ServerMessages as u8,
+                error_continue!(
+                    ServerMessage::SetTeam {
+                        id: *other_id,
+                        team: info.team
+                    }
+                    .bytes()
+                ),
+            );
+        }
+    }
+
+    let spawn = spawns::select_spawn(nw, client_id, None);
+    if !handshake.spectate {
+        spawns::telefrag(nw, client_id, None, spawn.position);
+    }
+    let entity = Player::spawn(
+        nw,
+        false,
+        Transform::from_translation(spawn.position).with_rotation(Quat::from_rotation_y(spawn.yaw)),
+        client_id,
+        Vec::new(),
+        None,
+    );
+    let name = FastStr::from(
+        steam
+            .as_ref()
+            .map(|s| s.friends().get_friend(SteamId::from_raw(client_id)))
+            .map(|f| f.name())
+            .unwrap_or(format!("{client_id}")),
+    );
+    nw.lobby
+        .insert(client_id, PlayerInfo::new(entity, name.clone()));
+
+    let message = format!("PLAYER {} JOINED", name.to_lowercase());
+    info!("{message}");
+
+    server.broadcast_message(
+        ServerChannel::ServerMessages as u8,
+        error_return!(
+            ServerMessage::SpawnPlayer {
+                id: client_id,
+                translation: spawn.position,
+                weapons: Vec::new(),
+                name
+            }
+            .bytes()
+        ),
+    );
+    server.send_message(
+        client_id,
+        ServerChannel::ServerMessages as u8,
+        error_return!(
+            ServerMessage::Reset {
+                position: spawn.position,
+                yaw: spawn.yaw
+            }
+            .bytes()
+        ),
+    );
+    if handshake.spectate {
+        let info = option_return!(nw.lobby.get_mut(&client_id));
+        info.spectating = true;
+        Player::set_spectating(&mut nw.commands, entity, true);
+        server.broadcast_message(
+            ServerChannel::ServerMessages as u8,
+            error_return!(
+                ServerMessage::SetSpectating {
+                    id: client_id,
+                    spectating: true
+                }
+                .bytes()
             ),
         );
     }
+    Some(message)
 }
 
-#[allow(clippy::type_complexity)]
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
 pub fn server_events(
     mut events: EventReader<ServerEvent>,
     mut sim_events: EventReader<SimulationEvent>,
     mut server: ResMut<RenetServer>,
+    mut admission: ResMut<Admission>,
 
     steam: Option<Res<SteamClient>>,
+    bans: Res<BanList>,
+    transport: Option<Res<NetcodeServerTransport>>,
+    simulator: Option<Res<NetSimulator>>,
+    asset_hash: Res<AssetHash>,
     map: Res<CurrentMap>,
     mut nw: NetWorld,
 ) {
     frag_checker(&mut server, &mut nw);
+    admission.update(&mut server);
 
     // Handle connection details
     let mut messages = Vec::new();
     for event in events.read() {
         match event {
             ServerEvent::ClientConnected { client_id } => {
-                server.send_message(
-                    *client_id,
-                    ServerChannel::ServerMessages as u8,
-                    error_return!(ServerMessage::SetMap(map.0.clone()).bytes()),
-                );
-
-                for (pickup, trans) in &nw.pickups_query {
-                    server.send_message(
-                        *client_id,
-                        ServerChannel::ServerMessages as u8,
-                        error_continue!(
-                            ServerMessage::SpawnPickup {
-                                id: pickup.id,
-                                translation: trans.translation,
-                                data: pickup.data.clone()
-                            }
-                            .bytes()
-                        ),
-                    )
-                }
-
-                // Spawn players for newly joined client
-                for (other_id, info) in &nw.lobby {
-                    let (_, pl, trans) = error_continue!(nw.players.get(info.entity));
-                    server.send_message(
-                        *client_id,
-                        ServerChannel::ServerMessages as u8,
-                        error_continue!(
-                            ServerMessage::SpawnPlayer {
-                                name: info.name.clone(),
-                                id: *other_id,
-                                translation: trans.translation,
-                                weapons: pl
-                                    .weapons
-                                    .iter()
-                                    .map(|v| v.iter().map(|w| w.data.id.clone()).collect())
-                                    .collect()
-                            }
-                            .bytes()
-                        ),
-                    );
-                }
-
-                let spawn_point = nw.player_spawn.0;
-                let entity =
-                    Player::spawn(&mut nw, false, spawn_point, *client_id, Vec::new(), None);
-                let name = FastStr::from(
-                    steam
-                        .as_ref()
-                        .map(|s| s.friends().get_friend(SteamId::from_raw(*client_id)))
-                        .map(|f| f.name())
-                        .unwrap_or(format!("{client_id}")),
-                );
-                nw.lobby
-                    .insert(*client_id, PlayerInfo::new(entity, name.clone()));
-
-                let message = format!("PLAYER {} JOINED", name.to_lowercase());
-                info!("{message}");
-                messages.push(message);
-
-                server.broadcast_message(
-                    ServerChannel::ServerMessages as u8,
-                    error_continue!(
-                        ServerMessage::SpawnPlayer {
-                            id: *client_id,
-                            translation: spawn_point,
-                            weapons: Vec::new(),
-                            name
-                        }
-                        .bytes()
-                    ),
-                )
+                info!("client {client_id} connected, waiting for handshake");
+                admission.connected(*client_id);
             }
             ServerEvent::ClientDisconnected { client_id, reason } => {
+                admission.disconnected(*client_id);
                 if let Some(player_info) = nw.lobby.remove(client_id) {
                     nw.commands.entity(player_info.entity).despawn_recursive();
                     let message = format!(
@@ -212,37 +362,70 @@ pub fn server_events(
         }
     }
 
+    let own_handshake = Handshake::new(&asset_hash, "", false);
+    for client_id in server.clients_id() {
+        if !admission.is_pending(client_id) {
+            continue;
+        }
+        let message =
+            option_continue!(server.receive_message(client_id, ClientChannel::Command as u8));
+        let message = error_continue!(ClientMessage::from_bytes(&message));
+        let ClientMessage::Handshake(handshake) = message else {
+            admission.kick(&mut server, client_id, "expected a handshake".to_string());
+            continue;
+        };
+        let ip = rcon::client_ip(transport.as_deref(), simulator.as_deref(), client_id);
+        if let Some(ban) = bans.find(client_id, ip) {
+            info!("rejected banned client {client_id}");
+            let reason = format!("you are banned from this server: {}", ban.reason);
+            admission.kick(&mut server, client_id, reason);
+            continue;
+        }
+        match own_handshake.verify(&handshake, nw.settings.password.as_deref()) {
+            Ok(()) => {
+                admission.admit(client_id);
+                messages.extend(admit_client(
+                    &mut server,
+                    client_id,
+                    &handshake,
+                    &steam,
+                    &map,
+                    &mut nw,
+                ));
+            }
+            Err(reason) => {
+                info!("rejected client {client_id}: {reason}");
+                admission.kick(&mut server, client_id, reason);
+            }
+        }
+    }
+
     for message in messages {
         transmit_message(&mut server, &mut nw, message);
     }
 
     for message in sim_events.read() {
         match message {
-            SimulationEvent::PlayerPicksUpPickup { id, player, pickup } => {
-                let remove_message = ServerMessage::DespawnPickup { id: *id };
+            SimulationEvent::PlayerPicksUpPickup { id, player, data } => {
+                let hide_message = ServerMessage::HidePickup { id: *id };
                 server.broadcast_message(
-                    ServerChannel::NetworkedEntities as u8,
-                    error_continue!(remove_message.bytes()),
+                    ServerChannel::ServerMessages as u8,
+                    error_continue!(hide_message.bytes()),
                 );
-                let pickup_message = ClientMessage::PickupWeapon {
-                    weapon: pickup.clone(),
-                };
-
-                update_world(*player, &pickup_message, &mut nw);
-
-                let pickup_message_wrapped = ServerMessage::PlayerUpdate {
-                    id: *player,
-                    message: pickup_message,
-                };
-
-                let bytes = error_continue!(pickup_message_wrapped.bytes());
-
-                server.broadcast_message(ServerChannel::NetworkedEntities as u8, bytes.clone());
+                items::pick_up(&mut server, &mut nw, *player, data);
             }
         }
     }
 
     for client_id in server.clients_id() {
+        // Messages from clients not in the game yet are dropped
+        if admission.is_pending(client_id) || admission.is_kicked(client_id) {
+            while server
+                .receive_message(client_id, ClientChannel::Input as u8)
+                .is_some()
+            {}
+            continue;
+        }
         while let Some(message) = server.receive_message(client_id, ClientChannel::Input as u8) {
             let message = error_continue!(ClientMessage::from_bytes(&message));
             handle_client_message(&mut server, client_id, message, &mut nw);
@@ -267,7 +450,47 @@ macro_rules! get_nw {
         unsafe { std::ptr::read(r) }
     }};
 }
+/// Makes `nw` and `server` available to the Qwak host functions, call before calling into a plugin
 #[allow(mutable_transmutes)]
+pub fn expose_to_qwaks(nw: &NetWorld, server: &RenetServer) {
+    unsafe {
+        NW_PTR = Some(std::mem::transmute::<
+            (&NetWorld, &RenetServer),
+            (&'static mut NetWorld, &'static mut RenetServer),
+        >((nw, server)))
+    };
+}
+
+/// Tells a player how much ammo is left for both attacks of their current weapon,
+/// and how much is in its magazine, after a shot or reload they couldn't pay for
+fn send_weapon_ammo(server: &mut RenetServer, client_id: u64, player: &Player) {
+    let mut ammo = [1, 2]
+        .into_iter()
+        .filter_map(|attack| player.attack_ammo(attack).map(|(ammo, _)| ammo))
+        .collect::<Vec<_>>();
+    ammo.dedup();
+    let mut messages = ammo
+        .into_iter()
+        .map(|ammo| ServerMessage::SetAmmo {
+            amount: player.ammo.get(&ammo).copied().unwrap_or_default(),
+            ammo,
+        })
+        .collect::<Vec<_>>();
+    if let Some(weapon) = player.magazine() {
+        messages.push(ServerMessage::SetMagazine {
+            weapon: weapon.data.id.clone(),
+            loaded: weapon.loaded,
+        });
+    }
+    for message in messages {
+        server.send_message(
+            client_id,
+            ServerChannel::ServerMessages as u8,
+            error_continue!(message.bytes()),
+        );
+    }
+}
+
 pub fn handle_client_message(
     server: &mut RenetServer,
     client_id: u64,
@@ -275,7 +498,76 @@ pub fn handle_client_message(
     nw: &mut NetWorld,
 ) {
     let rapier_context = nw.rapier_context.single();
+    let spectating = nw.lobby.get(&client_id).is_some_and(|info| info.spectating);
     match message {
+        ClientMessage::Interact
+        | ClientMessage::Fire { .. }
+        | ClientMessage::StartReload
+        | ClientMessage::Reload
+            if spectating => {}
+        ClientMessage::Fire { .. } if nw.match_state.phase == MatchPhase::Intermission => {}
+        // Only the server hands out weapons
+        ClientMessage::PickupWeapon { weapon } => {
+            warn!("client {client_id} tried to pick up {weapon} without touching it");
+        }
+        ClientMessage::Spectate { spectating } => {
+            set_spectating(server, nw, client_id, spectating);
+        }
+        ClientMessage::Rcon { password, command } => {
+            nw.commands.send_event(RconRequest {
+                client_id,
+                password,
+                command,
+            });
+        }
+        ClientMessage::Vote { choice } => game_mode::maps::vote(nw, client_id, choice),
+        // The host has already put the rounds in
+        ClientMessage::StartReload | ClientMessage::Reload if client_id == nw.current_id.0 => {}
+        ClientMessage::StartReload => {
+            let now = nw.time.elapsed_secs();
+            let player = option_return!(nw.lobby.get(&client_id)).entity;
+            let (_, mut player, _) = error_return!(nw.players.get_mut(player));
+            if !player.start_reload(now) {
+                warn!("client {client_id} started reloading without room or ammo for it");
+                send_weapon_ammo(server, client_id, &player);
+            }
+        }
+        ClientMessage::Reload => {
+            let now = nw.time.elapsed_secs();
+            let player = option_return!(nw.lobby.get(&client_id)).entity;
+            let (_, mut player, _) = error_return!(nw.players.get_mut(player));
+            if !player.finish_reload(now) {
+                warn!("client {client_id} finished a reload too soon or without ammo for it");
+                send_weapon_ammo(server, client_id, &player);
+            }
+        }
+        ClientMessage::Land { speed } if !speed.is_finite() => {
+            warn!("client {client_id} landed at {speed} units per second");
+        }
+        ClientMessage::Land { speed } => {
+            // Players move on their own clients, the server works out how fast they landed
+            // from where they said they were, the host's own speed is trusted
+            if !spectating && nw.match_state.phase != MatchPhase::Intermission {
+                let player = option_return!(nw.lobby.get(&client_id)).entity;
+                let (_, mut pl, _) = error_return!(nw.players.get_mut(player));
+                let speed = match client_id == nw.current_id.0 {
+                    true => speed,
+                    false => pl.landing_speed(&nw.movement),
+                };
+                damage::fall(server, nw, player, speed);
+            }
+            update_world(client_id, &message, nw);
+            server.broadcast_message(
+                ServerChannel::NetworkedEntities as u8,
+                error_return!(
+                    ServerMessage::PlayerUpdate {
+                        id: client_id,
+                        message,
+                    }
+                    .bytes()
+                ),
+            );
+        }
         ClientMessage::Interact => {
             let player = option_return!(nw.lobby.get(&client_id)).entity;
             let (player_entity, mut player, trans) = error_return!(nw.players.get_mut(player));
@@ -287,27 +579,36 @@ pub fn handle_client_message(
                 option_return!(player.interact(player_entity, rapier_context, cam_trans, &trans));
             let (_, int) = option_return!(nw.interactables.get(int).ok());
 
-            unsafe {
-                NW_PTR = Some(std::mem::transmute::<
-                    (&NetWorld, &RenetServer),
-                    (&'static mut NetWorld, &'static mut RenetServer),
-                >((&*nw, &*server)))
-            };
+            expose_to_qwaks(nw, server);
             error_return!(
                 nw.plugins
                     .default
                     .map_interact(MapInteraction(int.script.to_string(), client_id))
             );
         }
+        ClientMessage::Handshake(_) => {
+            error!("client {client_id} sent a handshake after being admitted");
+        }
+        ClientMessage::Chat { text, team } => {
+            chat::commands::handle_chat(server, client_id, &text, team, nw);
+        }
         ClientMessage::Fire { attack } => {
-            let mut hit_pos = Vec::new();
-            let mut hit_ents = Vec::new();
-
             let player = option_return!(nw.lobby.get(&client_id)).entity;
             let (player_entity, mut player, trans) = error_return!(nw.players.get_mut(player));
 
             let cam = option_return!(player.children.camera);
             let (_, cam_trans) = error_return!(nw.cameras.get(cam));
+            // Hits push the victims away from where they were shot from
+            let origin = trans.translation + cam_trans.translation;
+
+            // The host has already taken the ammo when shooting, shots stop reloads too
+            if client_id != nw.current_id.0 {
+                player.stop_reload();
+                if !player.use_ammo(attack) {
+                    send_weapon_ammo(server, client_id, &player);
+                    return;
+                }
+            }
 
             let (slot, row) = option_return!(player.current_weapon);
             let attack_weapon = Some(player.weapons[slot][row].data.id.clone());
@@ -323,10 +624,7 @@ pub fn handle_client_message(
                 &nw.projectile_map,
                 &nw.asset_server,
             );
-            for (hit, pos) in hits {
-                hit_pos.push(pos);
-                hit_ents.push(hit);
-            }
+            let hit_pos = hits.iter().map(|(_, pos)| *pos).collect::<Vec<_>>();
 
             let attack_weapon = error_return!(
                 attack_weapon
@@ -338,44 +636,33 @@ pub fn handle_client_message(
                     .get(&attack_weapon)
                     .ok_or_else(|| format!("failed to find weapon {attack_weapon}"))
             );
-            for ent in hit_ents {
-                if let Ok((_, mut hit_player, _)) = nw.players.get_mut(ent) {
-                    hit_player.last_hurter = client_id;
-                    let damage = if attack == 1 {
-                        if let Attack::RayCast {
-                            damage, damage_mod, ..
-                        } = &attack_weapon.attack1
-                        {
-                            damage + (damage_mod * (nw.game_entropy.get_f32() * 2.0 - 1.0))
-                        } else {
-                            error!("weird attack 1");
-                            0.0
-                        }
-                    } else if let Attack::RayCast {
-                        damage, damage_mod, ..
-                    } = &attack_weapon.attack2
-                    {
-                        damage + (damage_mod * (nw.game_entropy.get_f32() * 2.0 - 1.0))
-                    } else {
-                        error!("weird attack 2");
-                        0.0
-                    };
-                    hit_player.health -= damage;
-                    if hit_player.id != nw.current_id.0 {
-                        server.send_message(
-                            hit_player.id,
-                            ServerChannel::NetworkedEntities as u8,
-                            error_continue!(ServerMessage::Hit { amount: damage }.bytes()),
-                        )
-                    } else {
-                        for (_, mut player, _) in &mut nw.players {
-                            if player.id == nw.current_id.0 {
-                                player.health -= damage;
-                                break;
-                            }
-                        }
-                    }
+            let attack_data = match attack {
+                1 => attack_weapon.attack1.clone(),
+                _ => attack_weapon.attack2.clone(),
+            };
+            let weapon = attack_weapon.id.clone();
+            for (ent, pos) in hits {
+                if !nw.players.contains(ent) {
+                    continue;
                 }
+                let Attack::RayCast {
+                    damage,
+                    damage_mod,
+                    knockback,
+                    ..
+                } = &attack_data
+                else {
+                    error!("weird attack {attack}");
+                    continue;
+                };
+                let amount = damage + (damage_mod * (nw.game_entropy.get_f32() * 2.0 - 1.0));
+                let hurter = Hurter {
+                    id: client_id,
+                    weapon: Some(weapon.clone()),
+                    cause: DeathCause::Hitscan,
+                };
+                let knockback = (pos - origin).normalize_or_zero() * *knockback;
+                damage::damage(server, nw, ent, amount, knockback, hurter);
             }
 
             hitscan_hit_gfx(&nw.asset_server, &mut nw.commands, &hit_pos, &nw.particles);
@@ -405,12 +692,13 @@ pub fn init_server(
     next_state: &mut NextState<NetState>,
     steam_client: &Option<Res<SteamClient>>,
 ) -> bool {
+    let settings = world.resource::<ServerSettings>().clone();
     let server = RenetServer::new(connection_config());
 
     if let Some(sc) = steam_client {
         let steam_transport_config = SteamServerConfig {
-            max_clients: 64,
-            access_permission: AccessPermission::Public,
+            max_clients: settings.max_clients,
+            access_permission: settings.steam_access.into(),
         };
 
         let transport = error_return!(SteamServerTransport::new(sc, steam_transport_config));
@@ -420,31 +708,67 @@ pub fn init_server(
         world.insert_resource(CurrentClientId(sc.user().steam_id().raw()))
     } else {
         let current_time = error_return!(SystemTime::now().duration_since(SystemTime::UNIX_EPOCH));
-        let public_addr = error_return!("127.0.0.1:8000".parse());
-        let socket = error_return!(UdpSocket::bind(public_addr));
+        let mut bind_addr = settings.bind_addr();
+        if let Some(conditions) = settings.netsim {
+            let (simulator, server_addr) =
+                error_return!(NetSimulator::for_server(&settings, conditions));
+            world.insert_resource(simulator);
+            bind_addr = server_addr;
+        }
+        let socket = error_return!(UdpSocket::bind(bind_addr));
 
         let server_config = ServerConfig {
             current_time,
-            max_clients: 64,
+            max_clients: settings.max_clients,
             protocol_id: PROTOCOL_ID,
-            public_addresses: vec![public_addr],
+            public_addresses: settings.public_addrs(),
             authentication: ServerAuthentication::Unsecure,
         };
 
         let transport = error_return!(NetcodeServerTransport::new(server_config, socket));
 
+        match DiscoveryResponder::bind((settings.bind_address, DISCOVERY_PORT)) {
+            Ok(responder) => world.insert_resource(responder),
+            Err(e) => error!("failed to start LAN discovery: {e}"),
+        }
+
         world.insert_resource(transport);
         world.insert_resource(CurrentClientId(current_time.as_millis() as u64));
     }
     world.insert_resource(server);
     world.insert_resource(Lobby::default());
+    world.insert_resource(MatchState::new(&settings));
+    world.insert_resource(Admission::default());
+    world.insert_resource(BanList::load(&settings.ban_file));
+    world.insert_resource(settings.load_movement());
     next_state.set(NetState::Server);
-    info!("started server...");
+    info!(
+        "started server \"{}\" on {}...",
+        settings.name,
+        settings.bind_addr()
+    );
     true
 }
 
 pub fn systems() -> SystemConfigs {
-    (server_events,).into_configs()
+    (
+        server_events,
+        game_mode::update_match.after(server_events),
+        scoreboard::push_scoreboard.after(server_events),
+        rcon::handle_rcon.after(server_events),
+        game_mode::maps::finish_map_change,
+        items::respawn_pickups.after(server_events),
+        items::tick_players,
+        // Tells everyone where new projectiles start before they move
+        (projectiles::send_projectiles, projectiles::collide)
+            .chain()
+            .after(server_events),
+        (simulator::cycle_presets, simulator::sync_conditions)
+            .chain()
+            .run_if(resource_exists::<NetSimulator>),
+        answer_discovery,
+    )
+        .into_configs()
 }
 
 pub fn errors() -> SystemConfigs {
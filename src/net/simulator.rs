@@ -0,0 +1,463 @@
+use super::config::ServerSettings;
+use crate::chat::{ChatHistory, ChatLine};
+use bevy::prelude::*;
+use serde::Deserialize;
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    fmt,
+    io::{self, ErrorKind},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    str::FromStr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Extra time a reordered packet is held back, so that the ones after it arrive first
+const REORDER_DELAY: Duration = Duration::from_millis(50);
+/// Clients which haven't sent anything for this long are forgotten
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long the relay sleeps when there is nothing to do
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Named network conditions, cycled through with `F3` on the host
+pub const PRESETS: [(&str, NetConditions); 5] = [
+    ("off", NetConditions::PERFECT),
+    (
+        "lan",
+        NetConditions {
+            latency: 2,
+            jitter: 1,
+            loss: 0.0,
+            reorder: 0.0,
+        },
+    ),
+    (
+        "broadband",
+        NetConditions {
+            latency: 30,
+            jitter: 5,
+            loss: 0.01,
+            reorder: 0.0,
+        },
+    ),
+    (
+        "mobile",
+        NetConditions {
+            latency: 80,
+            jitter: 30,
+            loss: 0.05,
+            reorder: 0.02,
+        },
+    ),
+    (
+        "terrible",
+        NetConditions {
+            latency: 200,
+            jitter: 80,
+            loss: 0.15,
+            reorder: 0.1,
+        },
+    ),
+];
+
+/// How bad the simulated connection is, applied to each direction separately.
+///
+/// Parsed from a preset name, or from `<latency> [jitter] [loss] [reorder]`
+/// with the times in milliseconds and the chances in percent.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(default)]
+pub struct NetConditions {
+    /// Milliseconds every packet is held back
+    pub latency: u32,
+    /// Up to this many milliseconds are randomly added to or taken off the latency
+    pub jitter: u32,
+    /// Chance of a packet getting dropped, between 0 and 1
+    pub loss: f32,
+    /// Chance of a packet arriving after the ones sent after it, between 0 and 1
+    pub reorder: f32,
+}
+impl NetConditions {
+    pub const PERFECT: Self = Self {
+        latency: 0,
+        jitter: 0,
+        loss: 0.0,
+        reorder: 0.0,
+    };
+
+    /// The preset after the one these conditions match, or the first one
+    pub fn next_preset(&self) -> (&'static str, Self) {
+        let index = PRESETS.iter().position(|(_, c)| c == self);
+        PRESETS[index.map(|i| (i + 1) % PRESETS.len()).unwrap_or(0)]
+    }
+
+    /// How long a packet is held back, None if it gets dropped
+    fn delay(&self, rng: &mut Rng) -> Option<Duration> {
+        if rng.next_f32() < self.loss {
+            return None;
+        }
+        let jitter = (rng.next_f32() * 2.0 - 1.0) * self.jitter as f32;
+        let mut delay = Duration::from_secs_f32((self.latency as f32 + jitter).max(0.0) / 1000.0);
+        if rng.next_f32() < self.reorder {
+            delay += REORDER_DELAY;
+        }
+        Some(delay)
+    }
+}
+impl FromStr for NetConditions {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some((_, preset)) = PRESETS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(s))
+        {
+            return Ok(*preset);
+        }
+
+        if s.trim().is_empty() {
+            let presets = PRESETS.map(|(name, _)| name).join(", ");
+            return Err(format!(
+                "expected {presets} or <latency> [jitter] [loss] [reorder]"
+            ));
+        }
+        let mut values = s.split_whitespace();
+        let mut next = |name: &str| {
+            values
+                .next()
+                .map(|v| v.trim_end_matches(['%', 'm', 's']).parse::<f32>())
+                .transpose()
+                .map(|v| v.unwrap_or_default())
+                .map_err(|e| format!("invalid {name}: {e}"))
+        };
+        let latency = next("latency")?;
+        let jitter = next("jitter")?;
+        let loss = next("loss")?;
+        let reorder = next("reorder")?;
+        Ok(Self {
+            latency: latency.max(0.0) as u32,
+            jitter: jitter.max(0.0) as u32,
+            loss: (loss / 100.0).clamp(0.0, 1.0),
+            reorder: (reorder / 100.0).clamp(0.0, 1.0),
+        })
+    }
+}
+impl fmt::Display for NetConditions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "latency {}ms, jitter {}ms, loss {}%, reorder {}%",
+            self.latency,
+            self.jitter,
+            self.loss * 100.0,
+            self.reorder * 100.0
+        )
+    }
+}
+
+/// A small xorshift generator, so that a seed gives the same packets dropped in tests
+struct Rng(u64);
+impl Rng {
+    fn next_f32(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+/// A packet waiting to be sent: when, the order it came in, where to, and the data
+type Delayed = Reverse<(Instant, u64, Relay, Vec<u8>)>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Relay {
+    ToServer(SocketAddr),
+    ToClient(SocketAddr),
+}
+
+/// A UDP relay between the clients and the server which delays, drops and reorders packets.
+///
+/// Clients connect to `listen` like they would to the server. Every client gets a socket of its own
+/// towards `server`, so the server sees them all coming from the loopback address.
+/// The relay stops when this is dropped.
+#[derive(Debug, Resource)]
+pub struct NetSimulator {
+    conditions: Arc<Mutex<NetConditions>>,
+    stop: Arc<AtomicBool>,
+}
+impl NetSimulator {
+    pub fn start(
+        listen: SocketAddr,
+        server: SocketAddr,
+        conditions: NetConditions,
+        seed: u64,
+    ) -> io::Result<Self> {
+        let socket = UdpSocket::bind(listen)?;
+        socket.set_nonblocking(true)?;
+        info!("simulating {conditions} between {listen} and {server}");
+        let simulator = Self {
+            conditions: Arc::new(Mutex::new(conditions)),
+            stop: Arc::new(AtomicBool::new(false)),
+        };
+
+        let (conditions, stop) = (simulator.conditions.clone(), simulator.stop.clone());
+        thread::Builder::new()
+            .name("net simulator".to_string())
+            .spawn(move || {
+                let mut rng = Rng(seed.max(1));
+                let mut relay = RelayState {
+                    socket,
+                    server,
+                    clients: HashMap::new(),
+                    queue: BinaryHeap::new(),
+                    sequence: 0,
+                };
+                while !stop.load(Ordering::Relaxed) {
+                    let conditions = *conditions.lock().unwrap_or_else(|e| e.into_inner());
+                    if let Err(e) = relay.update(&conditions, &mut rng) {
+                        error!("net simulator stopped: {e}");
+                        return;
+                    }
+                    thread::sleep(POLL_INTERVAL);
+                }
+            })?;
+        Ok(simulator)
+    }
+
+    /// Starts a relay on the port the server would use, and returns it with the address
+    /// the server should bind to instead
+    pub fn for_server(
+        settings: &ServerSettings,
+        conditions: NetConditions,
+    ) -> io::Result<(Self, SocketAddr)> {
+        let loopback: IpAddr = match settings.bind_address {
+            IpAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+            IpAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+        };
+        // Claims a free port for the server, the relay only needs to know it
+        let server = UdpSocket::bind((loopback, 0))?.local_addr()?;
+        let seed = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|t| t.as_nanos() as u64)
+            .unwrap_or(1);
+        Ok((
+            Self::start(settings.bind_addr(), server, conditions, seed)?,
+            server,
+        ))
+    }
+
+    pub fn conditions(&self) -> NetConditions {
+        *self.conditions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn set_conditions(&self, conditions: NetConditions) {
+        *self.conditions.lock().unwrap_or_else(|e| e.into_inner()) = conditions;
+    }
+}
+impl Drop for NetSimulator {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+struct RelayState {
+    /// Where the clients send to
+    socket: UdpSocket,
+    server: SocketAddr,
+    /// The socket towards the server for every client, and when the client last sent something
+    clients: HashMap<SocketAddr, (UdpSocket, Instant)>,
+    queue: BinaryHeap<Delayed>,
+    sequence: u64,
+}
+impl RelayState {
+    fn update(&mut self, conditions: &NetConditions, rng: &mut Rng) -> io::Result<()> {
+        let mut buf = [0; 2048];
+        let now = Instant::now();
+
+        loop {
+            let (len, from) = match self.socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(e) if is_transient(&e) => break,
+                Err(e) => return Err(e),
+            };
+            if !self.clients.contains_key(&from) {
+                let upstream = UdpSocket::bind((self.server.ip(), 0))?;
+                upstream.set_nonblocking(true)?;
+                self.clients.insert(from, (upstream, now));
+            }
+            if let Some((_, last_seen)) = self.clients.get_mut(&from) {
+                *last_seen = now;
+            }
+            self.push(conditions, rng, Relay::ToServer(from), &buf[..len]);
+        }
+
+        let mut replies = Vec::new();
+        for (client, (upstream, _)) in &self.clients {
+            loop {
+                match upstream.recv_from(&mut buf) {
+                    Ok((len, _)) => replies.push((*client, buf[..len].to_vec())),
+                    Err(e) if is_transient(&e) => break,
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        for (client, data) in replies {
+            self.push(conditions, rng, Relay::ToClient(client), &data);
+        }
+
+        let now = Instant::now();
+        while let Some(Reverse((at, ..))) = self.queue.peek()
+            && *at <= now
+        {
+            let Some(Reverse((_, _, relay, data))) = self.queue.pop() else {
+                break;
+            };
+            let sent = match relay {
+                Relay::ToServer(client) => match self.clients.get(&client) {
+                    Some((upstream, _)) => upstream.send_to(&data, self.server),
+                    None => continue,
+                },
+                Relay::ToClient(client) => self.socket.send_to(&data, client),
+            };
+            if let Err(e) = sent
+                && !is_transient(&e)
+            {
+                warn!("net simulator failed to send a packet: {e}");
+            }
+        }
+
+        self.clients
+            .retain(|_, (_, last_seen)| now.duration_since(*last_seen) < CLIENT_TIMEOUT);
+        Ok(())
+    }
+
+    fn push(&mut self, conditions: &NetConditions, rng: &mut Rng, relay: Relay, data: &[u8]) {
+        let Some(delay) = conditions.delay(rng) else {
+            return;
+        };
+        self.queue.push(Reverse((
+            Instant::now() + delay,
+            self.sequence,
+            relay,
+            data.to_vec(),
+        )));
+        self.sequence += 1;
+    }
+}
+
+/// Errors which only mean there is nothing to receive right now, or an ICMP message about an
+/// earlier packet that Windows reports on the next receive
+fn is_transient(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::ConnectionReset
+    )
+}
+
+/// Copies the `netsim` setting to the relay, so that it can be changed with rcon `set netsim`
+pub fn sync_conditions(settings: Res<ServerSettings>, simulator: Res<NetSimulator>) {
+    let conditions = settings.netsim.unwrap_or_default();
+    if simulator.conditions() != conditions {
+        info!("simulating {conditions}");
+        simulator.set_conditions(conditions);
+    }
+}
+
+/// `F3` on the host cycles through the presets
+pub fn cycle_presets(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut settings: ResMut<ServerSettings>,
+    mut chat: ResMut<ChatHistory>,
+) {
+    if !keys.just_pressed(KeyCode::F3) {
+        return;
+    }
+    let (name, conditions) = settings.netsim.unwrap_or_default().next_preset();
+    settings.netsim = Some(conditions);
+    chat.push(ChatLine::new(
+        None,
+        format!("network simulation: {name} ({conditions})"),
+        false,
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn relay(conditions: NetConditions) -> (NetSimulator, UdpSocket, UdpSocket) {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let listen = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let simulator =
+            NetSimulator::start(listen, server.local_addr().unwrap(), conditions, 7).unwrap();
+        client.connect(listen).unwrap();
+        for socket in [&server, &client] {
+            socket
+                .set_read_timeout(Some(Duration::from_millis(500)))
+                .unwrap();
+        }
+        (simulator, server, client)
+    }
+
+    #[test]
+    fn parses_presets_and_values() {
+        assert_eq!("off".parse(), Ok(NetConditions::PERFECT));
+        assert_eq!(
+            "100 20 5% 1".parse(),
+            Ok(NetConditions {
+                latency: 100,
+                jitter: 20,
+                loss: 0.05,
+                reorder: 0.01,
+            })
+        );
+        assert!("".parse::<NetConditions>().is_err());
+        assert!("fast".parse::<NetConditions>().is_err());
+    }
+
+    #[test]
+    fn delays_packets_both_ways() {
+        let conditions = NetConditions {
+            latency: 50,
+            ..NetConditions::PERFECT
+        };
+        let (_simulator, server, client) = relay(conditions);
+        let mut buf = [0; 16];
+
+        let sent = Instant::now();
+        client.send(b"ping").unwrap();
+        let (len, from) = server.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"ping");
+        assert!(sent.elapsed() >= Duration::from_millis(50));
+
+        server.send_to(b"pong", from).unwrap();
+        let len = client.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"pong");
+        assert!(sent.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn drops_everything_with_full_loss() {
+        let conditions = NetConditions {
+            loss: 1.0,
+            ..NetConditions::PERFECT
+        };
+        let (simulator, server, client) = relay(conditions);
+        let mut buf = [0; 16];
+        client.send(b"lost").unwrap();
+        assert!(server.recv_from(&mut buf).is_err());
+
+        simulator.set_conditions(NetConditions::PERFECT);
+        client.send(b"found").unwrap();
+        let (len, _) = server.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"found");
+    }
+}
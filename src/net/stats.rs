@@ -0,0 +1,311 @@
+use super::{Lobby, ServerMessage};
+use crate::{
+    chat::{ChatHistory, ChatLine},
+    player::{Player, PlayerController},
+};
+use bevy::prelude::*;
+use bevy_renet::renet::{RenetClient, RenetServer};
+use macros::{error_continue, error_return, option_continue};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::Path,
+    time::{Instant, SystemTime},
+};
+
+pub const NET_STATS_DIR: &str = "netstats";
+/// Upper bounds of the message size buckets in bytes, bigger messages go in one more bucket
+const SIZE_BUCKETS: [usize; 4] = [16, 64, 256, 1024];
+const CLIENT_CHANNELS: [&str; 2] = ["input", "command"];
+const SERVER_CHANNELS: [&str; 2] = ["server_messages", "networked_entities"];
+
+/// Messages and bytes on one channel
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ChannelCount {
+    pub messages: u64,
+    pub bytes: u64,
+}
+impl ChannelCount {
+    fn add(&mut self, bytes: usize) {
+        self.messages += 1;
+        self.bytes += bytes as u64;
+    }
+}
+
+/// Sizes of one kind of `ServerMessage`
+#[derive(Debug, Default, Clone)]
+pub struct VariantStats {
+    pub count: u64,
+    pub bytes: u64,
+    pub max: usize,
+    /// How many messages fell in each of `SIZE_BUCKETS`
+    pub buckets: [u64; SIZE_BUCKETS.len() + 1],
+}
+impl VariantStats {
+    fn add(&mut self, bytes: usize) {
+        self.count += 1;
+        self.bytes += bytes as u64;
+        self.max = self.max.max(bytes);
+        let bucket = SIZE_BUCKETS
+            .iter()
+            .position(|max| bytes <= *max)
+            .unwrap_or(SIZE_BUCKETS.len());
+        self.buckets[bucket] += 1;
+    }
+}
+
+/// What renet reports about a connection
+#[derive(Debug, Default, Clone, Copy)]
+struct Connection {
+    /// Milliseconds
+    rtt: f64,
+    /// Between 0 and 1
+    packet_loss: f64,
+    bytes_in: f64,
+    bytes_out: f64,
+}
+
+/// Network stats of the client, shown with `F4` and logged to `netstats/<time>.csv` with `F5`.
+/// The host only gets the connection of every client from renet.
+#[derive(Debug, Resource, Default)]
+pub struct NetStats {
+    pub overlay: bool,
+    /// Messages sent per `ClientChannel` in the current second
+    sent: [ChannelCount; 2],
+    /// Messages received per `ServerChannel` in the current second
+    received: [ChannelCount; 2],
+    /// The last full second, which is what's shown
+    last_sent: [ChannelCount; 2],
+    last_received: [ChannelCount; 2],
+    /// Received messages by variant since connecting
+    variants: BTreeMap<&'static str, VariantStats>,
+    connections: Vec<(String, Connection)>,
+    second: f32,
+    log: Option<(BufWriter<File>, Instant)>,
+}
+impl NetStats {
+    /// Starts counting from zero for a new connection
+    pub fn reset(&mut self) {
+        *self = Self {
+            overlay: self.overlay,
+            log: self.log.take(),
+            ..default()
+        };
+    }
+
+    pub fn sent(&mut self, channel: u8, bytes: usize) {
+        error_return!(
+            self.sent
+                .get_mut(channel as usize)
+                .ok_or("unknown client channel")
+        )
+        .add(bytes);
+    }
+
+    pub fn received(&mut self, channel: u8, message: &ServerMessage, bytes: usize) {
+        error_return!(
+            self.received
+                .get_mut(channel as usize)
+                .ok_or("unknown server channel")
+        )
+        .add(bytes);
+        self.variants.entry(message.name()).or_default().add(bytes);
+    }
+
+    fn start_log(&mut self) -> std::io::Result<()> {
+        fs::create_dir_all(NET_STATS_DIR)?;
+        let secs = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let path = Path::new(NET_STATS_DIR).join(format!("{secs}.csv"));
+        info!("logging network stats to {path:?}");
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(file, "time,connection,metric,value")?;
+        self.log = Some((file, Instant::now()));
+        Ok(())
+    }
+
+    /// Writes the last second as one row per value
+    fn write_log(&mut self) -> std::io::Result<()> {
+        let Some((file, started)) = &mut self.log else {
+            return Ok(());
+        };
+        let time = started.elapsed().as_secs_f32();
+        for (name, c) in &self.connections {
+            writeln!(file, "{time:.1},{name},rtt,{:.1}", c.rtt)?;
+            writeln!(file, "{time:.1},{name},packet_loss,{:.4}", c.packet_loss)?;
+            writeln!(file, "{time:.1},{name},bytes_in,{:.0}", c.bytes_in)?;
+            writeln!(file, "{time:.1},{name},bytes_out,{:.0}", c.bytes_out)?;
+        }
+        let channels = CLIENT_CHANNELS
+            .iter()
+            .zip(&self.last_sent)
+            .map(|(name, count)| ("sent", name, count))
+            .chain(
+                SERVER_CHANNELS
+                    .iter()
+                    .zip(&self.last_received)
+                    .map(|(name, count)| ("received", name, count)),
+            );
+        for (direction, name, count) in channels {
+            writeln!(
+                file,
+                "{time:.1},,{direction}.{name}.messages,{}",
+                count.messages
+            )?;
+            writeln!(file, "{time:.1},,{direction}.{name}.bytes,{}", count.bytes)?;
+        }
+        for (name, variant) in &self.variants {
+            writeln!(file, "{time:.1},,variant.{name}.count,{}", variant.count)?;
+            writeln!(file, "{time:.1},,variant.{name}.bytes,{}", variant.bytes)?;
+            let bounds = SIZE_BUCKETS.map(|max| format!("up_to_{max}"));
+            let bounds = bounds.iter().map(String::as_str).chain(["bigger"]);
+            for (bound, count) in bounds.zip(variant.buckets) {
+                writeln!(file, "{time:.1},,variant.{name}.{bound},{count}")?;
+            }
+        }
+        file.flush()
+    }
+
+    fn text(&self) -> String {
+        let mut lines = Vec::new();
+        for (name, c) in &self.connections {
+            lines.push(format!(
+                "{name}RTT {:.0} MS  LOSS {:.1}%  IN {:.1} KB/S  OUT {:.1} KB/S",
+                c.rtt,
+                c.packet_loss * 100.0,
+                c.bytes_in / 1024.0,
+                c.bytes_out / 1024.0
+            ));
+        }
+        let channel = |name: &str, count: &ChannelCount| {
+            format!(
+                "{} {}/S {:.1} KB/S",
+                name.replace('_', " ").to_uppercase(),
+                count.messages,
+                count.bytes as f32 / 1024.0
+            )
+        };
+        let sent = CLIENT_CHANNELS
+            .iter()
+            .zip(&self.last_sent)
+            .map(|(name, count)| channel(name, count));
+        lines.push(format!("SENT  {}", sent.collect::<Vec<_>>().join("  ")));
+        let received = SERVER_CHANNELS
+            .iter()
+            .zip(&self.last_received)
+            .map(|(name, count)| channel(name, count));
+        lines.push(format!(
+            "RECEIVED  {}",
+            received.collect::<Vec<_>>().join("  ")
+        ));
+
+        let buckets = SIZE_BUCKETS.map(|max| format!("<={max}")).join(" ");
+        lines.push(format!("MESSAGE COUNT AVG MAX  {buckets} >"));
+        for (name, v) in &self.variants {
+            let buckets = v.buckets.map(|b| b.to_string()).join(" ");
+            lines.push(format!(
+                "{name} {} {} {}  {buckets}",
+                v.count,
+                v.bytes / v.count.max(1),
+                v.max
+            ));
+        }
+        lines.join("\n")
+    }
+}
+
+/// `F4` shows the overlay, `F5` starts and stops logging
+pub fn toggle_net_stats(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut stats: ResMut<NetStats>,
+    mut chat: ResMut<ChatHistory>,
+) {
+    if keys.just_pressed(KeyCode::F4) {
+        stats.overlay = !stats.overlay;
+    }
+    if keys.just_pressed(KeyCode::F5) {
+        let text = if stats.log.take().is_some() {
+            "stopped logging network stats".to_string()
+        } else {
+            match stats.start_log() {
+                Ok(()) => format!("logging network stats to {NET_STATS_DIR}"),
+                Err(e) => format!("can't log network stats: {e}"),
+            }
+        };
+        chat.push(ChatLine::new(None, text, false));
+    }
+}
+
+/// Moves on to the next second, asking renet about the connections
+pub fn update_net_stats(
+    time: Res<Time>,
+    client: Option<Res<RenetClient>>,
+    server: Option<Res<RenetServer>>,
+    lobby: Res<Lobby>,
+    mut stats: ResMut<NetStats>,
+) {
+    stats.second += time.delta_secs();
+    if stats.second < 1.0 {
+        return;
+    }
+    stats.second = 0.0;
+
+    stats.connections.clear();
+    if let Some(client) = client {
+        let info = client.network_info();
+        stats.connections.push((
+            String::new(),
+            Connection {
+                rtt: info.rtt,
+                packet_loss: info.packet_loss,
+                bytes_in: info.bytes_received_per_second,
+                bytes_out: info.bytes_sent_per_second,
+            },
+        ));
+    } else if let Some(server) = server {
+        for id in server.clients_id() {
+            let info = error_continue!(server.network_info(id));
+            let name = lobby
+                .get(&id)
+                .map(|info| info.name.to_lowercase())
+                .unwrap_or_else(|| id.to_string());
+            stats.connections.push((
+                format!("{name}: "),
+                Connection {
+                    rtt: info.rtt,
+                    packet_loss: info.packet_loss,
+                    bytes_in: info.bytes_received_per_second,
+                    bytes_out: info.bytes_sent_per_second,
+                },
+            ));
+        }
+    }
+    stats.last_sent = std::mem::take(&mut stats.sent);
+    stats.last_received = std::mem::take(&mut stats.received);
+
+    if let Err(e) = stats.write_log() {
+        error!("failed to log network stats: {e}");
+        stats.log = None;
+    }
+}
+
+pub fn update_net_hud(
+    stats: Res<NetStats>,
+    players: Query<&Player, With<PlayerController>>,
+    mut hud: Query<(&mut Text, &mut Visibility)>,
+) {
+    for player in &players {
+        let net_hud = option_continue!(player.children.net_hud);
+        let (mut text, mut visibility) = error_continue!(hud.get_mut(net_hud));
+        *visibility = match stats.overlay {
+            true => Visibility::Visible,
+            false => Visibility::Hidden,
+        };
+        if stats.overlay {
+            text.0 = stats.text();
+        }
+    }
+}
@@ -3,9 +3,9 @@ use bevy::{log::error, prelude::*, render::render_asset::RenderAssetUsages};
 use image::{DynamicImage, ImageBuffer};
 use macros::{error_return, option_return};
 use std::sync::mpsc;
-use steamworks::{Client, SingleClient};
+use steamworks::{Client, SingleClient, SteamId};
 
-#[derive(Resource)]
+#[derive(Resource, Clone)]
 pub struct SteamClient {
     client: Client,
 }
@@ -51,22 +51,28 @@ pub fn grab_avatar(
     mut images: ResMut<Assets<Image>>,
 ) {
     let client = option_return!(client);
-    let avatar = option_return!(client
-        .friends()
-        .get_friend(client.user().steam_id())
-        .small_avatar());
+    let image = option_return!(avatar_image(&client, client.user().steam_id(), &mut images));
+
+    commands.insert_resource(CurrentAvatar(image));
+}
+
+/// Loads the small avatar of a Steam user, None if Steam doesn't have it (yet)
+pub fn avatar_image(
+    client: &Client,
+    id: SteamId,
+    images: &mut Assets<Image>,
+) -> Option<Handle<Image>> {
+    let avatar = client.friends().get_friend(id).small_avatar()?;
 
     let dyn_img = DynamicImage::ImageRgba8(error_return!(
         ImageBuffer::from_raw(32, 32, avatar).ok_or("failed to parse avatar data")
     ));
 
-    let image = images.add(Image::from_dynamic(
+    Some(images.add(Image::from_dynamic(
         dyn_img,
         false,
         RenderAssetUsages::RENDER_WORLD,
-    ));
-
-    commands.insert_resource(CurrentAvatar(image));
+    )))
 }
 
 #[derive(Debug, Resource)]
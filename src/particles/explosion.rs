@@ -0,0 +1,57 @@
+use bevy::prelude::*;
+use bevy_hanabi::prelude::*;
+
+/// A burst of fire flying out from where a projectile blew up
+pub fn setup(effects: &mut Assets<EffectAsset>) -> Handle<EffectAsset> {
+    // From bright yellow over orange to transparent black
+    let mut gradient = Gradient::new();
+    gradient.add_key(0.0, Vec4::new(4.0, 3.0, 1.0, 1.0));
+    gradient.add_key(0.3, Vec4::new(2.0, 0.6, 0.1, 1.0));
+    gradient.add_key(1.0, Vec4::splat(0.0));
+
+    let mut module = Module::default();
+
+    let init_pos = SetPositionSphereModifier {
+        center: module.lit(Vec3::ZERO),
+        radius: module.lit(0.1),
+        dimension: ShapeDimension::Volume,
+    };
+
+    // Flying away from the centre, slowed down by the drag below
+    let init_vel = SetVelocitySphereModifier {
+        center: module.lit(Vec3::ZERO),
+        speed: module.lit(3.0),
+    };
+
+    let lifetime = module.lit(0.6);
+    let init_lifetime = SetAttributeModifier::new(Attribute::LIFETIME, lifetime);
+
+    let drag = module.lit(4.0);
+    let update_drag = LinearDragModifier::new(drag);
+
+    let texture_slot = module.lit(0u32);
+    module.add_texture_slot("color");
+
+    // All particles at once, the entity is removed by its `ParticleLifetime`
+    let effect = EffectAsset::new(128, Spawner::once(128.0.into(), true), module)
+        .with_name("Explosion")
+        .init(init_pos)
+        .init(init_vel)
+        .init(init_lifetime)
+        .update(update_drag)
+        .render(ColorOverLifetimeModifier { gradient })
+        .render(ParticleTextureModifier {
+            texture_slot,
+            sample_mapping: ImageSampleMapping::ModulateOpacityFromR,
+        })
+        .render(OrientModifier {
+            mode: OrientMode::FaceCameraPosition,
+            rotation: None,
+        })
+        .render(SizeOverLifetimeModifier {
+            gradient: Gradient::linear(Vec3::splat(0.3), Vec3::splat(0.05)),
+            screen_space_size: false,
+        });
+
+    effects.add(effect)
+}
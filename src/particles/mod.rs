@@ -14,12 +14,14 @@ use bevy_hanabi::{EffectAsset, EffectMaterial, ParticleEffect, ParticleEffectBun
 
 mod bullet_hit;
 mod demo;
+mod explosion;
 
 #[allow(unused)]
 #[derive(Resource)]
 pub struct ParticleMap {
     pub demo: Handle<EffectAsset>,
     pub bullet_hit: Handle<EffectAsset>,
+    pub explosion: Handle<EffectAsset>,
 }
 
 impl ParticleMap {
@@ -47,6 +49,20 @@ impl ParticleMap {
                 images: vec![texture_handle],
             });
     }
+
+    pub fn spawn_explosion(&self, asset_server: &AssetServer, commands: &mut Commands, pos: Vec3) {
+        let texture_handle = asset_server.load("particles/bullethit.png");
+        commands
+            .spawn(ParticleEffectBundle {
+                effect: ParticleEffect::new(self.explosion.clone_weak()),
+                transform: Transform::from_translation(pos),
+                ..Default::default()
+            })
+            .insert(ParticleLifetime::new(1.0))
+            .insert(EffectMaterial {
+                images: vec![texture_handle],
+            });
+    }
 }
 
 #[derive(Component)]
@@ -80,6 +96,7 @@ pub fn register_particles(
     let map = ParticleMap {
         demo: demo::setup(&mut effects),
         bullet_hit: bullet_hit::setup(&mut effects),
+        explosion: explosion::setup(&mut effects),
     };
 
     commands.insert_resource(map);
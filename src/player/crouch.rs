@@ -0,0 +1,87 @@
+use super::{CROUCH_HALF_HEIGHT, Player, PlayerController, PlayerMpModel, STAND_HALF_HEIGHT};
+use bevy::prelude::*;
+use bevy_rapier3d::{pipeline::QueryFilter, plugin::RapierContext};
+use macros::error_return;
+use resources::inputs::PlayerInput;
+
+impl Player {
+    /// Crouches the own player while crouch is held, and stands them back up once there's room.
+    /// Feet stay where they are on the ground, the head stays where it is in the air.
+    pub fn crouch(
+        keys: Res<PlayerInput>,
+        rapier_context: Query<&RapierContext>,
+        mut query: Query<(Entity, &mut Player, &mut Transform), With<PlayerController>>,
+    ) {
+        let rapier_context = error_return!(rapier_context.get_single());
+        for (entity, mut player, mut trans) in &mut query {
+            let crouching = keys.crouch_pressed && !player.spectating;
+            if crouching == player.crouching {
+                continue;
+            }
+            let difference = STAND_HALF_HEIGHT - CROUCH_HALF_HEIGHT;
+            let shift = match crouching == player.on_ground {
+                true => -difference,
+                false => difference,
+            };
+
+            // The crouching collider fits inside the standing one, standing up needs headroom.
+            // Players who land with their legs pulled up stand up on the ground if they fit.
+            let shift = match crouching {
+                true => shift,
+                false => {
+                    let filter = QueryFilter::new()
+                        .exclude_sensors()
+                        .exclude_collider(entity);
+                    let fits = |shift: f32| {
+                        rapier_context
+                            .intersection_with_shape(
+                                trans.translation + Vec3::Y * shift,
+                                Quat::IDENTITY,
+                                &Player::collider(),
+                                filter,
+                            )
+                            .is_none()
+                    };
+                    match [shift, -shift].into_iter().find(|shift| fits(*shift)) {
+                        Some(shift) => shift,
+                        None => continue,
+                    }
+                }
+            };
+
+            trans.translation.y += shift;
+            // The camera moves with the player, it catches up with the new height over time
+            player.camera_movement.eye_height -= shift;
+            player.crouching = crouching;
+        }
+    }
+
+    /// Swaps the collider of every player who started or stopped crouching,
+    /// and squashes the models of the other players to match
+    pub fn update_crouch(
+        mut commands: Commands,
+        mut players: Query<(Entity, &mut Player, &Children)>,
+        mut models: Query<&mut Transform, With<PlayerMpModel>>,
+    ) {
+        for (entity, mut player, children) in &mut players {
+            let half_height = match player.crouching {
+                true => CROUCH_HALF_HEIGHT,
+                false => STAND_HALF_HEIGHT,
+            };
+            if player.half_height == half_height {
+                continue;
+            }
+            player.half_height = half_height;
+            commands
+                .entity(entity)
+                .insert(Self::collider_of(half_height));
+
+            let mut models = models.iter_many_mut(children);
+            while let Some(mut trans) = models.fetch_next() {
+                // The model stands on the bottom of the collider
+                trans.translation.y = -half_height;
+                trans.scale.y = trans.scale.x * half_height / STAND_HALF_HEIGHT;
+            }
+        }
+    }
+}
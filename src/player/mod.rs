@@ -1,13 +1,21 @@
 use std::collections::HashMap;
 
 use bevy::prelude::*;
+use bevy_rapier3d::geometry::Collider;
 use faststr::FastStr;
-use resources::data::WeaponData;
+use resources::{
+    AmmoMap,
+    data::{AmmoData, Movement, Powerup, WeaponData},
+};
 
-use crate::entities::message::Message;
+use crate::{entities::message::Message, kill_feed::Hurter};
 
+mod crouch;
 mod debug;
+mod movement;
+mod reload;
 mod spawn;
+mod spectate;
 mod update;
 
 #[derive(Component, Debug)]
@@ -19,6 +27,10 @@ pub struct PlayerController;
 #[derive(Debug, Component)]
 pub struct PlayerMpModel;
 
+/// Root of the own player's HUD, which is not a child of the player
+#[derive(Debug, Component)]
+pub struct PlayerHud;
+
 #[derive(Debug)]
 pub struct CameraMovement {
     backdrift: f32,
@@ -34,6 +46,10 @@ pub struct CameraMovement {
     cam_rot_current: f32,
 
     switch_offset: f32,
+
+    /// How high the camera sits above the middle of the player, moves towards the crouching
+    /// or standing height
+    eye_height: f32,
 }
 
 #[derive(Debug)]
@@ -43,20 +59,51 @@ pub struct WeaponState {
     anim_time: f32,
     need_to_reload: bool,
     reload_timer: f32,
+    /// Rounds in the magazine, see `WeaponData::magazine`
+    pub loaded: u32,
+    /// Seconds until the rounds being reloaded go in
+    reloading: Option<f32>,
+    /// Server time the reload, or the round going in now, started, see `Player::finish_reload`
+    reload_started: Option<f32>,
     pub data: WeaponData,
 }
 
+impl WeaponState {
+    /// Fills the magazine from the player's `ammo`, by a single round or as far as it goes
+    fn load(&mut self, ammo: &mut HashMap<FastStr, u32>, single: bool) -> u32 {
+        let reserve = match self.data.magazine_ammo() {
+            Some(id) => ammo.entry(id.clone()).or_default(),
+            None => return 0,
+        };
+        let room = self.data.magazine.saturating_sub(self.loaded);
+        let rounds = match single {
+            true => room.min(1),
+            false => room,
+        }
+        .min(*reserve);
+        *reserve -= rounds;
+        self.loaded += rounds;
+        rounds
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct PlayerChildren {
     pub camera: Option<Entity>,
     pub fps_model: Option<Entity>,
     pub health_hud: Option<Entity>,
     pub armour_hud: Option<Entity>,
+    pub powerup_hud: Option<Entity>,
     pub ammo_hud: Option<Entity>,
     pub debug_hud: Option<Entity>,
+    pub net_hud: Option<Entity>,
     pub message_holder: Option<Entity>,
+    pub chat_hud: Option<Entity>,
+    pub chat_input: Option<Entity>,
     pub shoot_sound_holder: Option<Entity>,
     pub lobby_hud: Option<Entity>,
+    pub match_hud: Option<Entity>,
+    pub kill_feed: Option<Entity>,
 }
 
 #[derive(Debug, Default)]
@@ -66,6 +113,15 @@ pub struct DebugInfo {
     pub last_airtime: f32,
 }
 
+/// How the server follows a remote player's falls from their position updates
+#[derive(Debug, Default)]
+struct FallTracker {
+    /// Height and server time of the last position update
+    last: Option<(f32, f32)>,
+    /// Height and server time the player started going down at
+    start: Option<(f32, f32)>,
+}
+
 #[derive(Component, Debug, Default)]
 pub struct PlayerFpsMaterial(Handle<StandardMaterial>);
 
@@ -85,19 +141,34 @@ pub struct Player {
     fps_anims: HashMap<FastStr, u32>,
 
     pub id: u64,
-    pub last_hurter: u64,
+    pub last_hurter: Hurter,
 
     pub health: f32,
     pub armour: f32,
+    /// The part of the damage the armour takes
+    pub armour_absorb: f32,
+    /// Seconds left of each powerup the player has
+    pub powerups: HashMap<Powerup, f32>,
+    /// How much the player carries of each `AmmoData`
+    pub ammo: HashMap<FastStr, u32>,
 
+    /// Spectators fly around freely and can't be hurt
+    pub spectating: bool,
+    /// Sent along with the position, so that everyone sees it
+    pub crouching: bool,
+    /// The player a spectator is watching through, only used for the own player
+    pub following: Option<u64>,
+
+    /// In units per second, the physics are in the `Movement` resource
     velocity: Vec3,
-    hort_speed: f32,
-    hort_max_speed: f32,
-    hort_friction: f32,
-    jump_height: f32,
-    jump_timer: f32,
-    gravity: f32,
+    /// Seconds left to jump on landing, see `Movement::jump_buffer`
+    jump_buffer: f32,
     on_ground: bool,
+    /// Inside a `Ladder`, climbing instead of walking
+    on_ladder: bool,
+    /// Jumped off a ladder and still touching it
+    left_ladder: bool,
+    fall: FallTracker,
 
     camera_movement: CameraMovement,
 
@@ -121,18 +192,22 @@ impl Default for Player {
     fn default() -> Self {
         Self {
             id: 0,
-            last_hurter: 0,
+            last_hurter: Hurter::default(),
             health: 100.0,
-            armour: 100.0,
+            armour: 0.0,
+            armour_absorb: 0.0,
+            powerups: HashMap::new(),
+            ammo: HashMap::new(),
+            spectating: false,
+            crouching: false,
+            following: None,
             velocity: Vec3::ZERO,
-            hort_friction: 1.0,
-            hort_speed: 4.5,
-            hort_max_speed: 0.4,
-            jump_height: 0.12,
-            jump_timer: 0.0,
-            gravity: 4.5,
+            jump_buffer: 0.0,
             on_ground: false,
-            half_height: 0.5,
+            on_ladder: false,
+            left_ladder: false,
+            fall: FallTracker::default(),
+            half_height: STAND_HALF_HEIGHT,
             radius: 0.15,
             air_time: None,
             current_weapon: None,
@@ -158,12 +233,210 @@ impl Default for Player {
                 bob_goal: 0.0,
 
                 switch_offset: 0.0,
+
+                eye_height: STAND_EYE_HEIGHT,
             },
             debug_info: Default::default(),
         }
     }
 }
 impl Player {
+    /// The shape every standing player collides with
+    pub fn collider() -> Collider {
+        Self::collider_of(STAND_HALF_HEIGHT)
+    }
+
+    fn collider_of(half_height: f32) -> Collider {
+        Collider::cylinder(half_height, 0.15)
+    }
+
+    /// The ammo every player spawns with
+    pub fn start_ammo(ammo_map: &AmmoMap) -> HashMap<FastStr, u32> {
+        ammo_map
+            .0
+            .values()
+            .map(|ammo| (ammo.id.clone(), ammo.start))
+            .collect()
+    }
+
+    /// Back to full health, no armour or powerups and the starting ammo, used when respawning
+    pub fn reset(&mut self, ammo_map: &AmmoMap) {
+        self.health = MAX_HEALTH;
+        self.armour = 0.0;
+        self.armour_absorb = 0.0;
+        self.powerups.clear();
+        self.last_hurter = Hurter::default();
+        self.ammo = Self::start_ammo(ammo_map);
+        for weapon in self.weapons.iter_mut().flatten() {
+            weapon.loaded = 0;
+            weapon.reloading = None;
+            weapon.reload_started = None;
+        }
+        self.fill_magazines();
+        self.velocity = Vec3::ZERO;
+        self.fall = FallTracker::default();
+        self.crouching = false;
+    }
+
+    /// Knocks the player away, in units per second
+    pub fn push(&mut self, velocity: Vec3) {
+        self.velocity += velocity;
+    }
+
+    /// Takes damage, the armour takes its part first
+    pub fn take_damage(&mut self, amount: f32) {
+        let saved = (amount * self.armour_absorb).min(self.armour);
+        self.armour -= saved;
+        self.health -= amount - saved;
+    }
+
+    /// Heals up to `limit`, false if the player already has that much
+    pub fn give_health(&mut self, amount: f32, limit: f32) -> bool {
+        if self.health >= limit {
+            return false;
+        }
+        self.health = (self.health + amount).min(limit);
+        true
+    }
+
+    /// Swaps the armour for the new one if it protects more, false if it doesn't
+    pub fn give_armour(&mut self, amount: f32, absorb: f32) -> bool {
+        if self.armour * self.armour_absorb >= amount * absorb {
+            return false;
+        }
+        self.armour = amount;
+        self.armour_absorb = absorb;
+        true
+    }
+
+    /// Picking up a powerup the player already has adds to the time left
+    pub fn give_powerup(&mut self, powerup: Powerup, duration: f32) {
+        *self.powerups.entry(powerup).or_default() += duration;
+    }
+
+    /// What the player's damage is multiplied by
+    pub fn damage_scale(&self) -> f32 {
+        self.powerups.keys().map(Powerup::damage_scale).product()
+    }
+
+    /// What the player's movement and firing speed are multiplied by
+    pub fn speed_scale(&self) -> f32 {
+        self.powerups.keys().map(Powerup::speed_scale).product()
+    }
+
+    /// Counts down the powerups, and takes away the ones that ran out
+    pub fn tick_powerups(&mut self, delta: f32) {
+        for time in self.powerups.values_mut() {
+            *time -= delta;
+        }
+        self.powerups.retain(|_, time| *time > 0.0);
+    }
+
+    /// The ammo attack 1 or 2 of the current weapon uses and how much, None if it's free
+    pub fn attack_ammo(&self, attack: usize) -> Option<(FastStr, u32)> {
+        let (slot, row) = self.current_weapon?;
+        let data = &self.weapons[slot].get(row)?.data;
+        let attack = match attack {
+            1 => &data.attack1,
+            2 => &data.attack2,
+            _ => return None,
+        };
+        attack.ammo().map(|(ammo, cost)| (ammo.clone(), cost))
+    }
+
+    /// Whether the current weapon can pay for an attack, from its magazine if it has one
+    pub fn has_ammo(&self, attack: usize) -> bool {
+        self.attack_ammo(attack)
+            .is_none_or(|(ammo, cost)| match self.magazine() {
+                Some(weapon) => weapon.loaded >= cost,
+                None => self.ammo.get(&ammo).copied().unwrap_or(0) >= cost,
+            })
+    }
+
+    /// Takes the ammo for an attack, false if there isn't enough
+    pub fn use_ammo(&mut self, attack: usize) -> bool {
+        if !self.has_ammo(attack) {
+            return false;
+        }
+        let (ammo, cost) = match self.attack_ammo(attack) {
+            Some(ammo) => ammo,
+            None => return true,
+        };
+        match self.magazine_mut() {
+            Some(weapon) => weapon.loaded -= cost,
+            None => *self.ammo.entry(ammo).or_default() -= cost,
+        }
+        true
+    }
+
+    /// The current weapon if it has a magazine
+    pub fn magazine(&self) -> Option<&WeaponState> {
+        let (slot, row) = self.current_weapon?;
+        self.weapons[slot]
+            .get(row)
+            .filter(|weapon| weapon.data.magazine > 0)
+    }
+
+    fn magazine_mut(&mut self) -> Option<&mut WeaponState> {
+        let (slot, row) = self.current_weapon?;
+        self.weapons[slot]
+            .get_mut(row)
+            .filter(|weapon| weapon.data.magazine > 0)
+    }
+
+    /// Whether the current weapon has room in its magazine and there's ammo to fill it with
+    pub fn can_reload(&self) -> bool {
+        let weapon = match self.magazine() {
+            Some(weapon) => weapon,
+            None => return false,
+        };
+        let reserve = weapon
+            .data
+            .magazine_ammo()
+            .and_then(|ammo| self.ammo.get(ammo))
+            .copied()
+            .unwrap_or_default();
+        weapon.loaded < weapon.data.magazine && reserve > 0
+    }
+
+    /// Fills up every magazine, players spawn with loaded weapons on the server and clients alike
+    fn fill_magazines(&mut self) {
+        for weapon in self.weapons.iter_mut().flatten() {
+            weapon.load(&mut self.ammo, false);
+        }
+    }
+
+    /// Moves ammo into the current weapon's magazine, a single round for weapons that reload
+    /// one at a time, and returns how many rounds went in
+    pub fn load_magazine(&mut self) -> u32 {
+        let (slot, row) = match self.current_weapon {
+            Some(current) => current,
+            None => return 0,
+        };
+        match self.weapons[slot].get_mut(row) {
+            Some(weapon) => weapon.load(&mut self.ammo, weapon.data.reload_single),
+            None => 0,
+        }
+    }
+
+    pub fn can_take_ammo(&self, ammo: &AmmoData) -> bool {
+        self.ammo.get(&ammo.id).copied().unwrap_or(0) < ammo.max
+    }
+
+    /// Adds ammo up to its maximum, false if the player can't carry any more
+    pub fn give_ammo(&mut self, ammo: &AmmoData, amount: u32) -> bool {
+        if !self.can_take_ammo(ammo) {
+            return false;
+        }
+        let current = self.ammo.entry(ammo.id.clone()).or_default();
+        *current = (*current + amount).min(ammo.max);
+        true
+    }
+
+    pub fn has_weapon(&self, id: &str) -> bool {
+        self.weapons.iter().flatten().any(|w| w.data.id == id)
+    }
+
     pub fn add_weapon(&mut self, data: WeaponData, slot: usize, mesh: Handle<Scene>) -> bool {
         if !self.weapons[slot].iter().any(|c| c.data.id == data.id) {
             self.weapons[slot].push(WeaponState {
@@ -171,6 +444,9 @@ impl Player {
                 data,
                 mesh,
                 reload_timer: 0.0,
+                loaded: 0,
+                reloading: None,
+                reload_started: None,
                 timer: 0.0,
                 anim_time: 0.0,
             });
@@ -209,7 +485,61 @@ impl Player {
             info!("Got message: {message}")
         }
     }
+
+    /// Follows a remote player's `height` on the server, a fall starts where they stop going up
+    pub fn track_fall(&mut self, height: f32, now: f32) {
+        match self.fall.last {
+            Some((last, _)) if height < last => {
+                self.fall.start = self.fall.start.or(self.fall.last);
+            }
+            _ => self.fall.start = None,
+        }
+        self.fall.last = Some((height, now));
+    }
+
+    /// How fast the server thinks a remote player landed, in units per second.
+    /// A fall can't be faster than dropping its height under gravity, and one that took longer
+    /// than that, like walking down stairs, counts as slower.
+    pub fn landing_speed(&mut self, movement: &Movement) -> f32 {
+        let (start_height, start) = match self.fall.start.take() {
+            Some(start) => start,
+            None => return 0.0,
+        };
+        let (height, now) = self.fall.last.unwrap_or((start_height, start));
+        let drop = start_height - height;
+        let free_fall = (2.0 * movement.gravity * drop).sqrt();
+        match now - start {
+            time if time > 0.0 => free_fall.min(2.0 * drop / time),
+            _ => free_fall,
+        }
+    }
+
+    /// The sound of landing at `speed`, landings that hurt sound like it
+    pub fn land_sound(speed: f32, movement: &Movement) -> &'static str {
+        match speed > movement.fall_damage_speed {
+            true => "sounds/Player/Hurt/hurt2.ogg",
+            false => "sounds/Player/playerJump.ogg",
+        }
+    }
 }
 
+/// Health above this goes back down over time
+pub const MAX_HEALTH: f32 = 100.0;
+/// How much health above `MAX_HEALTH` is lost per second
+pub const HEALTH_DECAY: f32 = 1.0;
+
+/// Half the height of a standing player
+const STAND_HALF_HEIGHT: f32 = 0.5;
+/// Half the height of a crouching player. On the ground the difference is taken off the top,
+/// in the air it's taken off the bottom so that crouch jumps reach higher ledges.
+const CROUCH_HALF_HEIGHT: f32 = 0.35;
+/// How high the camera sits above the middle of the player
+const STAND_EYE_HEIGHT: f32 = 0.25;
+const CROUCH_EYE_HEIGHT: f32 = 0.1;
+/// How far the camera dips on landing for every unit per second of falling speed
+const LAND_DIP: f32 = 0.01;
+/// The deepest the camera dips on landing
+const LAND_DIP_MAX: f32 = 0.2;
+
 const HEALTH_GLYPH: &str = "+";
 const ARMOR_GLYPH: &str = "Δ";
@@ -0,0 +1,47 @@
+use bevy::math::Vec3;
+use resources::data::Movement;
+
+/// Quake's `PM_Accelerate`, speeds up towards `wish_dir` until the speed along it reaches `cap`,
+/// at most by `accelerate * wish_speed` per second. The speed in other directions is kept.
+pub fn accelerate(
+    velocity: &mut Vec3,
+    wish_dir: Vec3,
+    wish_speed: f32,
+    cap: f32,
+    accelerate: f32,
+    delta: f32,
+) {
+    let add_speed = wish_speed.min(cap) - velocity.dot(wish_dir);
+    if add_speed <= 0.0 {
+        return;
+    }
+    let accel_speed = (accelerate * wish_speed * delta).min(add_speed);
+    *velocity += wish_dir * accel_speed;
+}
+
+/// Quake's `PM_Friction`, slows down the horizontal speed on the ground
+pub fn friction(velocity: &mut Vec3, friction: f32, movement: &Movement, delta: f32) {
+    let speed = velocity.with_y(0.0).length();
+    if speed < f32::EPSILON {
+        return;
+    }
+    let control = speed.max(movement.stop_speed);
+    let new_speed = (speed - control * friction * delta).max(0.0);
+    let scale = new_speed / speed;
+    velocity.x *= scale;
+    velocity.z *= scale;
+}
+
+/// The velocity on a ladder. Moving forward climbs up, or down while looking down,
+/// and the part of `wish_dir` going into the ladder is stopped by the wall behind it.
+pub fn climb(wish_dir: Vec3, forward: Vec3, look_y: f32, climb_speed: f32) -> Vec3 {
+    let vertical = match look_y < LADDER_LOOK_DOWN {
+        true => -1.0,
+        false => 1.0,
+    };
+    let up = wish_dir.dot(forward) * vertical;
+    (wish_dir + Vec3::Y * up) * climb_speed
+}
+
+/// How far down, as the height of the look direction, players look to climb down ladders
+const LADDER_LOOK_DOWN: f32 = -0.4;
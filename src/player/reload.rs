@@ -0,0 +1,104 @@
+use super::{Player, PlayerController};
+use crate::{
+    game_mode::{MatchPhase, MatchState},
+    net::ClientMessage,
+};
+use bevy::prelude::*;
+use faststr::FastStr;
+use macros::option_continue;
+use resources::inputs::PlayerInput;
+
+/// Seconds a reload may finish early on the server, for messages the network bunched up
+const RELOAD_LEEWAY: f32 = 0.1;
+
+impl Player {
+    /// Reloads the own player's weapon when reload is pressed or the magazine runs dry,
+    /// and puts the rounds in once it's done. The server follows along with
+    /// `ClientMessage::StartReload` and `ClientMessage::Reload`.
+    pub fn reload(
+        keys: Res<PlayerInput>,
+        time: Res<Time>,
+        match_state: Res<MatchState>,
+        mut query: Query<&mut Player, With<PlayerController>>,
+        mut client_events: EventWriter<ClientMessage>,
+    ) {
+        for mut player in &mut query {
+            if player.spectating || match_state.phase == MatchPhase::Intermission {
+                continue;
+            }
+            let (slot, row) = option_continue!(player.current_weapon);
+            let empty = !player.has_ammo(1);
+            let can_reload = player.can_reload();
+            let weapon = option_continue!(player.weapons[slot].get_mut(row));
+            // Pressing reload or running dry starts reloading, but not in the middle of a shot
+            let start = (keys.reload_just_pressed || empty) && can_reload && weapon.timer <= 0.0;
+            match weapon.reloading {
+                None if start => {
+                    client_events.send(ClientMessage::StartReload);
+                }
+                None => continue,
+                Some(left) if left > time.delta_secs() => {
+                    weapon.reloading = Some(left - time.delta_secs());
+                    continue;
+                }
+                Some(_) => {
+                    weapon.reloading = None;
+                    let single = weapon.data.reload_single;
+                    player.load_magazine();
+                    client_events.send(ClientMessage::Reload);
+                    // Reloading a round at a time goes on until the magazine is full
+                    if !single || !player.can_reload() {
+                        continue;
+                    }
+                }
+            }
+
+            let weapon = &mut player.weapons[slot][row];
+            weapon.reloading = Some(weapon.data.animations.reload_time_skip);
+            weapon.anim_time = weapon.data.animations.reload_time;
+            // Sent to everyone else by `weapon_animations`
+            player.current_weapon_anim = FastStr::from("reload");
+            player.restart_anim = true;
+        }
+    }
+    /// Starts reloading the current weapon on the server at `now`, false if it can't be reloaded
+    pub fn start_reload(&mut self, now: f32) -> bool {
+        let can_reload = self.can_reload();
+        let weapon = match self.magazine_mut() {
+            Some(weapon) => weapon,
+            None => return false,
+        };
+        weapon.reload_started = can_reload.then_some(now);
+        can_reload
+    }
+
+    /// Puts the rounds in on the server when the client finished reloading, false if the reload
+    /// never started or the client finished it before `reload_time_skip` had passed
+    pub fn finish_reload(&mut self, now: f32) -> bool {
+        let weapon = match self.magazine_mut() {
+            Some(weapon) => weapon,
+            None => return false,
+        };
+        let early = weapon.data.animations.reload_time_skip - RELOAD_LEEWAY;
+        match weapon.reload_started {
+            Some(started) if now - started >= early => weapon.reload_started = None,
+            _ => return false,
+        }
+        let single = weapon.data.reload_single;
+        if self.load_magazine() == 0 {
+            return false;
+        }
+        // The next round starts going in right away, like on the client
+        if single && self.can_reload() {
+            self.start_reload(now);
+        }
+        true
+    }
+
+    /// Stops the server's reload of the current weapon, shooting interrupts it
+    pub fn stop_reload(&mut self) {
+        if let Some(weapon) = self.magazine_mut() {
+            weapon.reload_started = None;
+        }
+    }
+}
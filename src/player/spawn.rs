@@ -1,9 +1,12 @@
 use super::{
-    Player, PlayerController, PlayerFpsMaterial, PlayerFpsModel, PlayerMpModel, ARMOR_GLYPH,
-    HEALTH_GLYPH,
+    Player, PlayerController, PlayerFpsMaterial, PlayerFpsModel, PlayerHud, PlayerMpModel,
+    ARMOR_GLYPH, HEALTH_GLYPH, STAND_EYE_HEIGHT, STAND_HALF_HEIGHT,
 };
 use crate::{
+    game_mode::spawns,
     net::{
+        client::{JoinSpawn, SpectateOnJoin},
+        demo::DemoPlayback,
         steam::{CurrentAvatar, SteamClient},
         PlayerInfo,
     },
@@ -12,39 +15,66 @@ use crate::{
 use bevy::{prelude::*, render::view::NoFrustumCulling, text::FontSmoothing};
 use bevy_rapier3d::prelude::*;
 use bevy_scene_hook::reload::{Hook, SceneBundle as HookedSceneBundle};
+use bevy_simple_text_input::{
+    TextInput, TextInputInactive, TextInputPlaceholder, TextInputTextFont, TextInputValue,
+};
 use faststr::FastStr;
-use resources::PlayerSpawnpoint;
+use macros::{error_return, option_return};
 
 impl Player {
     pub fn spawn_own_player(
         mut nw: NetWorld,
-        player_spawn: Res<PlayerSpawnpoint>,
+        join_spawn: Option<Res<JoinSpawn>>,
         avatar: Option<Res<CurrentAvatar>>,
         steam: Option<Res<SteamClient>>,
+        spectate_on_join: Res<SpectateOnJoin>,
+        demo: Option<Res<DemoPlayback>>,
     ) {
         let id = nw.current_id.0;
+        if let Some(info) = nw.lobby.get(&id) {
+            // The map changed, the player is already there
+            nw.commands.remove_resource::<JoinSpawn>();
+            let spawn = option_return!(join_spawn);
+            let (_, mut player, mut trans) = error_return!(nw.players.get_mut(info.entity));
+            player.reset(&nw.ammo_map);
+            trans.translation = spawn.position;
+            trans.rotation = Quat::from_rotation_y(spawn.yaw);
+            return;
+        }
+        // Clients are told where to spawn by the server
+        let (position, yaw) = match join_spawn {
+            Some(spawn) => (spawn.position, spawn.yaw),
+            None => {
+                let spawn = spawns::select_spawn(&mut nw, id, None);
+                (spawn.position, spawn.yaw)
+            }
+        };
+        nw.commands.remove_resource::<JoinSpawn>();
         let entity = Self::spawn(
             &mut nw,
             true,
-            player_spawn.0,
+            Transform::from_translation(position).with_rotation(Quat::from_rotation_y(yaw)),
             id,
             Vec::new(),
             avatar.as_ref(),
         );
 
-        nw.lobby.insert(
-            nw.current_id.0,
-            PlayerInfo::new(
-                entity,
-                FastStr::from(steam.map(|s| s.friends().name()).unwrap_or(format!("{id}"))),
-            ),
+        let mut info = PlayerInfo::new(
+            entity,
+            FastStr::from(steam.map(|s| s.friends().name()).unwrap_or(format!("{id}"))),
         );
+        // Demos are always watched as a spectator
+        info.spectating = spectate_on_join.0 || demo.is_some();
+        if info.spectating {
+            Self::set_spectating(&mut nw.commands, entity, true);
+        }
+        nw.lobby.insert(nw.current_id.0, info);
     }
 
     pub fn spawn(
         nw: &mut NetWorld,
         is_own: bool,
-        player_spawn: Vec3,
+        transform: Transform,
         current_id: u64,
         weapons: Vec<Vec<FastStr>>,
         avatar: Option<&Res<CurrentAvatar>>,
@@ -53,16 +83,22 @@ impl Player {
         let mut fps_model = None;
         let mut ammo_hud = None;
         let mut armour_hud = None;
+        let mut powerup_hud = None;
         let mut health_hud = None;
         let mut debug_hud = None;
+        let mut net_hud = None;
         let mut message_holder = None;
+        let mut chat_hud = None;
+        let mut chat_input = None;
         let mut shoot_sound_holder = None;
         let mut lobby_hud = None;
-        let mut entity = nw.commands.spawn(Collider::cylinder(0.5, 0.15));
+        let mut match_hud = None;
+        let mut kill_feed = None;
+        let mut entity = nw.commands.spawn(Self::collider());
 
         let player_commands = entity
             .insert(ActiveEvents::COLLISION_EVENTS)
-            .insert(Transform::from_translation(player_spawn))
+            .insert(transform)
             .insert(KinematicCharacterController::default())
             .insert(Restitution::coefficient(0.0))
             .insert(LockedAxes::ROTATION_LOCKED)
@@ -76,7 +112,7 @@ impl Player {
                             fov: 80.0f32.to_radians(),
                             ..default()
                         }),
-                        Transform::from_translation(Vec3::new(0.0, 0.25, 0.0)),
+                        Transform::from_translation(Vec3::new(0.0, STAND_EYE_HEIGHT, 0.0)),
                         Camera {
                             is_active: is_own,
                             ..default()
@@ -142,7 +178,8 @@ impl Player {
             player_commands.insert(PlayerController);
         } else {
             player_commands.with_children(|c| {
-                let mut trans = Transform::from_translation(Vec3::new(0.0, -0.5, 0.0));
+                let mut trans =
+                    Transform::from_translation(Vec3::new(0.0, -STAND_HALF_HEIGHT, 0.0));
                 trans.scale = Vec3::splat(0.5);
                 trans.rotate_y(180f32.to_radians());
                 c.spawn((
@@ -169,6 +206,7 @@ impl Player {
                     justify_content: JustifyContent::SpaceBetween,
                     ..default()
                 })
+                .insert(PlayerHud)
                 .with_children(|c| {
                     c.spawn((
                         Node {
@@ -198,6 +236,47 @@ impl Player {
                         .id(),
                     );
 
+                    c.spawn(Node {
+                        position_type: PositionType::Absolute,
+                        left: Val::Px(10.0),
+                        bottom: Val::Px(32.0 * 3.0 + 10.0),
+                        flex_direction: FlexDirection::Column,
+                        ..default()
+                    })
+                    .with_children(|c| {
+                        chat_hud = Some(
+                            c.spawn((
+                                Text::default(),
+                                TextFont {
+                                    font: nw.asset_server.load("ui/Color Basic.otf"),
+                                    font_size: 16.0,
+                                    font_smoothing: FontSmoothing::None,
+                                },
+                                TextColor(Color::WHITE),
+                            ))
+                            .id(),
+                        );
+                        chat_input = Some(
+                            c.spawn((
+                                Node {
+                                    width: Val::Px(400.0),
+                                    ..default()
+                                },
+                                TextInput,
+                                TextInputValue::default(),
+                                TextInputPlaceholder::default(),
+                                TextInputInactive(true),
+                                TextInputTextFont(TextFont {
+                                    font: nw.asset_server.load("ui/Color Basic.otf"),
+                                    font_size: 16.0,
+                                    font_smoothing: FontSmoothing::None,
+                                }),
+                                Visibility::Hidden,
+                            ))
+                            .id(),
+                        );
+                    });
+
                     c.spawn(Node {
                         position_type: PositionType::Absolute,
                         left: Val::Px(0.0),
@@ -211,19 +290,56 @@ impl Player {
                     .with_children(|c| {
                         lobby_hud = Some(
                             c.spawn((
-                                Text::new("LOBBY DATA"),
+                                Node {
+                                    flex_direction: FlexDirection::Column,
+                                    align_self: AlignSelf::Center,
+                                    row_gap: Val::Px(4.0),
+                                    padding: UiRect::all(Val::Px(12.0)),
+                                    ..default()
+                                },
+                                BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.6)),
+                            ))
+                            .insert(Visibility::Hidden)
+                            .id(),
+                        );
+                    });
+
+                    c.spawn(Node {
+                        position_type: PositionType::Absolute,
+                        top: Val::Px(10.0),
+                        width: Val::Percent(100.0),
+                        justify_content: JustifyContent::Center,
+                        ..default()
+                    })
+                    .with_children(|c| {
+                        match_hud = Some(
+                            c.spawn((
+                                Text::default(),
                                 TextFont {
                                     font: nw.asset_server.load("ui/Color Basic.otf"),
                                     font_smoothing: FontSmoothing::None,
                                     ..default()
                                 },
                                 TextColor(Color::WHITE),
+                                TextLayout::new_with_justify(JustifyText::Center),
                             ))
-                            .insert(Visibility::Hidden)
                             .id(),
                         );
                     });
 
+                    kill_feed = Some(
+                        c.spawn(Node {
+                            position_type: PositionType::Absolute,
+                            right: Val::Px(10.0),
+                            top: Val::Px(10.0),
+                            flex_direction: FlexDirection::Column,
+                            align_items: AlignItems::FlexEnd,
+                            row_gap: Val::Px(2.0),
+                            ..default()
+                        })
+                        .id(),
+                    );
+
                     let text_color = Color::srgb(0.921, 0.682, 0.203);
 
                     c.spawn(Node {
@@ -268,6 +384,27 @@ impl Player {
                         );
                     });
 
+                    c.spawn(Node {
+                        position_type: PositionType::Absolute,
+                        left: Val::Px(34.0 * 3.0),
+                        bottom: Val::Px(18.0 * 4.0),
+                        ..default()
+                    })
+                    .with_children(|c| {
+                        powerup_hud = Some(
+                            c.spawn((
+                                Text::default(),
+                                TextFont {
+                                    font: nw.asset_server.load("ui/Color Basic.otf"),
+                                    font_smoothing: FontSmoothing::None,
+                                    ..default()
+                                },
+                                TextColor(text_color),
+                            ))
+                            .id(),
+                        );
+                    });
+
                     c.spawn(Node {
                         position_type: PositionType::Absolute,
                         left: Val::Px(255.0),
@@ -311,6 +448,7 @@ impl Player {
 
                     c.spawn(Node {
                         position_type: PositionType::Absolute,
+                        flex_direction: FlexDirection::Column,
                         left: Val::Px(0.0),
                         top: Val::Px(0.0),
                         ..default()
@@ -327,6 +465,17 @@ impl Player {
                             ))
                             .id(),
                         );
+                        net_hud = Some(
+                            c.spawn((
+                                Visibility::Hidden,
+                                Text::default(),
+                                TextFont {
+                                    font_size: 16.0,
+                                    ..default()
+                                },
+                            ))
+                            .id(),
+                        );
                     });
                 });
         }
@@ -335,16 +484,23 @@ impl Player {
 
         let mut player_data = Player {
             id: current_id,
+            ammo: Self::start_ammo(&nw.ammo_map),
             children: super::PlayerChildren {
                 camera,
                 fps_model,
                 ammo_hud,
                 armour_hud,
+                powerup_hud,
                 health_hud,
                 debug_hud,
+                net_hud,
                 message_holder,
+                chat_hud,
+                chat_input,
                 shoot_sound_holder,
                 lobby_hud,
+                match_hud,
+                kill_feed,
             },
             ..default()
         };
@@ -359,6 +515,7 @@ impl Player {
                 }
             }
         }
+        player_data.fill_magazines();
         player_commands.insert(player_data);
         id
     }
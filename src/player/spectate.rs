@@ -0,0 +1,151 @@
+use super::{Player, PlayerController, PlayerMpModel};
+use crate::net::{ClientMessage, Lobby};
+use bevy::prelude::*;
+use bevy_rapier3d::geometry::ColliderDisabled;
+use macros::{error_return, option_continue, option_return};
+use resources::inputs::PlayerInput;
+
+/// How fast spectators fly, in units per second
+const SPECTATOR_SPEED: f32 = 6.0;
+
+impl Player {
+    /// Turns the player on `entity` into a spectator or back into a player.
+    /// The collider is disabled while spectating, so that hitscans, projectiles and pickups ignore them.
+    pub fn set_spectating(commands: &mut Commands, entity: Entity, spectating: bool) {
+        let mut entity = option_return!(commands.get_entity(entity));
+        if spectating {
+            entity.insert(ColliderDisabled);
+        } else {
+            entity.remove::<ColliderDisabled>();
+        }
+        entity.queue(move |mut entity: EntityWorldMut| {
+            if let Some(mut player) = entity.get_mut::<Player>() {
+                player.spectating = spectating;
+                player.following = None;
+                player.velocity = Vec3::ZERO;
+            }
+        });
+    }
+
+    pub fn toggle_spectate(
+        keys: Res<PlayerInput>,
+        query: Query<&Player, With<PlayerController>>,
+        mut events: EventWriter<ClientMessage>,
+    ) {
+        if !keys.spectate_just_pressed {
+            return;
+        }
+        for player in &query {
+            events.send(ClientMessage::Spectate {
+                spectating: !player.spectating,
+            });
+        }
+    }
+
+    /// Free flying with the debug fly keys, or following another player's view.
+    /// The attack keys pick the next player to follow, jumping goes back to flying.
+    #[allow(clippy::type_complexity)]
+    pub fn spectate(
+        keys: Res<PlayerInput>,
+        time: Res<Time>,
+        lobby: Res<Lobby>,
+        mut own: Query<(&mut Player, &mut Transform), (With<PlayerController>, Without<Camera3d>)>,
+        others: Query<(&Player, &Transform), (Without<PlayerController>, Without<Camera3d>)>,
+        mut cameras: Query<&mut Transform, With<Camera3d>>,
+    ) {
+        let (mut player, mut trans) = option_return!(own.iter_mut().next());
+        if !player.spectating {
+            return;
+        }
+        let own_camera = option_return!(player.children.camera);
+
+        if keys.weapon_shoot1_just_pressed || keys.weapon_shoot2_just_pressed {
+            let candidates = lobby
+                .iter()
+                .filter(|(id, _)| **id != player.id)
+                .filter(|(_, info)| {
+                    others
+                        .get(info.entity)
+                        .map(|(p, _)| !p.spectating)
+                        .unwrap_or_default()
+                })
+                .map(|(id, _)| *id)
+                .collect::<Vec<_>>();
+            let current = player
+                .following
+                .and_then(|id| candidates.iter().position(|c| *c == id));
+            let next = match (current, keys.weapon_shoot1_just_pressed) {
+                (Some(i), true) => (i + 1) % candidates.len().max(1),
+                (Some(i), false) => (i + candidates.len() - 1) % candidates.len(),
+                (None, true) => 0,
+                (None, false) => candidates.len().saturating_sub(1),
+            };
+            player.following = candidates.get(next).copied();
+        }
+        if keys.jump_just_pressed {
+            player.following = None;
+        }
+
+        if let Some(following) = player.following {
+            let followed = lobby
+                .get(&following)
+                .and_then(|info| others.get(info.entity).ok())
+                .filter(|(p, _)| !p.spectating);
+            let Some((followed, followed_trans)) = followed else {
+                // They left or started spectating themselves
+                player.following = None;
+                return;
+            };
+            trans.translation = followed_trans.translation;
+            trans.rotation = followed_trans.rotation;
+
+            let followed_cam = option_return!(followed.children.camera);
+            let rotation = error_return!(cameras.get(followed_cam)).rotation;
+            error_return!(cameras.get_mut(own_camera)).rotation = rotation;
+            return;
+        }
+
+        let camera = error_return!(cameras.get(own_camera));
+        let forward = trans.rotation * camera.rotation * Vec3::NEG_Z;
+        let right = trans.rotation * Vec3::X;
+
+        let mut dir = Vec3::ZERO;
+        if keys.walk_forward_pressed {
+            dir += forward;
+        }
+        if keys.walk_backward_pressed {
+            dir -= forward;
+        }
+        if keys.walk_right_pressed {
+            dir += right;
+        }
+        if keys.walk_left_pressed {
+            dir -= right;
+        }
+        if keys.debug_fly_up_pressed {
+            dir += Vec3::Y;
+        }
+        if keys.debug_fly_down_pressed {
+            dir -= Vec3::Y;
+        }
+        trans.translation += dir.normalize_or_zero() * SPECTATOR_SPEED * time.delta_secs();
+    }
+
+    /// Hides the models of spectators, and of the player the own player is watching through
+    pub fn update_mp_models(
+        players: Query<&Player>,
+        own: Query<&Player, With<PlayerController>>,
+        mut models: Query<(&Parent, &mut Visibility), With<PlayerMpModel>>,
+    ) {
+        let following = own.iter().next().and_then(|p| p.following);
+        for (parent, mut visibility) in &mut models {
+            let player = option_continue!(players.get(parent.get()).ok());
+            let hidden = player.spectating || Some(player.id) == following;
+            visibility.set_if_neq(if hidden {
+                Visibility::Hidden
+            } else {
+                Visibility::Inherited
+            });
+        }
+    }
+}
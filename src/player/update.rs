@@ -1,12 +1,15 @@
 #![allow(clippy::missing_transmute_annotations)]
 
 use super::{
-    ARMOR_GLYPH, HEALTH_GLYPH, Player, PlayerController, PlayerFpsMaterial, PlayerFpsModel,
-    PlayerMpModel, WeaponState,
+    ARMOR_GLYPH, CROUCH_EYE_HEIGHT, HEALTH_GLYPH, LAND_DIP, LAND_DIP_MAX, Player, PlayerController,
+    PlayerFpsMaterial, PlayerFpsModel, PlayerMpModel, STAND_EYE_HEIGHT, WeaponState,
+    movement::{accelerate, climb, friction},
 };
 use crate::{
     entities::ProjectileEntity,
-    net::{ClientMessage, Lobby},
+    game_mode::{MatchPhase, MatchState},
+    map_gen::Ladder,
+    net::ClientMessage,
 };
 use bevy::{
     audio::Volume,
@@ -16,19 +19,22 @@ use bevy::{
     window::{CursorGrabMode, PrimaryWindow},
 };
 use bevy_rapier3d::{
-    control::KinematicCharacterController, geometry::Collider, pipeline::QueryFilter,
-    plugin::RapierContext, prelude::ShapeCastOptions,
+    control::{KinematicCharacterController, KinematicCharacterControllerOutput},
+    geometry::Collider,
+    pipeline::QueryFilter,
+    plugin::RapierContext,
+    prelude::ShapeCastOptions,
 };
 use bevy_scene_hook::reload::{Hook, State as HookState};
 use faststr::FastStr;
 use macros::{error_continue, option_continue, option_return};
 use resources::{
-    Paused,
-    data::{Attack, Projectiles, SoundEffect},
+    AmmoMap, Paused,
+    data::{Attack, Movement, Projectiles, SoundEffect},
     entropy::{EGame, EMisc, Entropy},
     inputs::PlayerInput,
 };
-use std::{fmt::Write, mem::transmute};
+use std::mem::transmute;
 
 enum SwitchDirection {
     Back,
@@ -37,7 +43,9 @@ enum SwitchDirection {
 impl Player {
     pub fn systems() -> SystemConfigs {
         (
+            Player::crouch.before(Player::update_input),
             Player::update_input,
+            Player::update_crouch.after(Player::crouch),
             Player::update_cam_vert,
             Player::update_cam_hort,
             Player::ground_detection,
@@ -46,9 +54,15 @@ impl Player {
             Player::weaponry_switch_keys,
             Player::weapon_animations,
             Player::camera_movement,
+            Player::reload.before(Player::shoot),
             Player::shoot,
             Player::update_hud,
             Player::update_interact,
+            Player::toggle_spectate,
+            Player::spectate
+                .after(Player::update_cam_hort)
+                .after(Player::update_cam_vert),
+            Player::update_mp_models,
         )
             .into_configs()
     }
@@ -56,7 +70,8 @@ impl Player {
     fn set_anim(weapon: &mut WeaponState, fire_time: f32, anim_time: f32, time: &Time) {
         weapon.timer = fire_time + time.delta_secs();
         weapon.anim_time = anim_time + time.delta_secs();
-        if weapon.data.animations.reload.is_some() {
+        // Weapons with a magazine only reload once it's empty, in `Player::reload`
+        if weapon.data.animations.reload.is_some() && weapon.data.magazine == 0 {
             weapon.need_to_reload = true;
             weapon.timer = fire_time + weapon.data.animations.reload_time_skip + time.delta_secs();
             weapon.reload_timer = anim_time + time.delta_secs();
@@ -66,37 +81,41 @@ impl Player {
 
     pub fn update_hud(
         q_players: Query<&Player, With<PlayerController>>,
-        mut text: Query<(&mut Text, &mut Visibility)>,
-        input: Res<PlayerInput>,
-        lobby: Res<Lobby>,
+        ammo_map: Res<AmmoMap>,
+        mut text: Query<&mut Text>,
     ) {
         for player in &q_players {
             let ammo_hud = option_continue!(player.children.ammo_hud);
-            let _ammo_hud = error_continue!(text.get_mut(ammo_hud));
+            let mut ammo_hud = error_continue!(text.get_mut(ammo_hud));
+            ammo_hud.0 = match player.attack_ammo(1) {
+                Some((ammo, _)) => {
+                    let amount = player.ammo.get(&ammo).copied().unwrap_or_default();
+                    let name = ammo_map.0.get(&ammo).map_or(ammo, |a| a.fancy_name.clone());
+                    match player.magazine() {
+                        Some(weapon) => format!("{}/{amount}\n{name}", weapon.loaded),
+                        None => format!("{amount}\n{name}"),
+                    }
+                }
+                None => String::new(),
+            };
 
             let health_hud = option_continue!(player.children.health_hud);
-            let (mut health_hud, _) = error_continue!(text.get_mut(health_hud));
+            let mut health_hud = error_continue!(text.get_mut(health_hud));
             health_hud.0 = format!("{HEALTH_GLYPH}{}", player.health.round());
 
             let armour_hud = option_continue!(player.children.armour_hud);
-            let (mut armour_hud, _) = error_continue!(text.get_mut(armour_hud));
+            let mut armour_hud = error_continue!(text.get_mut(armour_hud));
             armour_hud.0 = format!("{ARMOR_GLYPH}{}", player.armour.round());
 
-            let lobby_hud = option_continue!(player.children.lobby_hud);
-            let (mut text, mut vis) = error_continue!(text.get_mut(lobby_hud));
-            if input.show_lobby_just_pressed {
-                *vis = Visibility::Visible;
-                text.0 = format!(
-                    "Players:\n{}",
-                    lobby.values().fold(String::new(), |mut output, i| {
-                        let _ = writeln!(output, "{}: K={}, D={}", i.name, i.kills, i.deaths);
-                        output
-                    })
-                )
-            }
-            if input.show_lobby_just_released {
-                *vis = Visibility::Hidden;
-            }
+            let powerup_hud = option_continue!(player.children.powerup_hud);
+            let mut powerup_hud = error_continue!(text.get_mut(powerup_hud));
+            let mut powerups = player
+                .powerups
+                .iter()
+                .map(|(powerup, time)| format!("{} {}", powerup.name(), time.ceil()))
+                .collect::<Vec<_>>();
+            powerups.sort();
+            powerup_hud.0 = powerups.join("\n");
         }
     }
 
@@ -107,10 +126,15 @@ impl Player {
         keys: Res<PlayerInput>,
         time: Res<Time>,
         asset_server: Res<AssetServer>,
+        match_state: Res<MatchState>,
         mut client_events: EventWriter<ClientMessage>,
     ) {
         for (player_ent, mut player, _) in &mut q_players {
+            if player.spectating || match_state.phase == MatchPhase::Intermission {
+                continue;
+            }
             let (slot, row) = option_continue!(player.current_weapon);
+            let (has_ammo1, has_ammo2) = (player.has_ammo(1), player.has_ammo(2));
             // let cur = player.current_weapon_anim.clone();
             let weapon = &mut player.weapons[slot][row];
             weapon.timer -= time.delta_secs();
@@ -119,12 +143,24 @@ impl Player {
             weapon.anim_time = weapon.anim_time.max(-1.0);
             weapon.reload_timer -= time.delta_secs();
             weapon.reload_timer = weapon.reload_timer.max(-1.0);
+            if weapon.reloading.is_some() {
+                // Rounds reloaded one at a time can be fired right away, which stops the reload
+                let fire = (keys.weapon_shoot1_pressed && has_ammo1)
+                    || (keys.weapon_shoot2_pressed && has_ammo2);
+                if !weapon.data.reload_single || !fire {
+                    continue;
+                }
+                weapon.reloading = None;
+            }
             // println!(
             //     "timer {}:\n\t{:?}\n\t{}\n\t{}",
             //     cur, weapon.timer, weapon.anim_time, weapon.reload_timer
             // );
             if weapon.timer > 0.0 {
-                if weapon.reload_timer <= 0.0 && weapon.data.animations.reload.is_some() {
+                if weapon.need_to_reload
+                    && weapon.reload_timer <= 0.0
+                    && weapon.data.animations.reload.is_some()
+                {
                     if player.current_weapon_anim != "reload" {
                         player.restart_anim = true;
                     }
@@ -136,10 +172,10 @@ impl Player {
 
             let mut shot = false;
 
-            if keys.weapon_shoot2_pressed && !weapon.need_to_reload {
+            if keys.weapon_shoot2_pressed && has_ammo2 && !weapon.need_to_reload {
                 player.attack2(&time, &mut client_events);
                 shot = true;
-            } else if keys.weapon_shoot1_pressed && !weapon.need_to_reload {
+            } else if keys.weapon_shoot1_pressed && has_ammo1 && !weapon.need_to_reload {
                 player.attack1(&time, &mut client_events);
                 shot = true;
             } else if weapon.anim_time <= 0.0 && player.current_weapon_anim != "idle" {
@@ -200,9 +236,19 @@ impl Player {
                 .switch_offset
                 .lerp(0.0, time.delta_secs() * 10.0);
 
+            let eye_height = match player.crouching {
+                true => CROUCH_EYE_HEIGHT,
+                false => STAND_EYE_HEIGHT,
+            };
+            player.camera_movement.eye_height = player
+                .camera_movement
+                .eye_height
+                .lerp(eye_height, time.delta_secs() * 10.0);
+
             let (_, mut cam_trans) =
                 error_continue!(q_cam.get_mut(option_continue!(player.children.camera)));
             cam_trans.rotation.z = player.camera_movement.cam_rot_current;
+            cam_trans.translation.y = player.camera_movement.eye_height;
 
             let (_, mut trans) =
                 error_continue!(q_model.get_mut(option_continue!(player.children.fps_model)));
@@ -260,67 +306,168 @@ impl Player {
         >,
         mut client_events: EventWriter<ClientMessage>,
     ) {
-        for _ in &mut query {
-            if keys.interact_just_pressed {
+        for (_, player, _) in &mut query {
+            if keys.interact_just_pressed && !player.spectating {
                 client_events.send(ClientMessage::Interact);
             }
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn update_input(
+        mut commands: Commands,
+        asset_server: Res<AssetServer>,
         keys: Res<PlayerInput>,
         time: Res<Time>,
         mut query: Query<
             (
                 &mut KinematicCharacterController,
+                Option<&KinematicCharacterControllerOutput>,
                 &mut Player,
                 &mut Transform,
             ),
             With<PlayerController>,
         >,
         cameras: Query<(&Camera3d, &Transform), Without<PlayerController>>,
+        match_state: Res<MatchState>,
+        movement: Res<Movement>,
         mut events: EventWriter<ClientMessage>,
     ) {
-        for (mut controller, mut player, gt) in &mut query {
+        for (mut controller, output, mut player, gt) in &mut query {
+            // Spectators fly around in `Player::spectate`, and nobody moves during the intermission
+            if player.spectating || match_state.phase == MatchPhase::Intermission {
+                controller.translation = None;
+                continue;
+            }
+            let delta = time.delta_secs();
+            // How fast the player was falling, before the ground took the speed away
+            let fall_speed = -player.velocity.y;
+
+            // Walls and ceilings take away the speed going into them
+            if let Some(output) = output {
+                let (desired, effective) =
+                    (output.desired_translation, output.effective_translation);
+                let kept = |desired: f32, effective: f32| match effective.abs() < desired.abs() {
+                    true => (effective / desired).max(0.0),
+                    false => 1.0,
+                };
+                player.velocity *= Vec3::new(
+                    kept(desired.x, effective.x),
+                    kept(desired.y, effective.y),
+                    kept(desired.z, effective.z),
+                );
+            }
+
             // movement
             let local_z = gt.local_z();
-            let forward = -Vec3::new(local_z.x, 0., local_z.z);
-            let right = Vec3::new(local_z.z, 0., -local_z.x);
+            let forward = -Vec3::new(local_z.x, 0., local_z.z).normalize_or_zero();
+            let right = Vec3::new(local_z.z, 0., -local_z.x).normalize_or_zero();
 
-            let hort_speed = player.hort_speed;
+            let mut wish_dir = Vec3::ZERO;
             if keys.walk_forward_pressed {
-                player.velocity += forward * hort_speed * time.delta_secs();
+                wish_dir += forward;
                 player.camera_movement.backdrift_goal = player.camera_movement.backdrift_max;
             } else if keys.walk_backward_pressed {
-                player.velocity -= forward * hort_speed * time.delta_secs();
+                wish_dir -= forward;
                 player.camera_movement.backdrift_goal = -player.camera_movement.backdrift_max;
             } else {
                 player.camera_movement.backdrift_goal = 0.0;
             }
 
             if keys.walk_left_pressed {
-                player.velocity -= right * hort_speed * time.delta_secs();
+                wish_dir -= right;
                 player.camera_movement.cam_rot_goal = player.camera_movement.cam_rot_max_goal;
             } else if keys.walk_right_pressed {
-                player.velocity += right * hort_speed * time.delta_secs();
+                wish_dir += right;
                 player.camera_movement.cam_rot_goal = -player.camera_movement.cam_rot_max_goal;
             } else {
                 player.camera_movement.cam_rot_goal = 0.0;
             }
+            // Going diagonally is no faster than going straight
+            let wish_dir = wish_dir.normalize_or_zero();
+            let max_speed = match player.crouching {
+                true => movement.crouch_speed,
+                false => movement.max_speed,
+            };
+            let wish_speed = max_speed * player.speed_scale();
+
+            if player.on_ladder {
+                // Ladders hold players in place, jumping lets go and pushes away from them
+                player.jump_buffer = 0.0;
+                if keys.jump_just_pressed {
+                    player.velocity = -forward * movement.ladder_jump;
+                    player.on_ladder = false;
+                    player.left_ladder = true;
+                } else {
+                    let look_y = player
+                        .children
+                        .camera
+                        .and_then(|cam| cameras.get(cam).ok())
+                        .map(|(_, t)| t.forward().y)
+                        .unwrap_or_default();
+                    player.velocity = climb(wish_dir, forward, look_y, movement.climb_speed);
+                }
+            } else {
+                // Jumps pressed a little before landing still count
+                player.jump_buffer = (player.jump_buffer - delta).max(0.0);
+                if keys.jump_just_pressed || (movement.auto_hop && keys.jump_pressed) {
+                    player.jump_buffer = movement.jump_buffer;
+                }
+
+                // Still touching the ground right after jumping doesn't count
+                let grounded = player.on_ground && player.velocity.y <= 0.0;
+                if grounded && fall_speed > movement.land_speed {
+                    // The camera catches up with the eye height again like after crouching
+                    player.camera_movement.eye_height -= (fall_speed * LAND_DIP).min(LAND_DIP_MAX);
+                    commands.spawn((
+                        AudioPlayer::<AudioSource>(
+                            asset_server.load(Player::land_sound(fall_speed, &movement)),
+                        ),
+                        PlaybackSettings::DESPAWN,
+                    ));
+                    events.send(ClientMessage::Land { speed: fall_speed });
+                }
+                if grounded && player.jump_buffer > 0.0 {
+                    // No friction when jumping right on landing, that's what keeps bunny hops going
+                    player.jump_buffer = 0.0;
+                    player.velocity.y = movement.jump_speed;
+                    player.air_time = Some(std::time::Instant::now());
+                } else if grounded {
+                    player.velocity.y = 0.0;
+                    // Crouching while running slides along
+                    let sliding = player.crouching && player.velocity.length() > wish_speed;
+                    let amount = match sliding {
+                        true => movement.slide_friction,
+                        false => movement.friction,
+                    };
+                    friction(&mut player.velocity, amount, &movement, delta);
+                }
+
+                let (cap, accel) = match grounded {
+                    true => (wish_speed, movement.accelerate),
+                    false => (movement.air_speed_cap, movement.air_accelerate),
+                };
+                accelerate(
+                    &mut player.velocity,
+                    wish_dir,
+                    wish_speed,
+                    cap,
+                    accel,
+                    delta,
+                );
+                if !grounded {
+                    player.velocity.y -= movement.gravity * delta;
+                }
+            }
 
-            player.camera_movement.backdrift_goal += (player.velocity.y.abs() / 5.0).min(0.03);
+            let step = player.velocity * delta;
+            controller.translation = Some(step);
 
-            //player.velocity.x = player.velocity.x.clamp(-5.0, 5.0);
-            //player.velocity.z = player.velocity.z.clamp(-5.0, 5.0);
+            player.camera_movement.backdrift_goal += (step.y.abs() / 5.0).min(0.03);
 
-            if player.velocity != Vec3::ZERO {
-                player.camera_movement.bob_goal += time.delta_secs()
-                    * (Vec3::new(player.velocity.x, 0.0, player.velocity.z)
-                        .abs()
-                        .length()
-                        - player.velocity.y.abs())
-                    .max(0.0)
-                    * 2.0;
+            if step != Vec3::ZERO {
+                player.camera_movement.bob_goal +=
+                    delta * (step.with_y(0.0).length() - step.y.abs()).max(0.0) * 2.0;
                 if player.camera_movement.bob_goal > std::f32::consts::PI * 2.0 {
                     player.camera_movement.bob_goal -= std::f32::consts::PI * 2.0;
                 }
@@ -328,34 +475,9 @@ impl Player {
                 player.camera_movement.bob_goal = 0.0;
             }
 
-            if player.on_ground && player.jump_timer <= 0.0 {
-                player.velocity.y = 0.0;
-                player.jump_timer = 0.0;
-                if keys.jump_just_pressed {
-                    player.jump_timer = 0.1;
-                    player.velocity.y = player.jump_height;
-                    player.air_time = Some(std::time::Instant::now())
-                }
-            } else {
-                player.velocity.y += time.delta_secs() * player.jump_timer * player.gravity;
-                player.jump_timer -= time.delta_secs() * 50.0;
-                player.jump_timer = player.jump_timer.clamp(-0.1, 1.0);
-            }
-
-            controller.translation = Some(player.velocity);
-
-            let x = player.velocity.x;
-            let z = player.velocity.z;
-            player.velocity = Vec3::new(
-                x.lerp(0.0, player.hort_friction)
-                    .clamp(-player.hort_max_speed, player.hort_max_speed),
-                player.velocity.y,
-                z.lerp(0.0, player.hort_friction)
-                    .clamp(-player.hort_max_speed, player.hort_max_speed),
-            );
-
             events.send(ClientMessage::UpdatePosition {
                 position: gt.translation,
+                crouching: player.crouching,
                 rotation: gt.rotation.into(),
                 cam_rot: player
                     .children
@@ -365,7 +487,7 @@ impl Player {
                     .unwrap_or_default(),
             });
 
-            player.debug_info.current_speed = Vec2::new(x, z).distance(Vec2::ZERO);
+            player.debug_info.current_speed = player.velocity.with_y(0.0).length();
             player.debug_info.current_falling = player.velocity.y;
         }
     }
@@ -581,6 +703,13 @@ impl Player {
             if player.current_weapon == player.current_weapon_old {
                 continue;
             }
+            // Switching weapons stops reloading, the rounds that weren't in yet stay out
+            if let Some((slot, row)) = player.current_weapon_old
+                && let Some(weapon) = player.weapons[slot].get_mut(row)
+            {
+                weapon.reloading = None;
+                weapon.reload_started = None;
+            }
             player.current_weapon_old = player.current_weapon;
             if let Some((slot, row)) = player.current_weapon {
                 // TODO replace these with proper gets.
@@ -691,11 +820,12 @@ impl Player {
     }
 
     pub fn ground_detection(
-        mut query: Query<(&mut Player, &Transform), With<PlayerController>>,
+        mut query: Query<(Entity, &mut Player, &Transform), With<PlayerController>>,
         rapier_context: Query<&RapierContext>,
+        ladders: Query<(), With<Ladder>>,
     ) {
         let rapier_context = rapier_context.single();
-        for (mut player, trans) in query.iter_mut() {
+        for (entity, mut player, trans) in query.iter_mut() {
             let collider_height = 0.01;
             let shape = Collider::cylinder(collider_height, player.radius);
             let mut shape_pos = trans.translation;
@@ -703,7 +833,8 @@ impl Player {
             let shape_rot = Quat::default();
             let shape_vel = Vec3::new(0.0, -0.2, 0.0);
             let max_time_of_impact = 0.0;
-            let filter = QueryFilter::default();
+            // Ladders and pickups are no ground to stand on
+            let filter = QueryFilter::new().exclude_sensors();
             let stop_at_penetration = true;
 
             player.on_ground = rapier_context
@@ -721,6 +852,23 @@ impl Player {
                 )
                 .is_some();
 
+            let is_ladder = |collider| ladders.contains(collider);
+            let touching_ladder = rapier_context
+                .intersection_with_shape(
+                    trans.translation,
+                    Quat::IDENTITY,
+                    &Player::collider_of(player.half_height),
+                    QueryFilter::new()
+                        .exclude_collider(entity)
+                        .predicate(&is_ladder),
+                )
+                .is_some();
+            // Players who jumped off a ladder only grab one again after landing or letting go
+            if player.on_ground || !touching_ladder {
+                player.left_ladder = false;
+            }
+            player.on_ladder = touching_ladder && !player.left_ladder;
+
             if player.on_ground {
                 if let Some(air_time) = player.air_time {
                     if air_time.elapsed().as_secs_f32() > 0.01 {
@@ -760,10 +908,9 @@ impl Player {
             -x * rot.sin() + sign * z * rot.cos(),
         );
 
-        let filter = QueryFilter {
-            exclude_collider: Some(player_entity),
-            ..default()
-        };
+        let filter = QueryFilter::new()
+            .exclude_sensors()
+            .exclude_collider(player_entity);
         let res = rapier_context.cast_ray(origin, dir, 1.5, false, filter);
         if let Some((ent, distance)) = res {
             let pos = origin + dir * distance;
@@ -822,6 +969,7 @@ impl Player {
                 damage: _,
                 damage_mod: _,
                 range,
+                ..
             } => {
                 let angle_mod = angle_mod.to_radians();
 
@@ -838,10 +986,10 @@ impl Player {
                         dir + angle_offsets
                     };
 
-                    let filter = QueryFilter {
-                        exclude_collider: Some(player_entity),
-                        ..default()
-                    };
+                    // Shots go through pickups and ladders
+                    let filter = QueryFilter::new()
+                        .exclude_sensors()
+                        .exclude_collider(player_entity);
                     let res = rapier_context.cast_ray(origin, dir, *range, false, filter);
                     if let Some((ent, distance)) = res {
                         let pos = origin + dir * distance;
@@ -851,36 +999,25 @@ impl Player {
 
                 hits
             }
-            Attack::Projectile { projectile } => {
+            Attack::Projectile {
+                projectile,
+                knockback,
+                ..
+            } => {
                 if let Some(proj) = projectile_map.0.get(projectile) {
-                    // Fix mesh rotation
-                    let mut trans = Transform::from_translation(origin);
-                    trans.scale = Vec3::splat(proj.scale);
-                    trans.rotation = player_trans.rotation;
-
-                    let look_dir = origin + dir;
-
-                    trans.rotation = player_trans.rotation;
-                    trans.look_at(look_dir, Vec3::Y);
-                    trans.rotate_x(proj.rotation[0].to_radians());
-                    trans.rotate_y(proj.rotation[1].to_radians());
-                    trans.rotate_z(proj.rotation[2].to_radians());
-
-                    commands
-                        .spawn((
-                            Mesh3d(asset_server.load(&proj.model_file)),
-                            MeshMaterial3d(materials.add(StandardMaterial {
-                                base_color_texture: Some(asset_server.load(&proj.texture_file)),
-                                perceptual_roughness: 1.0,
-                                reflectance: 0.0,
-                                ..default()
-                            })),
-                            trans,
-                        ))
-                        .insert(ProjectileEntity {
-                            data: proj.clone(),
-                            dir,
-                        });
+                    let weapon = Some(self.weapons[slot][row].data.id.clone());
+                    ProjectileEntity::spawn(
+                        commands,
+                        materials,
+                        asset_server,
+                        proj,
+                        None,
+                        self.id,
+                        weapon,
+                        *knockback,
+                        origin,
+                        dir,
+                    );
                 } else {
                     error!("Unknown projectile: {projectile}")
                 }
@@ -895,11 +1032,14 @@ impl Player {
 
     fn attack1(&mut self, time: &Time, client_events: &mut EventWriter<ClientMessage>) {
         let (slot, row) = option_return!(self.current_weapon);
+        self.use_ammo(1);
+        // Haste makes weapons fire faster
+        let speed = self.speed_scale();
         let weapon = &mut self.weapons[slot][row];
         self.current_weapon_anim = FastStr::from("shoot1");
         Self::set_anim(
             weapon,
-            weapon.data.animations.fire_time1,
+            weapon.data.animations.fire_time1 / speed,
             weapon.data.animations.anim_time1,
             time,
         );
@@ -910,11 +1050,14 @@ impl Player {
 
     fn attack2(&mut self, time: &Time, client_events: &mut EventWriter<ClientMessage>) {
         let (slot, row) = option_return!(self.current_weapon);
+        self.use_ammo(2);
+        // Haste makes weapons fire faster
+        let speed = self.speed_scale();
         let weapon = &mut self.weapons[slot][row];
         self.current_weapon_anim = FastStr::from("shoot2");
         Self::set_anim(
             weapon,
-            weapon.data.animations.fire_time2,
+            weapon.data.animations.fire_time2 / speed,
             weapon.data.animations.anim_time2,
             time,
         );
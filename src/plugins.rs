@@ -1,14 +1,29 @@
 use std::path::PathBuf;
 
+use crate::chat::{self, ChatHistory, ChatState, chat_closed};
 use crate::entities::message::Message;
-use crate::entities::{ProjectileEntity, pickup::PickupEntity};
-use crate::map_gen::{load_map, texture_systems::*};
-use crate::net::{self, NetState};
+use crate::entities::{
+    ProjectileEntity,
+    pickup::{PickupEntity, PickupIndex},
+    projectiles::ProjectileIndex,
+};
+use crate::game_mode::{self, MatchState};
+use crate::kill_feed;
+use crate::map_gen::{ReloadMap, load_map, reload_map, texture_systems::*, unload_map};
+use crate::net::{
+    self, NetState,
+    client::{AutoReconnect, SpectateOnJoin},
+    config::ServerSettings,
+    demo::{DemoPlayback, RecordDemos},
+    rcon::RconPassword,
+    stats::NetStats,
+};
 use crate::player::Player;
 use crate::qwak_host_functions::qwak_functions;
+use crate::scoreboard::{self, Scoreboard};
 use crate::{mainmenu, startup};
 use bevy::prelude::*;
-use data::Projectiles;
+use data::{Movement, Projectiles};
 use qwak::*;
 use resources::{
     entropy::{entropy_game, entropy_misc},
@@ -36,8 +51,8 @@ impl Qwaks {
 
 pub struct Resources;
 impl Resources {
-    fn get_map() -> PathBuf {
-        if let Some(map) = std::env::args().nth(1) {
+    fn get_map(settings: &ServerSettings) -> PathBuf {
+        if let Some(map) = ServerSettings::positional_arg() {
             if std::fs::File::open(&map).is_ok() {
                 return map.into();
             } else {
@@ -45,26 +60,45 @@ impl Resources {
             }
         }
 
-        "assets/maps/Test.map".into()
+        settings
+            .maps
+            .first()
+            .cloned()
+            .unwrap_or_else(|| "assets/maps/Test.map".into())
     }
 }
 impl Plugin for Resources {
     fn build(&self, app: &mut App) {
+        let settings = ServerSettings::load();
         app.init_state::<CurrentStage>()
             .init_state::<NetState>()
-            .insert_resource(CurrentMap(Self::get_map()))
+            .insert_resource(CurrentMap(Self::get_map(&settings)))
+            .insert_resource(settings)
+            .insert_resource(AutoReconnect::default())
+            .insert_resource(RecordDemos::default())
+            .insert_resource(SpectateOnJoin::default())
             .insert_resource(TextureLoadingState::NotLoaded)
             .insert_resource(TexturesLoading::default())
             .insert_resource(TextureMap::default())
-            .insert_resource(PlayerSpawnpoint(Vec3::ZERO))
+            .insert_resource(PlayerSpawnpoints::default())
             .insert_resource(MapDoneLoading(false))
             .insert_resource(Paused(true))
             .insert_resource(PickupMap::new())
             .insert_resource(WeaponMap::new())
+            .insert_resource(AmmoMap::new())
+            .insert_resource(PickupIndex::default())
+            .insert_resource(ProjectileIndex::default())
             .insert_resource(PlayerInput::default())
+            .insert_resource(ChatHistory::default())
+            .insert_resource(MatchState::default())
+            .insert_resource(Scoreboard::default())
+            .insert_resource(ChatState::default())
+            .insert_resource(RconPassword::default())
+            .insert_resource(NetStats::default())
             .insert_resource(entropy_game())
             .insert_resource(entropy_misc())
             .insert_resource(Projectiles::default())
+            .insert_resource(Movement::default())
             .insert_resource(Qwaks::new(qwak_functions()));
     }
 }
@@ -89,12 +123,11 @@ impl Plugin for ClientPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(
             PreUpdate,
-            (
-                net::client::systems(),
-                net::client::errors(),
-                net::client::errors_steam(),
-            )
-                .run_if(in_state(NetState::Client)),
+            net::client::systems().run_if(in_state(NetState::Client)),
+        )
+        .add_systems(
+            PreUpdate,
+            net::demo::play_demo.run_if(resource_exists::<DemoPlayback>),
         )
         .add_systems(
             PreUpdate,
@@ -126,7 +159,13 @@ impl Plugin for MainMenuStage {
         )
         .add_systems(
             Update,
-            (mainmenu::update_level_buttons, mainmenu::update_id_buttons)
+            (
+                mainmenu::update_level_buttons,
+                mainmenu::update_id_buttons,
+                mainmenu::update_server_browser,
+                mainmenu::update_error_dialog,
+                mainmenu::update_toggles,
+            )
                 .run_if(in_state(CurrentStage::MainMenu)),
         )
         .add_systems(
@@ -137,6 +176,10 @@ impl Plugin for MainMenuStage {
             Update,
             mainmenu::update_point_light.run_if(in_state(CurrentStage::MainMenu)),
         )
+        .add_systems(
+            Update,
+            net::client::reconnect.run_if(in_state(CurrentStage::MainMenu)),
+        )
         .add_systems(OnExit(CurrentStage::MainMenu), mainmenu::clear);
     }
 }
@@ -145,6 +188,14 @@ pub struct GameStage;
 impl Plugin for GameStage {
     fn build(&self, app: &mut bevy::prelude::App) {
         app.add_systems(OnEnter(CurrentStage::InGame), register_textures)
+            .add_systems(OnExit(CurrentStage::InGame), unload_map)
+            .add_systems(
+                Update,
+                (reload_map, register_textures)
+                    .chain()
+                    .run_if(in_state(CurrentStage::InGame))
+                    .run_if(resource_exists::<ReloadMap>),
+            )
             .add_systems(
                 Update,
                 texture_waiter
@@ -155,17 +206,19 @@ impl Plugin for GameStage {
                 Update,
                 load_map
                     .run_if(in_state(CurrentStage::InGame))
-                    .run_if(if_texture_done_loading.and(run_once)),
+                    .run_if(if_texture_done_loading.and(resource_changed::<TextureLoadingState>)),
             )
             .add_systems(
                 PreUpdate,
-                PlayerInput::update.run_if(in_state(CurrentStage::InGame)),
+                PlayerInput::update
+                    .run_if(in_state(CurrentStage::InGame))
+                    .run_if(chat_closed),
             )
             .add_systems(
                 Update,
                 Player::spawn_own_player
                     .run_if(in_state(CurrentStage::InGame))
-                    .run_if(if_map_done_loading.and(run_once)),
+                    .run_if(if_map_done_loading.and(resource_changed::<MapDoneLoading>)),
             )
             .add_systems(
                 Update,
@@ -174,12 +227,28 @@ impl Plugin for GameStage {
                     PickupEntity::systems(),
                     ProjectileEntity::systems(),
                     Message::update_messages,
+                    chat::systems(),
+                    game_mode::systems(),
+                    scoreboard::update_scoreboard,
+                    kill_feed::update_kill_feed,
+                    (
+                        net::stats::toggle_net_stats,
+                        net::stats::update_net_stats,
+                        net::stats::update_net_hud,
+                    )
+                        .chain(),
                 )
                     .run_if(in_state(CurrentStage::InGame)), //.run_if(if_not_paused),
             )
             .add_systems(
                 Update,
                 (Player::pause_handler, Player::debug).run_if(in_state(CurrentStage::InGame)),
+            )
+            .add_systems(
+                Update,
+                (net::demo::demo_controls, net::demo::update_demo_hud)
+                    .run_if(in_state(CurrentStage::InGame))
+                    .run_if(resource_exists::<DemoPlayback>),
             );
     }
 }
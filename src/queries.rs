@@ -1,7 +1,10 @@
 use crate::{
+    chat::ChatHistory,
     entities::pickup::PickupEntity,
+    game_mode::MatchState,
+    kill_feed::Frag,
     map_gen::Interactable,
-    net::{CurrentClientId, Lobby},
+    net::{CurrentClientId, Lobby, config::ServerSettings},
     particles::ParticleMap,
     player::Player,
     plugins::Qwaks,
@@ -9,8 +12,8 @@ use crate::{
 use bevy::{ecs::system::SystemParam, prelude::*};
 use bevy_rapier3d::plugin::RapierContext;
 use resources::{
-    PlayerSpawnpoint, WeaponMap,
-    data::Projectiles,
+    AmmoMap, PlayerSpawnpoints, WeaponMap,
+    data::{Movement, Projectiles},
     entropy::{EGame, Entropy},
 };
 
@@ -32,14 +35,20 @@ pub struct NetWorld<'w, 's> {
     pub rapier_context: Query<'w, 's, &'static RapierContext>,
     pub asset_server: Res<'w, AssetServer>,
     pub weapon_map: Res<'w, WeaponMap>,
+    pub ammo_map: Res<'w, AmmoMap>,
+    pub movement: ResMut<'w, Movement>,
     pub materials: ResMut<'w, Assets<StandardMaterial>>,
     pub meshes: ResMut<'w, Assets<Mesh>>,
     pub game_entropy: ResMut<'w, Entropy<EGame>>,
     pub projectile_map: Res<'w, Projectiles>,
     pub time: Res<'w, Time>,
     pub current_id: Res<'w, CurrentClientId>,
-    pub player_spawn: Res<'w, PlayerSpawnpoint>,
+    pub player_spawns: Res<'w, PlayerSpawnpoints>,
     pub lobby: ResMut<'w, Lobby>,
     pub particles: Res<'w, ParticleMap>,
     pub plugins: Res<'w, Qwaks>,
+    pub chat: ResMut<'w, ChatHistory>,
+    pub match_state: ResMut<'w, MatchState>,
+    pub settings: ResMut<'w, ServerSettings>,
+    pub frags: EventWriter<'w, Frag>,
 }
@@ -0,0 +1,292 @@
+use crate::{
+    game_mode::{MatchPhase, MatchState, TEAM_COLOURS, TEAM_COUNT, TEAM_NAMES},
+    net::{
+        CurrentClientId, Lobby, PlayerInfo, ServerChannel, ServerMessage,
+        steam::{SteamClient, avatar_image},
+    },
+    player::{Player, PlayerController},
+};
+use bevy::{prelude::*, text::FontSmoothing};
+use bevy_renet::renet::RenetServer;
+use macros::{error_return, option_return};
+use resources::inputs::PlayerInput;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use steamworks::SteamId;
+
+/// How often the server sends the scoreboard, in seconds
+const PUSH_INTERVAL: f32 = 1.0;
+const FONT_SIZE: f32 = 16.0;
+const AVATAR_SIZE: f32 = 16.0;
+const NAME_WIDTH: f32 = 220.0;
+const COLUMN_WIDTH: f32 = 80.0;
+const SPECTATOR_COLOUR: Color = Color::srgb(0.6, 0.6, 0.6);
+
+/// The stats of a single player, as sent by the server
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ScoreRow {
+    pub id: u64,
+    pub score: i64,
+    pub kills: u64,
+    pub deaths: u64,
+    pub ping: u32,
+}
+
+/// The column the scoreboard is sorted by, cycled with `scoreboard_sort`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortColumn {
+    Name,
+    #[default]
+    Score,
+    Kills,
+    Deaths,
+    Efficiency,
+    Ping,
+}
+impl SortColumn {
+    const ALL: [SortColumn; 6] = [
+        SortColumn::Name,
+        SortColumn::Score,
+        SortColumn::Kills,
+        SortColumn::Deaths,
+        SortColumn::Efficiency,
+        SortColumn::Ping,
+    ];
+
+    fn next(self) -> Self {
+        let index = Self::ALL
+            .iter()
+            .position(|c| *c == self)
+            .unwrap_or_default();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            SortColumn::Name => "NAME",
+            SortColumn::Score => "SCORE",
+            SortColumn::Kills => "KILLS",
+            SortColumn::Deaths => "DEATHS",
+            SortColumn::Efficiency => "EFF",
+            SortColumn::Ping => "PING",
+        }
+    }
+
+    fn value(self, info: &PlayerInfo) -> String {
+        match self {
+            SortColumn::Name => info.name.to_lowercase(),
+            SortColumn::Score => info.score.to_string(),
+            SortColumn::Kills => info.kills.to_string(),
+            SortColumn::Deaths => info.deaths.to_string(),
+            SortColumn::Efficiency => format!("{:.0}%", info.efficiency() * 100.0),
+            SortColumn::Ping => info.ping.to_string(),
+        }
+    }
+
+    /// Best first, except for names which are sorted alphabetically
+    fn compare(self, a: &PlayerInfo, b: &PlayerInfo) -> std::cmp::Ordering {
+        match self {
+            SortColumn::Name => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+            SortColumn::Score => b.score.cmp(&a.score),
+            SortColumn::Kills => b.kills.cmp(&a.kills),
+            SortColumn::Deaths => a.deaths.cmp(&b.deaths),
+            SortColumn::Efficiency => b.efficiency().total_cmp(&a.efficiency()),
+            SortColumn::Ping => a.ping.cmp(&b.ping),
+        }
+    }
+}
+
+#[derive(Debug, Resource, Default)]
+pub struct Scoreboard {
+    pub sort: SortColumn,
+    /// Steam avatars of the players, loaded when they first show up on the scoreboard
+    avatars: HashMap<u64, Handle<Image>>,
+}
+
+/// Measures the pings, and sends everyone's stats to the clients
+pub fn push_scoreboard(
+    mut server: ResMut<RenetServer>,
+    mut lobby: ResMut<Lobby>,
+    current_id: Res<CurrentClientId>,
+    time: Res<Time>,
+    mut timer: Local<f32>,
+) {
+    *timer -= time.delta_secs();
+    if *timer > 0.0 {
+        return;
+    }
+    *timer = PUSH_INTERVAL;
+
+    for (id, info) in lobby.iter_mut() {
+        // renet reports the round trip time in milliseconds
+        if *id != current_id.0
+            && let Ok(network) = server.network_info(*id)
+        {
+            info.ping = network.rtt.round() as u32;
+        }
+    }
+    let rows = lobby
+        .iter()
+        .map(|(id, info)| ScoreRow {
+            id: *id,
+            score: info.score,
+            kills: info.kills,
+            deaths: info.deaths,
+            ping: info.ping,
+        })
+        .collect();
+    server.broadcast_message(
+        ServerChannel::ServerMessages as u8,
+        error_return!(ServerMessage::Scoreboard(rows).bytes()),
+    );
+}
+
+fn spawn_cell(
+    c: &mut ChildBuilder,
+    text: impl Into<String>,
+    width: f32,
+    font: &Handle<Font>,
+    colour: Color,
+) {
+    c.spawn((
+        Node {
+            width: Val::Px(width),
+            ..default()
+        },
+        Text::new(text),
+        TextFont {
+            font: font.clone(),
+            font_size: FONT_SIZE,
+            font_smoothing: FontSmoothing::None,
+        },
+        TextColor(colour),
+    ));
+}
+
+fn spawn_row(
+    c: &mut ChildBuilder,
+    avatar: Option<Handle<Image>>,
+    cells: [String; 6],
+    font: &Handle<Font>,
+    colour: Color,
+) {
+    c.spawn(Node {
+        column_gap: Val::Px(8.0),
+        align_items: AlignItems::Center,
+        ..default()
+    })
+    .with_children(|c| {
+        let size = Node {
+            width: Val::Px(AVATAR_SIZE),
+            height: Val::Px(AVATAR_SIZE),
+            ..default()
+        };
+        match avatar {
+            Some(image) => c.spawn((size, ImageNode::new(image))),
+            None => c.spawn(size),
+        };
+        for (i, cell) in cells.into_iter().enumerate() {
+            let width = if i == 0 { NAME_WIDTH } else { COLUMN_WIDTH };
+            spawn_cell(c, cell, width, font, colour);
+        }
+    });
+}
+
+/// Shows the scoreboard while `show_lobby` is held and during the intermission.
+/// Players are grouped by team in the team modes, spectators come last.
+#[allow(clippy::too_many_arguments)]
+pub fn update_scoreboard(
+    mut commands: Commands,
+    input: Res<PlayerInput>,
+    lobby: Res<Lobby>,
+    state: Res<MatchState>,
+    mut scoreboard: ResMut<Scoreboard>,
+    steam: Option<Res<SteamClient>>,
+    mut images: ResMut<Assets<Image>>,
+    asset_server: Res<AssetServer>,
+    q_players: Query<&Player, With<PlayerController>>,
+    mut visibility: Query<&mut Visibility>,
+    mut was_shown: Local<bool>,
+) {
+    let player = option_return!(q_players.iter().next());
+    let hud = option_return!(player.children.lobby_hud);
+    let mut vis = error_return!(visibility.get_mut(hud));
+
+    let shown = input.show_lobby_pressed || state.phase == MatchPhase::Intermission;
+    let opened = shown && !*was_shown;
+    *was_shown = shown;
+    if !shown {
+        vis.set_if_neq(Visibility::Hidden);
+        return;
+    }
+    vis.set_if_neq(Visibility::Visible);
+
+    let resorted = input.scoreboard_sort_just_pressed;
+    if resorted {
+        scoreboard.sort = scoreboard.sort.next();
+    }
+    // Team scores only move along with the stats in the lobby
+    if !opened && !resorted && !lobby.is_changed() {
+        return;
+    }
+
+    let mut groups = Vec::new();
+    if state.mode.is_team_based() {
+        for team in 0..TEAM_COUNT {
+            let title = format!("{} {}", TEAM_NAMES[team], state.team_scores[team]);
+            let players = lobby
+                .iter()
+                .filter(|(_, i)| !i.spectating && i.team == Some(team as u8))
+                .collect::<Vec<_>>();
+            groups.push((Some(title), TEAM_COLOURS[team], players));
+        }
+    } else {
+        let players = lobby.iter().filter(|(_, i)| !i.spectating).collect();
+        groups.push((None, Color::WHITE, players));
+    }
+    let spectators = lobby
+        .iter()
+        .filter(|(_, i)| i.spectating)
+        .collect::<Vec<_>>();
+    if !spectators.is_empty() {
+        groups.push((Some("SPECTATORS".to_string()), SPECTATOR_COLOUR, spectators));
+    }
+
+    // Avatars only exist for Steam players, and Steam may not have them downloaded yet
+    if let Some(steam) = steam {
+        for id in lobby.keys() {
+            if scoreboard.avatars.contains_key(id) {
+                continue;
+            }
+            if let Some(avatar) = avatar_image(&steam, SteamId::from_raw(*id), &mut images) {
+                scoreboard.avatars.insert(*id, avatar);
+            }
+        }
+    }
+
+    let sort = scoreboard.sort;
+    let font = asset_server.load("ui/Color Basic.otf");
+    commands.entity(hud).despawn_descendants();
+    commands.entity(hud).with_children(|c| {
+        let header = SortColumn::ALL.map(|column| {
+            if column == sort {
+                format!("{}*", column.title())
+            } else {
+                column.title().to_string()
+            }
+        });
+        spawn_row(c, None, header, &font, Color::WHITE);
+
+        for (title, colour, mut players) in groups {
+            if let Some(title) = title {
+                spawn_cell(c, title, NAME_WIDTH, &font, colour);
+            }
+            players.sort_by(|(_, a), (_, b)| sort.compare(a, b));
+            for (id, info) in players {
+                let avatar = scoreboard.avatars.get(id).cloned();
+                let cells = SortColumn::ALL.map(|column| column.value(info));
+                spawn_row(c, avatar, cells, &font, colour);
+            }
+        }
+    });
+}